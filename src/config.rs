@@ -1,12 +1,23 @@
-use std::{collections::HashMap, error::Error};
+use std::{collections::HashMap, error::Error, fmt};
 
 use highfleet::v1_163::EscadraString;
 use serde::{de::Deserializer, Deserialize, Serialize};
+use serde_json::{Map, Value};
 
 use crate::{plane, structs::loadout::Loadout};
 
+/// Location of the mod's config file, relative to the game directory.
+pub const CONFIG_PATH: &str = "Modloader/config/qol.json";
+
+/// Version of the config file layout written by this release.
+///
+/// Files without a `config_version` predate versioning and count as version 0.
+pub const CONFIG_VERSION: u32 = 1;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
+    #[serde(default)]
+    pub config_version: u32,
     pub enable_anti_wobble: bool,
     pub enable_unblocked_guns: bool,
     pub enable_reduced_shake: bool,
@@ -26,7 +37,7 @@ pub struct Config {
     pub sell_multiplier: f32,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ShopPart {
     pub probability: f32,
     pub min_parts: u32,
@@ -63,6 +74,18 @@ fn default_sell_multiplier() -> f32 {
     1.0
 }
 
+fn default_min_zoom_level() -> u8 {
+    3
+}
+
+fn default_max_zoom_level() -> u8 {
+    5
+}
+
+fn default_zoom_levels() -> Vec<f32> {
+    vec![14.0, 7.0, 1.0, 0.7, 0.5, 0.3]
+}
+
 /// Custom deserializer for `shop_parts` that accepts each value as either a
 /// single `ShopPart` object or an array of `ShopPart` objects, allowing both
 /// formats to coexist in the same config file.
@@ -77,32 +100,78 @@ where
 }
 
 impl Default for Config {
+    /// The default settings with the planes currently loaded by the game.
     fn default() -> Self {
-        let plane_config = plane::get_planes();
+        Self {
+            planes: plane::get_planes(),
+            ..Self::without_planes()
+        }
+    }
+}
 
+impl Config {
+    /// The default settings without any planes, which keeps the game's own.
+    ///
+    /// Unlike [`Config::default`], this does not read the game's memory.
+    fn without_planes() -> Self {
         Self {
+            config_version: CONFIG_VERSION,
             enable_anti_wobble: false,
             enable_unblocked_guns: false,
             enable_reduced_shake: false,
             enable_flare_crash_fix: true,
             enable_unblocked_ttl: false,
             enable_arcade_zoom: true,
-            max_zoom_level: 5,
-            min_zoom_level: 3,
-            zoom_levels: vec![14.0, 7.0, 1.0, 0.7, 0.5, 0.3],
-            planes: plane_config,
+            max_zoom_level: default_max_zoom_level(),
+            min_zoom_level: default_min_zoom_level(),
+            zoom_levels: default_zoom_levels(),
+            planes: HashMap::new(),
             enable_shop_parts: false,
             shop_parts: HashMap::new(),
-            sell_multiplier: 1.0,
+            sell_multiplier: default_sell_multiplier(),
         }
     }
-}
 
-impl Config {
-    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+    /// Loads the config at `path`, upgrading the file first if settings are missing
+    /// or it was written by an older release.
+    ///
+    /// Missing settings are filled in from their defaults and unknown settings are
+    /// kept. The defaults do not depend on the running game, so missing planes stay
+    /// empty. The original file is backed up before the upgraded one is written.
+    ///
+    /// Each top-level setting is read on its own, so a broken setting only falls
+    /// back to its default. The file is left unchanged while any setting is broken.
+    pub fn load_upgraded(path: &str) -> Result<Self, Box<dyn Error>> {
         let config_str = std::fs::read_to_string(path)?;
+        let defaults = serde_json::to_value(Config::without_planes())?;
+        let (mut config, errors) = read_sections(&config_str, &defaults)?;
+        let version = file_version(&config);
+
+        for error in &errors {
+            log::error!("{path}: {error}, using its default");
+        }
+        // Writing the defaults of broken settings back would overwrite the user's values.
+        if errors.is_empty() && upgrade(&mut config, &defaults)? {
+            let backup = format!("{path}.v{version}.bak");
+            match std::fs::copy(path, &backup) {
+                Ok(_) => match std::fs::write(path, serde_json::to_string_pretty(&config)?) {
+                    Ok(()) => log::info!(
+                        "Upgraded {path} to config version {CONFIG_VERSION}, the original was saved to {backup}"
+                    ),
+                    Err(error) => log::warn!("Unable to write the upgraded {path}: {error}"),
+                },
+                Err(error) => {
+                    log::warn!("Unable to back up {path} to {backup}, leaving it unchanged: {error}")
+                }
+            }
+        }
 
-        Ok(serde_json::from_str(&config_str)?)
+        if let (Some(config), Some(defaults)) = (config.as_object_mut(), defaults.as_object()) {
+            for (key, value) in defaults {
+                config.entry(key.clone()).or_insert_with(|| value.clone());
+            }
+        }
+        Ok(serde_json::from_value(config)?)
     }
 
     pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
@@ -114,3 +183,733 @@ impl Config {
         Ok(())
     }
 }
+
+fn file_version(config: &Value) -> u64 {
+    config
+        .get("config_version")
+        .and_then(Value::as_u64)
+        .unwrap_or(0)
+}
+
+/// Adds the settings of `defaults` that are missing from `config` and records the
+/// current [`CONFIG_VERSION`].
+///
+/// Existing and unknown settings are left untouched, as are files written by a
+/// newer release. Returns whether `config` changed.
+fn upgrade(config: &mut Value, defaults: &Value) -> Result<bool, Box<dyn Error>> {
+    let version = file_version(config);
+    let (Some(config), Some(defaults)) = (config.as_object_mut(), defaults.as_object()) else {
+        return Err("the config must be a JSON object".into());
+    };
+    if version > u64::from(CONFIG_VERSION) {
+        log::warn!(
+            "The config was written by a newer release (version {version}), not upgrading it"
+        );
+        return Ok(false);
+    }
+
+    let mut changed = false;
+    for (key, value) in defaults {
+        if !config.contains_key(key) {
+            log::info!("Adding the new setting `{key}` to the config");
+            config.insert(key.clone(), value.clone());
+            changed = true;
+        }
+    }
+    if version < u64::from(CONFIG_VERSION) {
+        config.insert("config_version".to_string(), CONFIG_VERSION.into());
+        changed = true;
+    }
+
+    Ok(changed)
+}
+
+/// A top-level setting that could not be read.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SectionError {
+    pub key: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for SectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "`{}` at line {}, column {}: {}",
+            self.key, self.line, self.column, self.message
+        )
+    }
+}
+
+impl SectionError {
+    fn new(key: &str, error: &serde_json::Error) -> Self {
+        let message = error.to_string();
+        let location = format!(" at line {} column {}", error.line(), error.column());
+
+        Self {
+            key: key.to_string(),
+            line: error.line(),
+            column: error.column(),
+            message: message
+                .strip_suffix(&location)
+                .unwrap_or(&message)
+                .to_string(),
+        }
+    }
+}
+
+/// The raw text of a top-level setting.
+struct Section<'a> {
+    key: String,
+    /// Offset of the opening quote of the key.
+    key_start: usize,
+    value: &'a str,
+    /// Offset of the `,` or `}` that follows the value.
+    value_end: usize,
+}
+
+/// Reads every top-level setting of `text` on its own, checking it against the
+/// [`Config`] field of the same name.
+///
+/// Returns the settings that could be read and an error for each one that could
+/// not. Fails only if the top-level object itself is malformed.
+fn read_sections(
+    text: &str,
+    defaults: &Value,
+) -> Result<(Value, Vec<SectionError>), Box<dyn Error>> {
+    let Some(defaults) = defaults.as_object() else {
+        return Err("the default config must be a JSON object".into());
+    };
+
+    let mut config = Map::new();
+    let mut errors = Vec::new();
+    for section in split_sections(text)? {
+        match check_section(text, &section, defaults)
+            .and_then(|()| serde_json::from_str(section.value))
+        {
+            Ok(value) => {
+                config.insert(section.key, value);
+            }
+            Err(error) => errors.push(SectionError::new(&section.key, &error)),
+        }
+    }
+
+    Ok((Value::Object(config), errors))
+}
+
+/// Deserializes a [`Config`] that takes `section` from the file and every other
+/// setting from `defaults`.
+///
+/// The text in front of the section is blanked out instead of removed, so errors
+/// carry the line and column of the file.
+fn check_section(
+    text: &str,
+    section: &Section,
+    defaults: &Map<String, Value>,
+) -> Result<(), serde_json::Error> {
+    let mut others = defaults.clone();
+    others.remove(&section.key);
+
+    // Only the top-level `{` is kept, at the offset it has in the file
+    let open = object_start(text.as_bytes());
+    let mut composed = text.as_bytes()[..section.key_start]
+        .iter()
+        .enumerate()
+        .map(|(offset, &byte)| match byte {
+            b'{' if offset == open => '{',
+            b'\n' => '\n',
+            _ => ' ',
+        })
+        .collect::<String>();
+    composed.push_str(&text[section.key_start..section.value_end]);
+    if !others.is_empty() {
+        let others = Value::Object(others).to_string();
+        composed.push(',');
+        composed.push_str(&others[1..others.len() - 1]);
+    }
+    composed.push('}');
+
+    serde_json::from_str::<Config>(&composed).map(drop)
+}
+
+/// Splits the top-level object of `text` into its settings without parsing their values.
+fn split_sections(text: &str) -> Result<Vec<Section<'_>>, Box<dyn Error>> {
+    let bytes = text.as_bytes();
+    let mut position = object_start(bytes);
+    if bytes.get(position) != Some(&b'{') {
+        return Err(syntax_error(text, position, "expected `{`"));
+    }
+    position += 1;
+
+    let mut sections = Vec::new();
+    loop {
+        position = skip_whitespace(bytes, position);
+        match bytes.get(position) {
+            Some(b'}') => break,
+            Some(b'"') => {}
+            _ => return Err(syntax_error(text, position, "expected a setting name")),
+        }
+
+        let key_start = position;
+        position = string_end(bytes, key_start)
+            .ok_or_else(|| syntax_error(text, key_start, "unterminated setting name"))?;
+        let key: String = serde_json::from_str(&text[key_start..position])
+            .map_err(|error| syntax_error(text, key_start, &error.to_string()))?;
+
+        position = skip_whitespace(bytes, position);
+        if bytes.get(position) != Some(&b':') {
+            return Err(syntax_error(text, position, "expected `:`"));
+        }
+        let value_start = position + 1;
+        position = value_end(bytes, value_start)
+            .ok_or_else(|| syntax_error(text, text.len(), "unterminated object, expected `}`"))?;
+
+        sections.push(Section {
+            key,
+            key_start,
+            value: text[value_start..position].trim(),
+            value_end: position,
+        });
+        if bytes[position] == b'}' {
+            break;
+        }
+        position += 1;
+    }
+
+    position = skip_whitespace(bytes, position + 1);
+    if position < bytes.len() {
+        return Err(syntax_error(text, position, "trailing characters"));
+    }
+
+    Ok(sections)
+}
+
+/// The UTF-8 byte order mark some editors put in front of the file.
+const BYTE_ORDER_MARK: &[u8] = b"\xEF\xBB\xBF";
+
+/// Returns the offset of the first byte after a leading byte order mark and
+/// whitespace, where the top-level object should start.
+fn object_start(bytes: &[u8]) -> usize {
+    let start = if bytes.starts_with(BYTE_ORDER_MARK) {
+        BYTE_ORDER_MARK.len()
+    } else {
+        0
+    };
+    skip_whitespace(bytes, start)
+}
+
+fn skip_whitespace(bytes: &[u8], mut position: usize) -> usize {
+    while bytes.get(position).is_some_and(u8::is_ascii_whitespace) {
+        position += 1;
+    }
+    position
+}
+
+/// Returns the offset just past the string starting at `start`.
+fn string_end(bytes: &[u8], start: usize) -> Option<usize> {
+    let mut position = start + 1;
+    while let Some(&byte) = bytes.get(position) {
+        match byte {
+            b'\\' => position += 2,
+            b'"' => return Some(position + 1),
+            _ => position += 1,
+        }
+    }
+    None
+}
+
+/// Returns the offset of the `,` or `}` ending the value that starts at `position`.
+fn value_end(bytes: &[u8], mut position: usize) -> Option<usize> {
+    let mut depth = 0usize;
+    while let Some(&byte) = bytes.get(position) {
+        match byte {
+            b'"' => {
+                position = string_end(bytes, position)?;
+                continue;
+            }
+            b'{' | b'[' => depth += 1,
+            b'}' | b']' if depth > 0 => depth -= 1,
+            b',' | b'}' if depth == 0 => return Some(position),
+            _ => {}
+        }
+        position += 1;
+    }
+    None
+}
+
+fn syntax_error(text: &str, offset: usize, message: &str) -> Box<dyn Error> {
+    let before = &text[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = offset - before.rfind('\n').map_or(0, |newline| newline + 1) + 1;
+
+    format!("line {line}, column {column}: {message}").into()
+}
+
+/// A group of settings that falls back to its defaults as a whole when invalid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SettingsGroup {
+    ArcadeZoom,
+    SellMultiplier,
+    ShopParts,
+}
+
+impl fmt::Display for SettingsGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ArcadeZoom => write!(f, "arcade zoom"),
+            Self::SellMultiplier => write!(f, "sell multiplier"),
+            Self::ShopParts => write!(f, "shop parts"),
+        }
+    }
+}
+
+/// A problem found by [`Config::validate`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Issue {
+    /// The settings group containing the problem.
+    pub group: SettingsGroup,
+    /// JSON path of the offending value, e.g. `$.zoom_levels[2]`.
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+/// The result of [`Config::validate`].
+#[derive(Debug, Default)]
+pub struct Validation {
+    /// Problems that make a settings group unusable with the configured settings.
+    pub errors: Vec<Issue>,
+    /// Suspicious settings that are used anyway.
+    pub warnings: Vec<Issue>,
+}
+
+impl Validation {
+    fn error(&mut self, group: SettingsGroup, path: String, message: impl Into<String>) {
+        self.errors.push(Issue {
+            group,
+            path,
+            message: message.into(),
+        });
+    }
+
+    fn warning(&mut self, group: SettingsGroup, path: String, message: impl Into<String>) {
+        self.warnings.push(Issue {
+            group,
+            path,
+            message: message.into(),
+        });
+    }
+
+    /// The settings groups with at least one error, in a stable order.
+    pub fn failed_groups(&self) -> Vec<SettingsGroup> {
+        let mut groups = self
+            .errors
+            .iter()
+            .map(|issue| issue.group)
+            .collect::<Vec<_>>();
+        groups.sort();
+        groups.dedup();
+        groups
+    }
+}
+
+impl Config {
+    /// Checks the settings for values the game cannot use.
+    pub fn validate(&self) -> Validation {
+        let mut validation = Validation::default();
+        self.validate_zoom(&mut validation);
+        self.validate_shop_parts(&mut validation);
+
+        if !(self.sell_multiplier.is_finite() && self.sell_multiplier >= 0.0) {
+            validation.error(
+                SettingsGroup::SellMultiplier,
+                "$.sell_multiplier".to_string(),
+                format!(
+                    "must be a non-negative number, found {}",
+                    self.sell_multiplier
+                ),
+            );
+        }
+
+        validation
+    }
+
+    fn validate_zoom(&self, validation: &mut Validation) {
+        let group = SettingsGroup::ArcadeZoom;
+        if self.min_zoom_level > self.max_zoom_level {
+            validation.error(
+                group,
+                "$.min_zoom_level".to_string(),
+                format!(
+                    "must not exceed max_zoom_level ({}), found {}",
+                    self.max_zoom_level, self.min_zoom_level
+                ),
+            );
+        }
+
+        if self.zoom_levels.is_empty() {
+            validation.error(
+                group,
+                "$.zoom_levels".to_string(),
+                "at least one zoom level is required",
+            );
+            return;
+        }
+        for (index, zoom) in self.zoom_levels.iter().enumerate() {
+            if !(zoom.is_finite() && *zoom > 0.0) {
+                validation.error(
+                    group,
+                    format!("$.zoom_levels[{index}]"),
+                    format!("must be a positive number, found {zoom}"),
+                );
+            }
+        }
+
+        if self.zoom_levels.len() < 5 {
+            validation.warning(
+                group,
+                "$.zoom_levels".to_string(),
+                "the game by default specifies 5 zoom levels; with fewer, the game may be unstable",
+            );
+        }
+        if self.max_zoom_level as usize >= self.zoom_levels.len() {
+            validation.warning(
+                group,
+                "$.max_zoom_level".to_string(),
+                format!(
+                    "zoom level {} has no zoom value, only {} are defined",
+                    self.max_zoom_level,
+                    self.zoom_levels.len()
+                ),
+            );
+        }
+    }
+
+    fn validate_shop_parts(&self, validation: &mut Validation) {
+        let group = SettingsGroup::ShopParts;
+        let mut names = self.shop_parts.keys().collect::<Vec<_>>();
+        names.sort();
+
+        for name in names {
+            for (index, part) in self.shop_parts[name].iter().enumerate() {
+                let path = format!("$.shop_parts.{name}[{index}]");
+                if !(0.0..=1.0).contains(&part.probability) {
+                    validation.error(
+                        group,
+                        format!("{path}.probability"),
+                        format!("must be between 0 and 1, found {}", part.probability),
+                    );
+                }
+                if part.min_parts > part.max_parts {
+                    validation.error(
+                        group,
+                        format!("{path}.min_parts"),
+                        format!(
+                            "must not exceed max_parts ({}), found {}",
+                            part.max_parts, part.min_parts
+                        ),
+                    );
+                }
+                for (city_index, city_type) in part.city_types.iter().enumerate() {
+                    if !(1..=7).contains(city_type) {
+                        validation.error(
+                            group,
+                            format!("{path}.city_types[{city_index}]"),
+                            format!("must be a city type between 1 and 7, found {city_type}"),
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    /// Restores the default settings of `group`, leaving its enable flag untouched.
+    pub fn reset(&mut self, group: SettingsGroup) {
+        match group {
+            SettingsGroup::ArcadeZoom => {
+                self.min_zoom_level = default_min_zoom_level();
+                self.max_zoom_level = default_max_zoom_level();
+                self.zoom_levels = default_zoom_levels();
+            }
+            SettingsGroup::SellMultiplier => self.sell_multiplier = default_sell_multiplier(),
+            SettingsGroup::ShopParts => self.shop_parts = HashMap::new(),
+        }
+    }
+
+    /// Validates the settings, logging every problem, and resets each settings
+    /// group with errors to its defaults.
+    pub fn repair(&mut self) {
+        let validation = self.validate();
+        for warning in &validation.warnings {
+            log::warn!("Config: {warning}");
+        }
+        for error in &validation.errors {
+            log::error!("Config: {error}");
+        }
+
+        for group in validation.failed_groups() {
+            log::error!("Using the default {group} settings because of the errors above");
+            self.reset(group);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        Config {
+            config_version: CONFIG_VERSION,
+            enable_anti_wobble: false,
+            enable_unblocked_guns: false,
+            enable_reduced_shake: false,
+            enable_flare_crash_fix: true,
+            enable_unblocked_ttl: false,
+            enable_arcade_zoom: true,
+            max_zoom_level: default_max_zoom_level(),
+            min_zoom_level: default_min_zoom_level(),
+            zoom_levels: default_zoom_levels(),
+            planes: HashMap::new(),
+            enable_shop_parts: true,
+            shop_parts: HashMap::from([(
+                "MDL_GUN_01".to_string(),
+                vec![ShopPart {
+                    probability: 0.5,
+                    min_parts: 1,
+                    max_parts: 2,
+                    city_types: vec![1, 7],
+                }],
+            )]),
+            sell_multiplier: 1.5,
+        }
+    }
+
+    fn paths(issues: &[Issue]) -> Vec<&str> {
+        issues.iter().map(|issue| issue.path.as_str()).collect()
+    }
+
+    #[test]
+    fn default_settings_are_valid() {
+        let validation = config().validate();
+
+        assert!(validation.errors.is_empty(), "{:?}", validation.errors);
+        assert!(validation.warnings.is_empty(), "{:?}", validation.warnings);
+    }
+
+    #[test]
+    fn defaults_do_not_need_the_game() {
+        let defaults = Config::without_planes();
+
+        assert!(defaults.planes.is_empty());
+        assert!(defaults.validate().errors.is_empty());
+    }
+
+    #[test]
+    fn problems_are_reported_with_their_path() {
+        let mut config = config();
+        config.min_zoom_level = 6;
+        config.zoom_levels = vec![14.0, 0.0, f32::NAN];
+        config.sell_multiplier = -1.0;
+        config.shop_parts.insert(
+            "MDL_GUN_02".to_string(),
+            vec![
+                ShopPart {
+                    probability: 50.0,
+                    min_parts: 3,
+                    max_parts: 2,
+                    city_types: vec![0, 3, 8],
+                },
+                ShopPart {
+                    probability: 1.0,
+                    min_parts: 1,
+                    max_parts: 1,
+                    city_types: Vec::new(),
+                },
+            ],
+        );
+
+        let validation = config.validate();
+
+        assert_eq!(
+            paths(&validation.errors),
+            [
+                "$.min_zoom_level",
+                "$.zoom_levels[1]",
+                "$.zoom_levels[2]",
+                "$.shop_parts.MDL_GUN_02[0].probability",
+                "$.shop_parts.MDL_GUN_02[0].min_parts",
+                "$.shop_parts.MDL_GUN_02[0].city_types[0]",
+                "$.shop_parts.MDL_GUN_02[0].city_types[2]",
+                "$.sell_multiplier",
+            ]
+        );
+        assert_eq!(
+            paths(&validation.warnings),
+            ["$.zoom_levels", "$.max_zoom_level"]
+        );
+        assert_eq!(
+            validation.failed_groups(),
+            [
+                SettingsGroup::ArcadeZoom,
+                SettingsGroup::SellMultiplier,
+                SettingsGroup::ShopParts
+            ]
+        );
+    }
+
+    #[test]
+    fn only_groups_with_errors_are_reset() {
+        let mut config = config();
+        config.zoom_levels = vec![14.0, -7.0, 1.0, 0.7, 0.5, 0.3];
+        config.max_zoom_level = 2;
+
+        config.repair();
+
+        assert_eq!(config.zoom_levels, default_zoom_levels());
+        assert_eq!(config.max_zoom_level, default_max_zoom_level());
+        assert_eq!(config.sell_multiplier, 1.5);
+        assert_eq!(config.shop_parts.len(), 1);
+        assert!(config.validate().errors.is_empty());
+    }
+
+    #[test]
+    fn upgrade_fills_in_missing_settings_and_keeps_the_rest() {
+        let defaults = serde_json::to_value(config()).unwrap();
+        let mut file = serde_json::json!({
+            "enable_anti_wobble": true,
+            "zoom_levels": [10.0, 5.0],
+            "my_note": "keep me",
+        });
+
+        assert!(upgrade(&mut file, &defaults).unwrap());
+
+        let file = file.as_object().unwrap();
+        assert_eq!(file["enable_anti_wobble"], true);
+        assert_eq!(file["zoom_levels"], serde_json::json!([10.0, 5.0]));
+        assert_eq!(file["my_note"], "keep me");
+        assert_eq!(file["sell_multiplier"], 1.5);
+        assert_eq!(file["config_version"], CONFIG_VERSION);
+        assert_eq!(
+            file.keys().take(3).collect::<Vec<_>>(),
+            ["enable_anti_wobble", "zoom_levels", "my_note"]
+        );
+        assert_eq!(file.len(), defaults.as_object().unwrap().len() + 1);
+    }
+
+    #[test]
+    fn current_and_newer_files_are_left_alone() {
+        let defaults = serde_json::to_value(config()).unwrap();
+
+        let mut current = defaults.clone();
+        assert!(!upgrade(&mut current, &defaults).unwrap());
+        assert_eq!(current, defaults);
+
+        let mut newer = serde_json::json!({ "config_version": CONFIG_VERSION + 1 });
+        assert!(!upgrade(&mut newer, &defaults).unwrap());
+        assert_eq!(newer.as_object().unwrap().len(), 1);
+
+        assert!(upgrade(&mut serde_json::json!([1, 2]), &defaults).is_err());
+    }
+
+    #[test]
+    fn upgraded_files_load_the_user_settings() {
+        let defaults = serde_json::to_value(config()).unwrap();
+        let mut file = serde_json::json!({
+            "enable_shop_parts": true,
+            "shop_parts": {
+                "MDL_GUN_03": { "probability": 0.25, "min_parts": 1, "max_parts": 1 }
+            },
+        });
+
+        upgrade(&mut file, &defaults).unwrap();
+        let loaded: Config = serde_json::from_value(file).unwrap();
+
+        assert_eq!(loaded.config_version, CONFIG_VERSION);
+        assert!(loaded.enable_shop_parts);
+        assert_eq!(loaded.shop_parts["MDL_GUN_03"][0].probability, 0.25);
+        assert_eq!(loaded.zoom_levels, default_zoom_levels());
+    }
+
+    #[test]
+    fn broken_sections_are_reported_and_skipped() {
+        let defaults = serde_json::to_value(config()).unwrap();
+        let text = r#"{
+  "enable_anti_wobble": true,
+  "max_zoom_level": "5",
+  "zoom_levels": [10.0, 5.0,, 1.0],
+  "shop_parts": { "MDL_GUN_03": { "probability": 0.25, "min_parts": 1 } },
+  "my_note": "keep, {me}"
+}"#;
+
+        let (file, errors) = read_sections(text, &defaults).unwrap();
+
+        let file = file.as_object().unwrap();
+        assert_eq!(
+            file.keys().collect::<Vec<_>>(),
+            ["enable_anti_wobble", "my_note"]
+        );
+        assert_eq!(file["my_note"], "keep, {me}");
+        assert_eq!(
+            errors
+                .iter()
+                .map(|error| (error.key.as_str(), error.line, error.column))
+                .collect::<Vec<_>>(),
+            [
+                ("max_zoom_level", 3, 23),
+                ("zoom_levels", 4, 29),
+                ("shop_parts", 5, 73),
+            ]
+        );
+        assert_eq!(
+            errors[0].to_string(),
+            "`max_zoom_level` at line 3, column 23: invalid type: string \"5\", expected u8"
+        );
+    }
+
+    #[test]
+    fn leading_whitespace_keeps_section_locations() {
+        let defaults = serde_json::to_value(config()).unwrap();
+        for prefix in ["\n\n", "\u{feff}", "\u{feff}\n  "] {
+            let text =
+                format!("{prefix}{{\n  \"max_zoom_level\": \"5\",\n  \"min_zoom_level\": 1\n}}");
+            let line = prefix.matches('\n').count() + 2;
+
+            let (file, errors) = read_sections(&text, &defaults).unwrap();
+
+            assert_eq!(file["min_zoom_level"], 1, "{prefix:?}");
+            assert_eq!(
+                errors
+                    .iter()
+                    .map(|error| (error.key.as_str(), error.line, error.column))
+                    .collect::<Vec<_>>(),
+                [("max_zoom_level", line, 23)],
+                "{prefix:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_files_are_rejected_with_their_location() {
+        let defaults = serde_json::to_value(config()).unwrap();
+        let cases = [
+            ("[1, 2]", "line 1, column 1"),
+            ("{\n  enable_anti_wobble: true\n}", "line 2, column 3"),
+            ("{\n  \"zoom_levels\": [1.0\n", "line 3, column 1"),
+            ("{ \"max_zoom_level\" 5 }", "line 1, column 20"),
+            ("{}\n}", "line 2, column 1"),
+        ];
+
+        for (text, location) in cases {
+            let error = read_sections(text, &defaults).unwrap_err().to_string();
+            assert!(error.starts_with(location), "{text:?}: {error}");
+        }
+    }
+}
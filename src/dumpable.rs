@@ -1,42 +1,50 @@
 use std::arch::asm;
 
-use crate::patchy::{Patch, ReturnType};
-
-pub unsafe fn dumpable() {
-    let address;
-    if cfg!(feature = "1_151") {
-        address = 0x1400240c0;
-    } else if cfg!(feature = "1_163") {
-        address = 0x1400256e0;
-    } else {
-        // Default to 1.163
-        address = 0x1400256e0;
+use crate::{
+    config::Config,
+    feature::Feature,
+    patchy::{Patch, PatchError, ReturnType},
+    version::addresses,
+};
+
+/// Offset of the dumpable flag, read by `set_dumpable` before any callback fires.
+static mut DUMPABLE_FLAG_OFFSET: usize = 0;
+
+/// Stops custom GUI elements in the battle screen from shaking.
+pub struct AntiWobble;
+
+impl Feature for AntiWobble {
+    type Settings = ();
+
+    const NAME: &'static str = "Anti-wobble";
+
+    fn settings(config: &Config) -> Option<()> {
+        config.enable_anti_wobble.then_some(())
     }
 
-    let p = Patch::patch_call(
-        address,
-        set_dumpable as *const (),
-        6,
-        true,
-        ReturnType::None,
-    );
-    std::mem::forget(p);
-}
+    unsafe fn install((): ()) -> Result<Vec<Patch>, PatchError> {
+        let addresses = addresses();
+        DUMPABLE_FLAG_OFFSET = addresses.dumpable_flag_offset;
 
-#[no_mangle]
-#[cfg(feature = "1_151")]
-unsafe extern "C" fn set_dumpable() {
-    asm! {
-        "mov byte ptr [rsi + 0x8e6], 0",
-        out("rsi") _,
+        let p = Patch::patch_call(
+            addresses.dumpable_hook,
+            addresses.original_bytes("dumpable.hook")?,
+            set_dumpable as *const (),
+            6,
+            true,
+            ReturnType::None,
+        )?;
+        Ok(vec![p])
     }
 }
 
 #[no_mangle]
-#[cfg(any(feature = "1_163", not(any(feature = "1_151", feature = "1_163"))))]
 unsafe extern "C" fn set_dumpable() {
     asm! {
-        "mov byte ptr [rsi + 0x91E], 0",
+        "mov rax, qword ptr [rip + {offset}]",
+        "mov byte ptr [rsi + rax], 0",
+        offset = sym DUMPABLE_FLAG_OFFSET,
+        out("rax") _,
         out("rsi") _,
     }
 }
@@ -0,0 +1,338 @@
+//! The features of the mod and the registry they are installed from.
+//!
+//! Each feature implements [`Feature`] next to the code it patches and is listed
+//! once in [`FEATURES`]. `init()` installs the registered features, and the config
+//! watcher applies their new settings when the config file changes.
+
+use std::{
+    collections::BTreeMap,
+    marker::PhantomData,
+    sync::{Mutex, PoisonError},
+};
+
+use crate::{
+    config::Config,
+    dumpable::AntiWobble,
+    flare_crash::FlareCrashFix,
+    guns::{GunBlocking, UnblockedGuns},
+    parts::CustomParts,
+    patchy::{self, FailedFeature, Patch, PatchError, PatchHandle},
+    sell_multiplier::SellMultiplier,
+    shake::ReducedShake,
+    ttl::UnblockedTtl,
+    version::{self, GameVersion},
+    zoom::ArcadeZoom,
+};
+
+/// Every feature of the mod, in the order they are installed.
+pub static FEATURES: [&dyn Registered; 9] = [
+    &Registration::<FlareCrashFix>(PhantomData),
+    &Registration::<AntiWobble>(PhantomData),
+    &Registration::<ArcadeZoom>(PhantomData),
+    &Registration::<UnblockedGuns>(PhantomData),
+    &Registration::<GunBlocking>(PhantomData),
+    &Registration::<ReducedShake>(PhantomData),
+    &Registration::<UnblockedTtl>(PhantomData),
+    &Registration::<CustomParts>(PhantomData),
+    &Registration::<SellMultiplier>(PhantomData),
+];
+
+/// The handles of the patches each feature prepared, by feature name.
+static PATCHES: Mutex<BTreeMap<&'static str, Vec<PatchHandle>>> = Mutex::new(BTreeMap::new());
+
+/// A feature that patches the game.
+pub trait Feature {
+    /// The part of the config the feature uses.
+    type Settings: PartialEq;
+
+    /// Name of the feature in the log.
+    const NAME: &'static str;
+
+    /// Game layouts the feature applies to; it is skipped on the others.
+    const VERSIONS: &'static [GameVersion] = &[GameVersion::V1_151, GameVersion::V1_163];
+
+    /// Returns the feature's settings, or `None` if it is disabled in `config`.
+    fn settings(config: &Config) -> Option<Self::Settings>;
+
+    /// Prepares the feature's patches and returns every one of them, so the
+    /// registry can turn them off and on once they are installed.
+    ///
+    /// A feature that prepares no patches, e.g. because its settings make it
+    /// unnecessary, counts as skipped.
+    ///
+    /// # Safety
+    /// Must be called before patches are installed.
+    unsafe fn install(settings: Self::Settings) -> Result<Vec<Patch>, PatchError>;
+
+    /// Turns the installed feature off by disabling `patches`, the handles of the
+    /// patches returned by [`Feature::install`].
+    ///
+    /// The patches that were already disabled are enabled again if one of them
+    /// cannot be disabled, so the feature stays whole.
+    ///
+    /// # Safety
+    /// See [`PatchHandle::disable`].
+    unsafe fn uninstall(patches: &[PatchHandle]) -> Result<(), PatchError> {
+        set_enabled(patches, false)
+    }
+
+    /// Applies changed settings to the installed feature.
+    ///
+    /// Runs on the config watcher thread while the game is running, so it may only
+    /// update state the patches read, never the patched code. Returns false if the
+    /// settings only take effect after restarting the game. The default suits
+    /// features without settings besides being enabled.
+    fn reload(_settings: Self::Settings) -> bool {
+        true
+    }
+}
+
+/// Disables or enables every patch of `patches`, undoing the changes already
+/// made if one of them fails.
+///
+/// # Safety
+/// See [`PatchHandle::disable`] and [`PatchHandle::enable`].
+unsafe fn set_enabled(patches: &[PatchHandle], enabled: bool) -> Result<(), PatchError> {
+    let toggle = |patch: PatchHandle, enabled| {
+        if enabled {
+            patch.enable()
+        } else {
+            patch.disable()
+        }
+    };
+
+    for (index, patch) in patches.iter().enumerate() {
+        if let Err(error) = toggle(*patch, enabled) {
+            for patch in &patches[..index] {
+                if let Err(undo_error) = toggle(*patch, !enabled) {
+                    log::error!("Unable to undo a change to the patch code: {undo_error}");
+                }
+            }
+            return Err(error);
+        }
+    }
+    Ok(())
+}
+
+/// A registered [`Feature`], independent of the type of its settings.
+pub trait Registered: Sync {
+    /// See [`Feature::NAME`].
+    fn name(&self) -> &'static str;
+
+    /// Whether the feature patches `version`, see [`Feature::VERSIONS`].
+    fn supports(&self, version: GameVersion) -> bool;
+
+    /// Whether the feature is enabled in `config`, see [`Feature::settings`].
+    fn is_enabled(&self, config: &Config) -> bool;
+
+    /// Prepares the feature's patches with its settings from `config`.
+    ///
+    /// # Safety
+    /// See [`Feature::install`].
+    unsafe fn install(&self, config: &Config) -> Result<(), PatchError>;
+
+    /// Brings the installed feature from the settings in `old` to those in `new`.
+    ///
+    /// Turning the feature off uninstalls it, see [`Feature::uninstall`], and
+    /// turning it back on enables its patches again. A feature that was not
+    /// installed at startup only takes effect after a restart.
+    fn reload(&self, old: &Config, new: &Config);
+}
+
+struct Registration<F>(PhantomData<fn() -> F>);
+
+impl<F: Feature> Registered for Registration<F> {
+    fn name(&self) -> &'static str {
+        F::NAME
+    }
+
+    fn supports(&self, version: GameVersion) -> bool {
+        F::VERSIONS.contains(&version)
+    }
+
+    fn is_enabled(&self, config: &Config) -> bool {
+        F::settings(config).is_some()
+    }
+
+    unsafe fn install(&self, config: &Config) -> Result<(), PatchError> {
+        let Some(settings) = F::settings(config) else {
+            return Ok(());
+        };
+
+        let handles = F::install(settings)?.iter().map(Patch::handle).collect();
+        PATCHES
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(F::NAME, handles);
+        Ok(())
+    }
+
+    fn reload(&self, old: &Config, new: &Config) {
+        let (old, new) = (F::settings(old), F::settings(new));
+        if !self.supports(version::game_version()) {
+            return;
+        }
+        let patches = PATCHES
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(F::NAME)
+            .cloned();
+        let Some(patches) = patches.filter(|_| patchy::feature_installed(F::NAME)) else {
+            if new.is_some() && old != new {
+                log::warn!(
+                    "{} was not installed at startup; restart HighFleet to apply it",
+                    F::NAME
+                );
+            }
+            return;
+        };
+
+        // The patches decide whether the feature is on, so turning it off or on
+        // after a failure is retried with the next change to the config.
+        let active = patches.iter().all(|patch| patch.is_enabled());
+        let Some(settings) = new else {
+            if active {
+                match unsafe { F::uninstall(&patches) } {
+                    Ok(()) => log::info!("{} turned off", F::NAME),
+                    Err(error) => log::error!("Unable to turn {} off: {error}", F::NAME),
+                }
+            }
+            return;
+        };
+        if active && old.as_ref() == Some(&settings) {
+            return;
+        }
+        if !active {
+            if let Err(error) = unsafe { set_enabled(&patches, true) } {
+                log::error!("Unable to turn {} back on: {error}", F::NAME);
+                return;
+            }
+            log::info!("{} turned back on", F::NAME);
+        }
+
+        if F::reload(settings) {
+            log::info!("Applied the new {} settings", F::NAME);
+        } else {
+            log::warn!(
+                "The new {} settings take effect after restarting HighFleet",
+                F::NAME
+            );
+        }
+    }
+}
+
+/// What became of each feature during `init`.
+#[derive(Default)]
+pub struct Summary {
+    active: Vec<&'static str>,
+    skipped: Vec<&'static str>,
+    failed: Vec<&'static str>,
+}
+
+impl Summary {
+    /// Prepares every registered feature that is enabled in `config` and
+    /// supports `version`.
+    ///
+    /// # Safety
+    /// Must be called before patches are installed.
+    pub unsafe fn prepare_all(config: &Config, version: GameVersion) -> Self {
+        let mut summary = Self::default();
+        for feature in FEATURES {
+            if !feature.is_enabled(config) {
+                log::info!("{} disabled", feature.name());
+                summary.skipped.push(feature.name());
+            } else if !feature.supports(version) {
+                log::info!("{} is not needed on this game version", feature.name());
+                summary.skipped.push(feature.name());
+            } else {
+                summary.prepare(feature.name(), || feature.install(config));
+            }
+        }
+        summary
+    }
+
+    /// Prepares the patches of `feature`.
+    ///
+    /// A feature that fails to prepare is recorded as failed and the patches it
+    /// had already prepared are withdrawn. One that prepares nothing is recorded
+    /// as skipped.
+    fn prepare(&mut self, feature: &'static str, prepare: impl FnOnce() -> Result<(), PatchError>) {
+        patchy::begin_feature(feature);
+        match prepare() {
+            Ok(()) if patchy::prepared_patches(feature) == 0 => self.skipped.push(feature),
+            Ok(()) => self.active.push(feature),
+            Err(error) => {
+                log::error!("{feature}: unable to prepare patches: {error}");
+                patchy::discard_feature(feature);
+                self.failed.push(feature);
+            }
+        }
+    }
+
+    /// Records a prepared feature that could not be installed.
+    pub fn install_failed(&mut self, failure: FailedFeature) {
+        log::error!("{}", failure.error);
+        if let Some(feature) = failure.feature {
+            self.active.retain(|active| *active != feature);
+            self.failed.push(feature);
+        }
+    }
+
+    /// Records that none of the prepared features could be installed.
+    pub fn all_failed(&mut self) {
+        self.failed.append(&mut self.active);
+    }
+
+    /// Logs which features are active, skipped and failed.
+    pub fn log(&self) {
+        let list = |features: &[&str]| {
+            if features.is_empty() {
+                "none".to_string()
+            } else {
+                features.join(", ")
+            }
+        };
+        log::info!("Active features: {}", list(&self.active));
+        log::info!("Skipped features: {}", list(&self.skipped));
+        if self.failed.is_empty() {
+            log::info!("Failed features: none");
+        } else {
+            log::error!("Failed features: {}", list(&self.failed));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn features_are_registered_once() {
+        for (index, feature) in FEATURES.iter().enumerate() {
+            assert!(
+                FEATURES[..index]
+                    .iter()
+                    .all(|other| other.name() != feature.name()),
+                "{} is registered twice",
+                feature.name()
+            );
+        }
+    }
+
+    #[test]
+    fn gun_features_follow_the_game_version() {
+        let supported = |name: &str, version| {
+            FEATURES
+                .iter()
+                .find(|feature| feature.name() == name)
+                .unwrap()
+                .supports(version)
+        };
+
+        assert!(supported("Unblocked guns", GameVersion::V1_151));
+        assert!(!supported("Unblocked guns", GameVersion::V1_163));
+        assert!(!supported("Gun blocking", GameVersion::V1_151));
+        assert!(supported("Gun blocking", GameVersion::V1_163));
+        assert!(!supported("Flare crash fix", GameVersion::V1_151));
+    }
+}
@@ -1,69 +1,77 @@
 //! Restores HighFleet 1.151's nullable missile-fuze link check in version 1.163.
 
-use std::slice;
-
-use windows::Win32::System::LibraryLoader::GetModuleHandleW;
-
-use crate::patchy::Patch;
+use crate::{
+    config::Config,
+    feature::Feature,
+    patchy::{
+        memory::{Memory, Native},
+        Context, Exit, Patch, PatchError,
+    },
+    version::{addresses, GameVersion},
+};
+
+/// MOV RBX, RAX; MOV RCX, RSI
+const ORIGINAL_SIZE: usize = 6;
+
+/// Prevents the 1.163 missile-fuze crash when a flare's linked object is gone.
+pub struct FlareCrashFix;
+
+impl Feature for FlareCrashFix {
+    type Settings = ();
+
+    const NAME: &'static str = "Flare crash fix";
+    const VERSIONS: &'static [GameVersion] = &[GameVersion::V1_163];
+
+    fn settings(config: &Config) -> Option<()> {
+        config.enable_flare_crash_fix.then_some(())
+    }
 
-const HOOK_RVA: usize = 0x36bb5;
-const NON_NULL_RESUME_RVA: usize = 0x36bbb;
-const NULL_EXIT_RVA: usize = 0x36ca2;
-const ORIGINAL_BYTES: [u8; 6] = [0x48, 0x8b, 0xd8, 0x48, 0x8b, 0xce];
+    unsafe fn install((): ()) -> Result<Vec<Patch>, PatchError> {
+        patch_flare_crash()
+    }
+}
 
 /// Installs the nullable missile-fuze link check required by HighFleet 1.163.
-#[cfg(any(feature = "1_163", not(any(feature = "1_151", feature = "1_163"))))]
-pub unsafe fn patch_flare_crash() {
-    let module_base = match GetModuleHandleW(None) {
-        Ok(module) => module.0 as usize,
-        Err(error) => {
-            log::error!("flare_crash_fix: unable to locate the game executable: {error}");
-            return;
-        }
+///
+/// Reports that the fix is unnecessary on versions without the crash, such as 1.151.
+unsafe fn patch_flare_crash() -> Result<Vec<Patch>, PatchError> {
+    let addresses = addresses();
+    let Some(sites) = &addresses.flare_crash else {
+        log::info!("Flare crash fix is not required on this game version");
+        return Ok(Vec::new());
     };
-    let hook_address = module_base + HOOK_RVA;
-    let found_bytes = slice::from_raw_parts(hook_address as *const u8, ORIGINAL_BYTES.len());
-
-    if found_bytes != ORIGINAL_BYTES {
-        log::error!(
-            "flare_crash_fix: unexpected bytes at {hook_address:#x}; expected {:02x?}, found {:02x?}",
-            ORIGINAL_BYTES,
-            found_bytes
-        );
-        return;
-    }
 
-    let trampoline = build_trampoline(module_base);
-    let patch = Patch::detour(hook_address, ORIGINAL_BYTES.len(), &trampoline);
-    std::mem::forget(patch);
+    let module_base = Native::main_module().map_err(PatchError::MalformedImage)?;
 
-    log::info!("Flare crash fix enabled");
-}
+    if sites.resume_rva != sites.hook_rva + ORIGINAL_SIZE {
+        return Err(PatchError::UnexpectedResume {
+            hook: module_base + sites.hook_rva,
+            resume: module_base + sites.resume_rva,
+        });
+    }
 
-/// Reports that the 1.163-only fix is unnecessary on HighFleet 1.151.
-#[cfg(feature = "1_151")]
-pub unsafe fn patch_flare_crash() {
-    log::info!("Flare crash fix is not required on HighFleet 1.151");
+    let patch = Patch::hook_with_exits(
+        module_base + sites.hook_rva,
+        addresses.original_bytes("flare_crash.hook_rva")?,
+        ORIGINAL_SIZE,
+        true,
+        &[(NULL_LINK, module_base + sites.null_exit_rva)],
+        check_fuze_link,
+    )?;
+    log::info!("Flare crash fix enabled");
+    Ok(vec![patch])
 }
 
-fn build_trampoline(module_base: usize) -> Vec<u8> {
-    let mut trampoline = Vec::with_capacity(39);
-
-    // TEST RAX, RAX; JNZ non_null (skip the 14-byte absolute null jump).
-    trampoline.extend_from_slice(&[0x48, 0x85, 0xc0, 0x75, 0x0e]);
-    push_absolute_jump(&mut trampoline, module_base + NULL_EXIT_RVA);
-
-    // Replay the instructions overwritten at the hook point.
-    trampoline.extend_from_slice(&ORIGINAL_BYTES);
-    push_absolute_jump(&mut trampoline, module_base + NON_NULL_RESUME_RVA);
+/// Skips the missile-fuze code when the linked object is gone.
+const NULL_LINK: &str = "null_link";
 
-    trampoline
-}
-
-fn push_absolute_jump(code: &mut Vec<u8>, destination: usize) {
-    // JMP qword ptr [RIP]; the destination pointer immediately follows.
-    code.extend_from_slice(&[0xff, 0x25, 0x00, 0x00, 0x00, 0x00]);
-    code.extend_from_slice(&destination.to_le_bytes());
+/// Takes the null exit when RAX, the fuze's linked object, is null.
+fn check_fuze_link(context: &mut Context) -> Exit {
+    if context.rax == 0 {
+        Exit::named(NULL_LINK)
+    } else {
+        Exit::CONTINUE
+    }
 }
 
 #[cfg(test)]
@@ -71,20 +79,11 @@ mod tests {
     use super::*;
 
     #[test]
-    fn trampoline_branches_to_the_expected_runtime_addresses() {
-        let module_base = 0x140000000;
-        let trampoline = build_trampoline(module_base);
-
-        assert_eq!(&trampoline[..5], &[0x48, 0x85, 0xc0, 0x75, 0x0e]);
-        assert_eq!(
-            usize::from_le_bytes(trampoline[11..19].try_into().unwrap()),
-            module_base + NULL_EXIT_RVA
-        );
-        assert_eq!(&trampoline[19..25], &ORIGINAL_BYTES);
-        assert_eq!(
-            usize::from_le_bytes(trampoline[31..39].try_into().unwrap()),
-            module_base + NON_NULL_RESUME_RVA
-        );
-        assert_eq!(trampoline.len(), 39);
+    fn null_links_take_the_null_exit() {
+        let mut context = Context::default();
+        assert_eq!(check_fuze_link(&mut context), Exit::named(NULL_LINK));
+
+        context.rax = 0x2_0000_1000;
+        assert_eq!(check_fuze_link(&mut context), Exit::CONTINUE);
     }
 }
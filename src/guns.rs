@@ -1,159 +1,143 @@
-use crate::patchy::Patch;
+use crate::{
+    config::Config,
+    feature::Feature,
+    mem::MemoryError,
+    patchy::{Context, Exit, Patch, PatchError},
+    structs::game::{Body, ShipPart},
+    version::{addresses, FireGunSites, GameVersion},
+};
+
+/// Lets guns fire through their own ship where the game blocks them.
+pub struct UnblockedGuns;
+
+impl Feature for UnblockedGuns {
+    type Settings = ();
+
+    const NAME: &'static str = "Unblocked guns";
+    const VERSIONS: &'static [GameVersion] = &[GameVersion::V1_151];
+
+    fn settings(config: &Config) -> Option<()> {
+        config.enable_unblocked_guns.then_some(())
+    }
 
-/// In v1.151, gun blocking already exists in the game.
-/// This function NOPs out the blocking check to allow guns to fire through own ship.
-#[cfg(feature = "1_151")]
-pub unsafe fn patch_sector_blocking() {
-    let address: usize = 0x14003314d;
-    let size: usize = 6;
+    unsafe fn install((): ()) -> Result<Vec<Patch>, PatchError> {
+        patch_sector_blocking()
+    }
+}
 
-    let data = vec![0x90; size]; // NOP instructions
-    let p = Patch::overwrite(address, &data);
-    std::mem::forget(p);
+/// Restores gun blocking where the game removed it, unless unblocked guns are enabled.
+pub struct GunBlocking;
+
+impl Feature for GunBlocking {
+    type Settings = ();
+
+    const NAME: &'static str = "Gun blocking";
+    const VERSIONS: &'static [GameVersion] = &[GameVersion::V1_163];
+
+    fn settings(config: &Config) -> Option<()> {
+        (!config.enable_unblocked_guns).then_some(())
+    }
+
+    unsafe fn install((): ()) -> Result<Vec<Patch>, PatchError> {
+        patch_sector_restoration()
+    }
 }
 
-/// Gun blocking is already absent in v1.163, so "unblocking" is a no-op.
-#[cfg(any(feature = "1_163", not(any(feature = "1_151", feature = "1_163"))))]
-pub unsafe fn patch_sector_blocking() {}
+/// Where the game still has gun blocking (v1.151), NOPs out the blocking check
+/// to allow guns to fire through own ship.
+///
+/// Gun blocking is already absent in v1.163, so "unblocking" is a no-op there.
+unsafe fn patch_sector_blocking() -> Result<Vec<Patch>, PatchError> {
+    let addresses = addresses();
+    let Some(address) = addresses.gun_sector_check else {
+        return Ok(Vec::new());
+    };
+    let size: usize = 6;
 
-/// In v1.151, gun blocking is native — nothing to restore.
-#[cfg(feature = "1_151")]
-pub unsafe fn patch_sector_restoration() {}
+    let data = vec![0x90; size]; // NOP instructions
+    let p = Patch::overwrite(
+        address,
+        addresses.original_bytes("guns.sector_check")?,
+        &data,
+    )?;
+    Ok(vec![p])
+}
 
-/// Restores the gun-blocking sector check into v1.163's FireGun function.
+/// Restores the gun-blocking sector check into FireGun where the game removed it (v1.163).
 ///
 /// In v1.151, FireGun contained logic that checked a 360-float "sectors" array
 /// on each gun body to determine if the gun's firing arc was blocked by its own
 /// ship. This check was removed in v1.163. This patch re-implements it by:
 ///
 /// 1. Writing an `is_gun_blocked` function in Rust
-/// 2. Allocating a code cave near FireGun
-/// 3. Injecting a trampoline at the charge-decrement point that calls the Rust
-///    function and conditionally skips firing if blocked
-#[cfg(any(feature = "1_163", not(any(feature = "1_151", feature = "1_163"))))]
-pub unsafe fn patch_sector_restoration() {
-    use crate::patchy::{relative_offset, PatchError};
-
-    // FireGun addresses in v1.163
-    const INJECTION_ADDR: usize = 0x140032f22;
-    const EXIT_0_ADDR: usize = 0x140032ef0;
-    const RETURN_ADDR: usize = 0x140032f29;
+/// 2. Hooking the charge-decrement point with a conditional-exit hook that
+///    calls the Rust function and skips firing if blocked
+///
+/// In v1.151 gun blocking is native, so there is nothing to restore.
+unsafe fn patch_sector_restoration() -> Result<Vec<Patch>, PatchError> {
     const OVERWRITE_SIZE: usize = 7; // SUBSS XMM1,XMM7 (4) + COMISS XMM6,XMM1 (3)
 
-    let fn_ptr = is_gun_blocked as *const () as usize;
-
-    // --- Build the code cave trampoline ---
-    //
-    // At the injection point (0x140032f22), register state is:
-    //   RDI  = param_1 (Body pointer, non-volatile)
-    //   XMM1 = fFireCharge (volatile — must save across call)
-    //   XMM6 = 0.0  (non-volatile)
-    //   XMM7 = 1.0  (non-volatile)
-    //   RSP  ≡ 0 mod 16
-    //
-    // Trampoline logic:
-    //   save XMM1 + shadow space
-    //   call is_gun_blocked(RDI)
-    //   restore XMM1
-    //   if blocked → JMP EXIT_0 (0x140032ef0)
-    //   else       → replay overwritten instructions, JMP 0x140032f29
-    let mut cave: Vec<u8> = Vec::with_capacity(64);
-
-    // SUB RSP, 0x30  (0x10 XMM1 save + 0x20 shadow space)
-    cave.extend_from_slice(&[0x48, 0x83, 0xEC, 0x30]);
-    // MOVDQU [RSP+0x20], XMM1
-    cave.extend_from_slice(&[0xF3, 0x0F, 0x7F, 0x4C, 0x24, 0x20]);
-    // MOV RCX, RDI
-    cave.extend_from_slice(&[0x48, 0x89, 0xF9]);
-    // FF 15 02 00 00 00   CALL [RIP+2]
-    // EB 08               JMP +8  (skip over 8-byte pointer)
-    // <8 bytes>           absolute function pointer
-    cave.extend_from_slice(&[0xFF, 0x15, 0x02, 0x00, 0x00, 0x00]);
-    cave.extend_from_slice(&[0xEB, 0x08]);
-    cave.extend_from_slice(&fn_ptr.to_le_bytes());
-    // MOVDQU XMM1, [RSP+0x20]
-    cave.extend_from_slice(&[0xF3, 0x0F, 0x6F, 0x4C, 0x24, 0x20]);
-    // ADD RSP, 0x30
-    cave.extend_from_slice(&[0x48, 0x83, 0xC4, 0x30]);
-    // TEST AL, AL
-    cave.extend_from_slice(&[0x84, 0xC0]);
-    // JNZ blocked  (forward jump over 4+3+5 = 12 bytes)
-    cave.extend_from_slice(&[0x75, 0x0C]);
-
-    // --- Not blocked: replay overwritten instructions ---
-    // SUBSS XMM1, XMM7
-    cave.extend_from_slice(&[0xF3, 0x0F, 0x5C, 0xCF]);
-    // COMISS XMM6, XMM1
-    cave.extend_from_slice(&[0x0F, 0x2F, 0xF1]);
-    // JMP rel32 → RETURN_ADDR (placeholder, fixed up below)
-    let jmp_back_off = cave.len();
-    cave.extend_from_slice(&[0xE9, 0x00, 0x00, 0x00, 0x00]);
-
-    // --- Blocked: jump to EXIT_0 ---
-    let jmp_exit_off = cave.len();
-    cave.extend_from_slice(&[0xE9, 0x00, 0x00, 0x00, 0x00]);
-
-    let trampoline_size = cave.len();
-    let p = Patch::detour_with(
-        INJECTION_ADDR,
+    let addresses = addresses();
+    let Some(FireGunSites {
+        charge_hook: injection_addr,
+        blocked_exit: exit_0_addr,
+        resume: return_addr,
+        ..
+    }) = addresses.fire_gun
+    else {
+        return Ok(Vec::new());
+    };
+
+    // The hook resumes right after the overwritten instructions.
+    if return_addr != injection_addr + OVERWRITE_SIZE {
+        return Err(PatchError::UnexpectedResume {
+            hook: injection_addr,
+            resume: return_addr,
+        });
+    }
+
+    // At the injection point (0x140032f22 in v1.163), RDI holds param_1, the gun
+    // Body. If blocked the hook exits to EXIT_0 (0x140032ef0 in v1.163), otherwise
+    // it replays the overwritten instructions and resumes at 0x140032f29.
+    let p = Patch::hook_with_exits(
+        injection_addr,
+        addresses.original_bytes("fire_gun.charge_hook")?,
         OVERWRITE_SIZE,
-        trampoline_size,
-        move |cave_base| {
-            let mut code = cave.clone();
-
-            let src_back = cave_base
-                .checked_add(jmp_back_off + 5)
-                .ok_or(PatchError::AddressOverflow)?;
-            let rel_back = relative_offset(src_back, RETURN_ADDR)?;
-            code[jmp_back_off + 1..jmp_back_off + 5].copy_from_slice(&rel_back.to_le_bytes());
-
-            let src_exit = cave_base
-                .checked_add(jmp_exit_off + 5)
-                .ok_or(PatchError::AddressOverflow)?;
-            let rel_exit = relative_offset(src_exit, EXIT_0_ADDR)?;
-            code[jmp_exit_off + 1..jmp_exit_off + 5].copy_from_slice(&rel_exit.to_le_bytes());
-
-            Ok(code)
-        },
-    );
+        true,
+        &[(BLOCKED, exit_0_addr)],
+        check_sector_blocking,
+    )?;
     let cave_base = p
         .trampoline_address()
-        .expect("gun-blocking detour has no trampoline");
-    std::mem::forget(p);
+        .expect("gun-blocking hook has no trampoline");
+
+    log::info!("gun_blocking: trampoline prepared at {injection_addr:#x} → cave at {cave_base:#x}");
+    Ok(vec![p])
+}
 
-    log::info!("gun_blocking: trampoline prepared at {INJECTION_ADDR:#x} → cave at {cave_base:#x}");
+/// Leaves FireGun through EXIT_0 when the gun's firing arc is blocked.
+const BLOCKED: &str = "blocked";
+
+fn check_sector_blocking(context: &mut Context) -> Exit {
+    match unsafe { is_gun_blocked(context.rdi as usize) } {
+        Ok(true) => Exit::named(BLOCKED),
+        Ok(false) => Exit::CONTINUE,
+        Err(error) => {
+            log::debug!(
+                "gun_blocking: unable to check the gun at {:#x}: {error}",
+                context.rdi
+            );
+            Exit::CONTINUE
+        }
+    }
 }
 
 // ---------------------------------------------------------------------------
 // Gun-blocking sector check (mirrors v1.151 FireGun logic)
 // ---------------------------------------------------------------------------
 
-/// Body struct field offsets for v1.163.
-///
-/// Mapped from v1.151 by cross-referencing disassembly patterns.
-/// Fields below 0x4F0 are unchanged; the sectors vector shifted +0x10
-/// due to a new int-vector inserted at 0x4F0.
-mod body {
-    pub const M_CODE: usize = 0x028;
-    pub const MASTER_NODE: usize = 0x088;
-    pub const OWNER_NODE: usize = 0x0B8;
-    pub const ANGLE: usize = 0x138;
-    pub const PART_INDEX: usize = 0x188;
-    pub const SECTORS_BEGIN: usize = 0x508;
-    pub const SECTORS_END: usize = 0x510;
-}
-
-/// ShipPart struct field offsets for v1.163.
-mod ship_part {
-    pub const MDL_SECTORS_TYPE: usize = 0x128;
-}
-
 const TAU: f32 = 6.2831855;
 const SECTOR_COUNT: usize = 360;
-const CODE_BODY: u8 = 0x0F;
-
-/// Address of `GetStats` in v1.163 (equivalent to v1.151's `GetShipPart`).
-const GET_STATS_ADDR: usize = 0x140281e00;
 
 type GetStatsFn = unsafe extern "C" fn(i32) -> *const u8;
 
@@ -166,51 +150,49 @@ type GetStatsFn = unsafe extern "C" fn(i32) -> *const u8;
 /// 4. Compute the gun's angle relative to the root body
 /// 5. Normalize to \[0, 2pi) and map to a sector index 0..359
 /// 6. Return true if that sector is blocked (value == 0.0)
-unsafe extern "C" fn is_gun_blocked(gun: *const u8) -> bool {
+///
+/// Fails if one of the structures along the way cannot be read.
+unsafe fn is_gun_blocked(gun: usize) -> Result<bool, MemoryError> {
+    let Some(fire_gun) = &addresses().fire_gun else {
+        return Ok(false);
+    };
+    let gun = Body::at(gun);
+
     // 1. Get ShipPart via part_index
-    let part_index = *(gun.add(body::PART_INDEX) as *const i32);
-    let get_stats: GetStatsFn = std::mem::transmute(GET_STATS_ADDR);
-    let part = get_stats(part_index);
+    // `GetStats` is v1.163's equivalent of v1.151's `GetShipPart`.
+    let get_stats: GetStatsFn = std::mem::transmute(fire_gun.get_stats_fn);
+    let part = get_stats(gun.part_index()?);
     if part.is_null() {
-        return false;
+        return Ok(false);
     }
 
     // 2. Part must define sector data
-    if *(part.add(ship_part::MDL_SECTORS_TYPE) as *const i32) == 0 {
-        return false;
+    let Some(part) = ShipPart::at(part as usize) else {
+        return Ok(false);
+    };
+    if !part.has_sectors()? {
+        return Ok(false);
     }
 
     // 3. Gun must have a parent body
-    let owner = *(gun.add(body::OWNER_NODE) as *const *const u8);
-    if owner.is_null() {
-        return false;
-    }
+    let Some(owner) = gun.owner()? else {
+        return Ok(false);
+    };
 
     // 4. Sectors array must contain exactly 360 floats (0x5A0 bytes)
-    let sectors_begin = *(gun.add(body::SECTORS_BEGIN) as *const *const f32);
-    let sectors_end = *(gun.add(body::SECTORS_END) as *const *const f32);
-    if sectors_begin.is_null() {
-        return false;
-    }
-    let byte_span = (sectors_end as isize - sectors_begin as isize) & !3isize;
-    if byte_span != (SECTOR_COUNT * std::mem::size_of::<f32>()) as isize {
-        return false;
+    let sectors = gun.sectors()?;
+    if sectors.len() != SECTOR_COUNT {
+        return Ok(false);
     }
 
     // 5. Walk master chain to root Body
     let mut root = owner;
-    loop {
-        let cursor = *(root.add(body::MASTER_NODE) as *const *const u8);
-        if cursor.is_null() || (*cursor.add(body::M_CODE) & 0x0F) != CODE_BODY {
-            break;
-        }
-        root = cursor;
+    while let Some(master) = root.master_body()? {
+        root = master;
     }
 
     // 6. Relative angle: gun minus root
-    let gun_angle = *(gun.add(body::ANGLE) as *const f32);
-    let root_angle = *(root.add(body::ANGLE) as *const f32);
-    let mut rel = gun_angle - root_angle;
+    let mut rel = gun.angle()? - root.angle()?;
 
     // 7. Normalize into [0, TAU)
     let steps = (rel.abs() / TAU + 0.5).floor();
@@ -226,10 +208,8 @@ unsafe extern "C" fn is_gun_blocked(gun: *const u8) -> bool {
 
     // 8. Map to sector index and check
     let idx = ((rel / TAU) * SECTOR_COUNT as f32) as i32;
-    if idx >= 0 && (idx as u64) < SECTOR_COUNT as u64 && *sectors_begin.offset(idx as isize) == 0.0
-    {
-        return true; // BLOCKED
+    if idx < 0 {
+        return Ok(false);
     }
-
-    false
+    Ok(sectors.get(idx as usize)? == Some(0.0)) // 0.0 means BLOCKED
 }
@@ -2,30 +2,58 @@
 
 #![deny(missing_docs)]
 
-use std::ffi::{c_char, CStr};
+use std::{
+    ffi::{c_char, CStr},
+    path::PathBuf,
+};
 
-use crate::config::Config;
+use crate::{
+    config::{Config, CONFIG_PATH},
+    feature::Summary,
+};
 
 mod config;
 mod dumpable;
+mod feature;
 mod flare_crash;
 mod guns;
 #[cfg(debug_assertions)]
 mod logger;
+mod mem;
 mod parts;
 mod patchy;
 mod plane;
+mod profile;
+mod reload;
 mod rng;
 mod sell_multiplier;
 mod shake;
 mod structs;
 mod ttl;
+mod version;
 mod zoom;
 
+/// Environment variable that turns on dry runs: patches are prepared and
+/// written to [`PATCH_PLAN_PATH`] instead of being installed.
+const DRY_RUN_VARIABLE: &str = "QOL_DRY_RUN";
+
+/// Where a dry run writes the patch plan.
+const PATCH_PLAN_PATH: &str = "Modloader/config/qol_patch_plan.json";
+
 #[no_mangle]
 unsafe extern "C" fn init() -> bool {
-    let config = Config::load("Modloader/config/qol.json");
-    let config = match config {
+    let Some(profile) = version::selected() else {
+        log::error!("No supported game version was detected, not patching");
+        return false;
+    };
+    log::info!(
+        "Patching {} using the {:?} layout",
+        profile.game_version,
+        profile.layout
+    );
+
+    let config = Config::load_upgraded(CONFIG_PATH);
+    let mut config = match config {
         Ok(c) => c,
         Err(e) => {
             log::error!("Failed to load config: {}", e);
@@ -33,110 +61,96 @@ unsafe extern "C" fn init() -> bool {
             let conf = Config::default();
 
             // Check if default config exists
-            if std::path::Path::new("Modloader/config/qol.json").exists() {
+            if std::path::Path::new(CONFIG_PATH).exists() {
                 log::error!(
                     "Config file exists but failed to load. Please check the file for errors."
                 );
             } else {
                 // Save the default config
-                if let Err(e) = conf.save("Modloader/config/qol.json") {
+                if let Err(e) = conf.save(CONFIG_PATH) {
                     log::error!("Failed to save default config: {}", e);
                 } else {
-                    log::info!("Default config saved to Modloader/config/qol.json");
+                    log::info!("Default config saved to {CONFIG_PATH}");
                 }
             }
 
             conf
         }
     };
+    config.repair();
 
-    if config.enable_flare_crash_fix {
-        flare_crash::patch_flare_crash();
-    } else {
-        log::info!("Flare crash fix disabled");
-    }
-
-    if config.enable_anti_wobble {
-        dumpable::dumpable();
-        log::info!("Anti-wobble enabled");
-    } else {
-        log::info!("Anti-wobble disabled");
-    }
-
-    if config.enable_arcade_zoom {
-        zoom::patch_zoom(config.min_zoom_level as u32, config.max_zoom_level as u32);
+    let dry_run = std::env::var_os(DRY_RUN_VARIABLE).map(|_| PathBuf::from(PATCH_PLAN_PATH));
+    if dry_run.is_some() {
         log::info!(
-            "Arcade zoom enabled (min zoom level {}, max zoom level {})",
-            config.min_zoom_level,
-            config.max_zoom_level
+            "{DRY_RUN_VARIABLE} is set, patches are only planned and written to {PATCH_PLAN_PATH}"
         );
-
-        if config.zoom_levels.len() < 5 {
-            log::warn!("The game by default specifies 5 zoom levels. If you specify less, the game may be unstable.");
-        }
-
-        if config.zoom_levels.len() < config.max_zoom_level as usize {
-            log::warn!("You have specified more max zoom levels than you have zoom levels. This may cause instability.");
-        }
-
-        zoom::patch_levels(config.zoom_levels);
-    } else {
-        log::info!("Arcade zoom disabled");
     }
 
-    if config.enable_unblocked_guns {
-        guns::patch_sector_blocking();
-        log::info!("Unblocked guns enabled");
-    } else {
-        guns::patch_sector_restoration();
-        log::info!("Gun blocking enabled");
-    }
-
-    if config.enable_reduced_shake {
-        shake::patch_shake();
-        log::info!("Reduced shake enabled");
-    } else {
-        log::info!("Reduced shake disabled");
-    }
+    let mut summary = Summary::prepare_all(&config, profile.layout);
 
-    if config.enable_unblocked_ttl {
-        ttl::patch_ttl();
-        log::info!("Unblocked TTL enabled");
+    if dry_run.is_some() {
+        log::info!("Dry run: planes and loadouts are left unchanged");
+    } else if config.planes.is_empty() {
+        log::info!("No planes in the config, keeping the game's planes and loadouts");
     } else {
-        log::info!("Unblocked TTL disabled");
+        plane::patch_planes(&config.planes);
     }
 
-    plane::patch_planes(&config.planes);
+    let installed = match patchy::finalize_patches(dry_run.as_deref()) {
+        Ok(failures) => {
+            for failure in failures {
+                summary.install_failed(failure);
+            }
+            true
+        }
+        Err(error) => {
+            log::error!("Unable to install prepared patches, the game runs unpatched: {error}");
+            summary.all_failed();
+            false
+        }
+    };
+    summary.log();
 
-    if config.enable_shop_parts {
-        parts::patch_custom_parts(config.shop_parts);
-        log::info!("Custom parts enabled");
-    } else {
-        log::info!("Custom parts disabled");
+    if installed && dry_run.is_none() {
+        reload::watch(CONFIG_PATH, &config);
     }
 
-    sell_multiplier::patch_sell_multiplier(config.sell_multiplier);
+    installed
+}
 
-    if let Err(error) = patchy::finalize_patches() {
-        log::error!("Unable to install prepared patches: {error}");
-        return false;
+/// Lets other mods check their patch sites against ours.
+///
+/// Copies up to `capacity` of this mod's installed patches into `ranges` and
+/// returns how many there are, so a caller whose buffer was too small can retry
+/// with a larger one. `ranges` may be null when `capacity` is 0.
+#[no_mangle]
+unsafe extern "C" fn patch_map(ranges: *mut patchy::PatchRange, capacity: usize) -> usize {
+    let map = patchy::patch_map();
+    if !ranges.is_null() {
+        let count = map.len().min(capacity);
+        std::ptr::copy_nonoverlapping(map.as_ptr(), ranges, count);
     }
-
-    true
+    map.len()
 }
 
 #[no_mangle]
 unsafe extern "C" fn version(version: *const c_char) -> bool {
     let version = CStr::from_ptr(version).to_str().unwrap();
-    if cfg!(feature = "1_151") {
-        version == "Steam 1.151"
-    } else if cfg!(feature = "1_163") {
-        version == "Steam 1.163"
-    } else if version == "Gog 1.163" {
-        log::error!("Gog 1.163 detected");
-        log::error!("Your game will crash. QOL only supports steam versions of the game.");
-        false
-    } else {
-        false
+    match profile::load_profile(version) {
+        Ok(Some(profile)) => version::select(profile),
+        Ok(None) if version == "Gog 1.163" => {
+            log::error!("Gog 1.163 detected");
+            log::error!("QOL has no address profile for this version of the game.");
+            log::error!(
+                "Add a profile for it to {} to enable the mod.",
+                profile::PROFILE_DIRECTORY
+            );
+            false
+        }
+        Ok(None) => false,
+        Err(error) => {
+            log::error!("Unable to load the address profile for {version}: {error}");
+            false
+        }
     }
 }
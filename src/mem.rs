@@ -0,0 +1,270 @@
+//! Checked access to game memory.
+//!
+//! The game's structures are reached through pointers that may be null, stale or
+//! simply wrong for the running build. Instead of dereferencing them directly,
+//! [`read`], [`write`] and [`resolve`] first check that the memory is committed
+//! and allows the access, and report an error where a raw access would crash
+//! the game.
+
+use std::{fmt, mem::size_of};
+
+use crate::patchy::memory::{Memory, Native, Region};
+
+/// Memory regions the checks are made against.
+pub trait AddressSpace {
+    /// Returns the committed region containing `address`, if any.
+    fn region(&self, address: usize) -> Option<Region>;
+}
+
+/// The address space of the game process.
+pub struct Process;
+
+impl AddressSpace for Process {
+    fn region(&self, address: usize) -> Option<Region> {
+        // SAFETY: only queries the operating system.
+        unsafe { Native::region(address) }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MemoryError {
+    AddressOverflow,
+    NullPointer { address: usize },
+    Unreadable { address: usize, size: usize },
+    Unwritable { address: usize, size: usize },
+}
+
+impl fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AddressOverflow => write!(f, "address calculation overflowed"),
+            Self::NullPointer { address } => write!(f, "the pointer at {address:#x} is null"),
+            Self::Unreadable { address, size } => {
+                write!(f, "the {size} bytes at {address:#x} are not readable")
+            }
+            Self::Unwritable { address, size } => {
+                write!(f, "the {size} bytes at {address:#x} are not writable")
+            }
+        }
+    }
+}
+
+impl std::error::Error for MemoryError {}
+
+/// Reads the `T` at `address`.
+///
+/// # Safety
+/// The bytes at `address` must form a valid `T`, and no other thread may be
+/// writing them.
+pub unsafe fn read<T: Copy>(address: usize) -> Result<T, MemoryError> {
+    read_in(&Process, address)
+}
+
+/// Writes `value` to `address`.
+///
+/// # Safety
+/// Nothing may rely on the bytes at `address` keeping their value, and no other
+/// thread may be accessing them.
+pub unsafe fn write<T: Copy>(address: usize, value: T) -> Result<(), MemoryError> {
+    write_in(&Process, address, value)
+}
+
+/// Follows a pointer chain from `base`, reading a pointer and adding the next
+/// offset at each step.
+///
+/// `resolve(base, &[0x348, 0x25c])` returns the address `[[base] + 0x348] + 0x25c`.
+/// A null pointer anywhere along the chain is an error.
+///
+/// # Safety
+/// No other thread may be writing the pointers along the chain.
+pub unsafe fn resolve(base: usize, offsets: &[usize]) -> Result<usize, MemoryError> {
+    resolve_in(&Process, base, offsets)
+}
+
+/// Checks that the `size` bytes at `address` can be read.
+pub fn check_readable(address: usize, size: usize) -> Result<(), MemoryError> {
+    check(&Process, address, size, |region| region.readable)
+        .map_err(|()| MemoryError::Unreadable { address, size })
+}
+
+unsafe fn read_in<T: Copy>(space: &impl AddressSpace, address: usize) -> Result<T, MemoryError> {
+    let size = size_of::<T>();
+    check(space, address, size, |region| region.readable)
+        .map_err(|()| MemoryError::Unreadable { address, size })?;
+    Ok((address as *const T).read_unaligned())
+}
+
+unsafe fn write_in<T: Copy>(
+    space: &impl AddressSpace,
+    address: usize,
+    value: T,
+) -> Result<(), MemoryError> {
+    let size = size_of::<T>();
+    check(space, address, size, |region| region.writable)
+        .map_err(|()| MemoryError::Unwritable { address, size })?;
+    (address as *mut T).write_unaligned(value);
+    Ok(())
+}
+
+unsafe fn resolve_in(
+    space: &impl AddressSpace,
+    base: usize,
+    offsets: &[usize],
+) -> Result<usize, MemoryError> {
+    let mut address = base;
+    for offset in offsets {
+        let pointer = read_in::<usize>(space, address)?;
+        if pointer == 0 {
+            return Err(MemoryError::NullPointer { address });
+        }
+        address = pointer
+            .checked_add(*offset)
+            .ok_or(MemoryError::AddressOverflow)?;
+    }
+    Ok(address)
+}
+
+/// Checks that every region overlapping the `size` bytes at `address` is committed
+/// and passes `allows`.
+fn check(
+    space: &impl AddressSpace,
+    address: usize,
+    size: usize,
+    allows: impl Fn(&Region) -> bool,
+) -> Result<(), ()> {
+    if address == 0 {
+        return Err(());
+    }
+    let end = address.checked_add(size).ok_or(())?;
+
+    let mut cursor = address;
+    while cursor < end {
+        let region = space.region(cursor).ok_or(())?;
+        if !allows(&region) || region.range.end <= cursor {
+            return Err(());
+        }
+        cursor = region.range.end;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Regions over memory owned by the test, so checked accesses really happen.
+    struct FakeSpace {
+        regions: Vec<Region>,
+    }
+
+    impl FakeSpace {
+        fn new(regions: impl IntoIterator<Item = (usize, usize, bool, bool)>) -> Self {
+            let regions = regions
+                .into_iter()
+                .map(|(start, size, readable, writable)| Region {
+                    range: start..start + size,
+                    readable,
+                    writable,
+                })
+                .collect();
+            Self { regions }
+        }
+    }
+
+    impl AddressSpace for FakeSpace {
+        fn region(&self, address: usize) -> Option<Region> {
+            self.regions
+                .iter()
+                .find(|region| region.range.contains(&address))
+                .cloned()
+        }
+    }
+
+    #[test]
+    fn accesses_are_checked_against_the_regions() {
+        let mut memory = [0u8; 32];
+        let base = memory.as_mut_ptr() as usize;
+        // Readable and writable, then read-only, then unmapped.
+        let space = FakeSpace::new([(base, 8, true, true), (base + 8, 8, true, false)]);
+
+        unsafe {
+            write_in(&space, base + 2, 0x1234_5678u32).unwrap();
+            assert_eq!(read_in::<u32>(&space, base + 2), Ok(0x1234_5678));
+            // Reads may span adjacent readable regions.
+            assert_eq!(read_in::<u64>(&space, base + 4), Ok(0x1234));
+
+            assert_eq!(
+                write_in(&space, base + 6, 0u32),
+                Err(MemoryError::Unwritable {
+                    address: base + 6,
+                    size: 4
+                })
+            );
+            assert_eq!(
+                read_in::<u32>(&space, base + 14),
+                Err(MemoryError::Unreadable {
+                    address: base + 14,
+                    size: 4
+                })
+            );
+            assert!(read_in::<u8>(&space, 0).is_err());
+        }
+        assert_eq!(memory[6..8], [0, 0]);
+    }
+
+    #[test]
+    fn pointer_chains_are_followed() {
+        let mut target = [0u8; 0x40];
+        let mut object = [0usize; 8];
+        object[3] = target.as_mut_ptr() as usize;
+        let base = [object.as_ptr() as usize];
+        let null = [0usize];
+
+        let space = FakeSpace::new([
+            (target.as_ptr() as usize, target.len(), true, true),
+            // Only the first half of the object is mapped.
+            (
+                object.as_ptr() as usize,
+                size_of_val(&object) / 2,
+                true,
+                true,
+            ),
+            (base.as_ptr() as usize, size_of_val(&base), true, false),
+            (null.as_ptr() as usize, size_of_val(&null), true, false),
+        ]);
+
+        unsafe {
+            // [[base] + 0x18] + 0x25
+            let address = resolve_in(&space, base.as_ptr() as usize, &[0x18, 0x25]).unwrap();
+            assert_eq!(address, target.as_ptr() as usize + 0x25);
+            assert_eq!(
+                resolve_in(&space, base.as_ptr() as usize, &[]),
+                Ok(base.as_ptr() as usize)
+            );
+
+            assert_eq!(
+                resolve_in(&space, null.as_ptr() as usize, &[0x18]),
+                Err(MemoryError::NullPointer {
+                    address: null.as_ptr() as usize
+                })
+            );
+            assert_eq!(
+                resolve_in(&space, base.as_ptr() as usize, &[0x20, 0]),
+                Err(MemoryError::Unreadable {
+                    address: object.as_ptr() as usize + 0x20,
+                    size: size_of::<usize>()
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn process_memory_is_checked() {
+        let value = 0x5eed_u32;
+        unsafe {
+            assert_eq!(read::<u32>(&value as *const u32 as usize), Ok(0x5eed));
+            assert!(read::<u32>(0x10).is_err());
+        }
+        assert!(check_readable(&value as *const u32 as usize, 4).is_ok());
+    }
+}
@@ -6,12 +6,23 @@
 //! Each part has a configurable probability of appearing and a random count in
 //! `[min_parts, max_parts]`, rolled fresh every time the shop generation runs.
 
-use std::{collections::HashMap, ffi::CString};
+use std::{
+    collections::HashMap,
+    ffi::CString,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        PoisonError, RwLock,
+    },
+};
 
 use crate::{
-    config::ShopPart,
-    patchy::{Patch, ReturnType},
+    config::{Config, ShopPart},
+    feature::Feature,
+    mem::{self, MemoryError},
+    patchy::{Patch, PatchError, ReturnType},
     rng,
+    structs::game::City,
+    version::addresses,
 };
 
 /// Stored representation of a custom part with its RNG parameters.
@@ -30,49 +41,49 @@ struct CustomPart {
 }
 
 /// The list of custom parts (with config) to inject into weapon shops.
-static mut CUSTOM_PARTS: Vec<CustomPart> = Vec::new();
+static CUSTOM_PARTS: RwLock<Vec<CustomPart>> = RwLock::new(Vec::new());
+
+/// Set once the shop generation patch has been prepared.
+static PATCHED: AtomicBool = AtomicBool::new(false);
 
-// DefinePart function address
+// DefinePart (`addresses().parts_define_part_fn`):
 // Body * __fastcall DefinePart(Body * allPartLibrary, char * moid, Node * categoryLibrary, int count)
 // On x86_64 Windows, __fastcall is the standard calling convention (RCX, RDX, R8, R9).
-#[cfg(feature = "1_151")]
-const DEFINE_PART_FN: usize = 0x1401fde40;
-#[cfg(any(feature = "1_163", not(any(feature = "1_151", feature = "1_163"))))]
-const DEFINE_PART_FN: usize = 0x14021b190;
-
-// Address of pointer to allPartLibrary (first arg to DefinePart, in RCX)
-#[cfg(feature = "1_151")]
-const ALL_PART_LIBRARY_PTR: usize = 0x143942568;
-#[cfg(any(feature = "1_163", not(any(feature = "1_151", feature = "1_163"))))]
-const ALL_PART_LIBRARY_PTR: usize = 0x143a139f0;
-
-const CITY_TYPE_OFFSET: usize = 0x25c;
-
-// Offset from the city object to the category library node pointer.
-const CATEGORY_NODE_OFFSET: usize = 0x348;
-
-// Address of pointer to the city object, used to resolve the category library (in R8)
-// via +CATEGORY_NODE_OFFSET, and the city type (an i32 in 1–7) via +CITY_TYPE_OFFSET.
-// We follow [CATEGORY_ROOT_PTR] -> +CATEGORY_NODE_OFFSET if non-null, else fall back to [CATEGORY_FALLBACK_PTR].
-#[cfg(feature = "1_151")]
-const CATEGORY_ROOT_PTR: usize = 0x1439220f0;
-#[cfg(feature = "1_151")]
-const CATEGORY_FALLBACK_PTR: usize = 0x147eed968;
-
-#[cfg(any(feature = "1_163", not(any(feature = "1_151", feature = "1_163"))))]
-const CATEGORY_ROOT_PTR: usize = 0x143a11940;
-#[cfg(any(feature = "1_163", not(any(feature = "1_151", feature = "1_163"))))]
-const CATEGORY_FALLBACK_PTR: usize = 0x147fc6f90;
-
-// Hook address: right after a DefinePart call for MDL_ANTENNA_01 in the shop generation function.
-// At 0x14029ae0f there is one instruction:
-//   MOV ECX,dword ptr [RAX + 0x2a8]   (6 bytes: 8b 88 a8 02 00 00)
+//
+// `parts_all_part_library` holds the pointer to allPartLibrary (first arg to DefinePart, in RCX).
+
+// `parts_city` is the address of the pointer to the city object, used to resolve the
+// category library (in R8) and the city type (an i32 in 1–7) through `City`. We use
+// the city's category node if non-null, else fall back to [parts_category_fallback].
+
+// The hook (`parts_hook`) sits right after a DefinePart call for MDL_ANTENNA_01 in the
+// shop generation function. There is one instruction there, which reads the player's
+// cash from the profile:
+//   v1.151 (0x14029ae0f): MOV ECX,dword ptr [RAX + 0x260]   (6 bytes: 8b 88 60 02 00 00)
+//   v1.163 (0x1402bcd49): MOV ECX,dword ptr [RAX + 0x2a8]   (6 bytes: 8b 88 a8 02 00 00)
 // This is 6 bytes, enough for a near jump. We save and replay it in the cave,
 // and our injected function runs after the original DefinePart call has already completed.
-#[cfg(feature = "1_151")]
-const HOOK_ADDRESS: usize = 0x14029ae0f;
-#[cfg(any(feature = "1_163", not(any(feature = "1_151", feature = "1_163"))))]
-const HOOK_ADDRESS: usize = 0x1402bcd49;
+
+/// Injects the configured parts into generated shops.
+pub struct CustomParts;
+
+impl Feature for CustomParts {
+    type Settings = HashMap<String, Vec<ShopPart>>;
+
+    const NAME: &'static str = "Custom parts";
+
+    fn settings(config: &Config) -> Option<Self::Settings> {
+        config.enable_shop_parts.then(|| config.shop_parts.clone())
+    }
+
+    unsafe fn install(parts: Self::Settings) -> Result<Vec<Patch>, PatchError> {
+        patch_custom_parts(parts)
+    }
+
+    fn reload(parts: Self::Settings) -> bool {
+        set_custom_parts(parts)
+    }
+}
 
 /// Patches the shop generation to include custom parts.
 ///
@@ -84,21 +95,66 @@ const HOOK_ADDRESS: usize = 0x1402bcd49;
 /// # Safety
 /// Must be called while the game process memory is accessible and before the shop
 /// generation function runs.
-pub unsafe fn patch_custom_parts(parts: HashMap<String, Vec<ShopPart>>) {
+unsafe fn patch_custom_parts(
+    parts: HashMap<String, Vec<ShopPart>>,
+) -> Result<Vec<Patch>, PatchError> {
     if parts.is_empty() {
         log::info!("No custom parts to inject, skipping patch.");
-        return;
+        return Ok(Vec::new());
     }
 
-    if HOOK_ADDRESS == 0x0 {
-        log::warn!("Custom parts patching is not supported on this game version.");
-        return;
+    let custom_parts = build_custom_parts(parts);
+    if custom_parts.is_empty() {
+        log::warn!("All custom part strings were invalid, skipping patch.");
+        return Ok(Vec::new());
     }
 
+    log::info!(
+        "Patching shop generation to inject up to {} custom part type(s).",
+        custom_parts.len()
+    );
+
+    // Seed the RNG once at init time.
+    rng::seed();
+
+    *CUSTOM_PARTS.write().unwrap_or_else(PoisonError::into_inner) = custom_parts;
+
+    // Hook after the existing DefinePart call.
+    // save_overwritten = true ensures the overwritten MOV executes first,
+    // then our function runs to inject the additional parts.
+    let addresses = addresses();
+    let p = Patch::patch_call(
+        addresses.parts_hook,
+        addresses.original_bytes("parts.hook")?,
+        inject_custom_parts as *const (),
+        6,
+        true,
+        ReturnType::None,
+    )?;
+    PATCHED.store(true, Ordering::Relaxed);
+    Ok(vec![p])
+}
+
+/// Replaces the parts injected into shops generated from now on.
+///
+/// Returns false if the shop generation patch is not installed, so the parts have no effect.
+fn set_custom_parts(parts: HashMap<String, Vec<ShopPart>>) -> bool {
+    let custom_parts = build_custom_parts(parts);
+    log::info!(
+        "Injecting up to {} custom part type(s) into shops.",
+        custom_parts.len()
+    );
+
+    *CUSTOM_PARTS.write().unwrap_or_else(PoisonError::into_inner) = custom_parts;
+    PATCHED.load(Ordering::Relaxed)
+}
+
+/// Converts the configured parts to [`CustomPart`]s, skipping invalid part strings.
+fn build_custom_parts(parts: HashMap<String, Vec<ShopPart>>) -> Vec<CustomPart> {
     // Convert to CustomPart structs with stable CString pointers.
     // Each part name can have multiple ShopPart entries (e.g. different
     // probabilities per city type), so we flatten them all into one list.
-    let custom_parts: Vec<CustomPart> = parts
+    parts
         .into_iter()
         .flat_map(|(name, cfgs)| {
             let cs = match CString::new(name.clone()) {
@@ -139,50 +195,15 @@ pub unsafe fn patch_custom_parts(parts: HashMap<String, Vec<ShopPart>>) {
                 })
                 .collect::<Vec<_>>()
         })
-        .collect();
-
-    if custom_parts.is_empty() {
-        log::warn!("All custom part strings were invalid, skipping patch.");
-        return;
-    }
-
-    log::info!(
-        "Patching shop generation to inject up to {} custom part type(s).",
-        custom_parts.len()
-    );
-
-    // Seed the RNG once at init time.
-    rng::seed();
-
-    // SAFETY: We only write to CUSTOM_PARTS once during init, before any reads occur.
-    CUSTOM_PARTS = custom_parts;
-
-    // Hook after the existing DefinePart call.
-    // save_overwritten = true ensures the original CALL instruction executes first,
-    // then our function runs to inject the additional parts.
-    let p = Patch::patch_call(
-        HOOK_ADDRESS,
-        inject_custom_parts as *const (),
-        6,
-        true,
-        ReturnType::None,
-    );
-    std::mem::forget(p);
+        .collect()
 }
 
 /// Reads the current city type from the city object.
 ///
-/// Returns `Some(1..=7)` on success, or `None` if the city object pointer is null
+/// Returns `Some(1..=7)` on success, or `None` if the city object cannot be read
 /// or the value is outside the expected range.
 unsafe fn read_city_type() -> Option<u32> {
-    if CATEGORY_ROOT_PTR == 0x0 {
-        return None;
-    }
-    let city_obj = *(CATEGORY_ROOT_PTR as *const *const u8);
-    if city_obj.is_null() {
-        return None;
-    }
-    let city_type = *((city_obj as usize + CITY_TYPE_OFFSET) as *const i32);
+    let city_type = City::current().and_then(|city| city.city_type()).ok()?;
     if (1..=7).contains(&city_type) {
         Some(city_type as u32)
     } else {
@@ -192,16 +213,12 @@ unsafe fn read_city_type() -> Option<u32> {
 }
 
 /// Resolves the category library node pointer using the same logic as the game:
-/// Try `[CATEGORY_ROOT_PTR]` -> `+CATEGORY_NODE_OFFSET`, fall back to `[CATEGORY_FALLBACK_PTR]`.
-unsafe fn get_category_node() -> *const u8 {
-    let root = *(CATEGORY_ROOT_PTR as *const *const u8);
-    if !root.is_null() {
-        let node = *((root as usize + CATEGORY_NODE_OFFSET) as *const *const u8);
-        if !node.is_null() {
-            return node;
-        }
+/// Try the current city's category node, fall back to `[parts_category_fallback]`.
+unsafe fn get_category_node() -> Result<*const u8, MemoryError> {
+    match City::current().and_then(|city| city.category_node()) {
+        Ok(node) if !node.is_null() => Ok(node),
+        _ => mem::read(addresses().parts_category_fallback),
     }
-    *(CATEGORY_FALLBACK_PTR as *const *const u8)
 }
 
 /// Called from the patch cave after the original DefinePart call.
@@ -221,25 +238,28 @@ unsafe extern "C" fn inject_custom_parts() {
         count: i32,
     ) -> *const u8;
 
-    let define_part: DefinePartFn = std::mem::transmute(DEFINE_PART_FN as *const ());
+    let addresses = addresses();
+    let define_part: DefinePartFn =
+        std::mem::transmute(addresses.parts_define_part_fn as *const ());
 
-    let all_part_library = *(ALL_PART_LIBRARY_PTR as *const *const u8);
-    if all_part_library.is_null() {
-        return;
-    }
-
-    let category_node = get_category_node();
-    if category_node.is_null() {
+    let libraries = mem::read::<*const u8>(addresses.parts_all_part_library)
+        .and_then(|all_part_library| Ok((all_part_library, get_category_node()?)));
+    let (all_part_library, category_node) = match libraries {
+        Ok(libraries) => libraries,
+        Err(error) => {
+            log::warn!("Unable to inject custom parts: {error}");
+            return;
+        }
+    };
+    if all_part_library.is_null() || category_node.is_null() {
         return;
     }
 
     // Read the current city type (re-read every call since the pointer may change).
     let city_type = read_city_type();
 
-    // SAFETY: CUSTOM_PARTS is only written to once during init before this callback
-    // can ever fire. After that it is effectively read-only.
-    let parts_ptr = std::ptr::addr_of!(CUSTOM_PARTS);
-    for part in (*parts_ptr).iter() {
+    let custom_parts = CUSTOM_PARTS.read().unwrap_or_else(PoisonError::into_inner);
+    for part in custom_parts.iter() {
         // If the part has a city_types filter, skip it when the current city doesn't match.
         if !part.city_types.is_empty() {
             match city_type {
@@ -0,0 +1,731 @@
+//! A small x86-64 emitter for building trampolines.
+//!
+//! Only the instructions patchy needs are supported. Jumps may refer to labels
+//! that are bound later, and to absolute addresses; both are resolved by
+//! [`Asm::finish`] once the address of the code is known.
+
+use std::ops::{Add, Sub};
+
+use super::{relative_offset, PatchError};
+
+/// A 64-bit general-purpose register.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(dead_code)]
+pub enum Reg {
+    Rax,
+    Rcx,
+    Rdx,
+    Rbx,
+    Rsp,
+    Rbp,
+    Rsi,
+    Rdi,
+    R8,
+    R9,
+    R10,
+    R11,
+    R12,
+    R13,
+    R14,
+    R15,
+}
+
+/// An SSE register.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(dead_code)]
+pub enum Xmm {
+    Xmm0,
+    Xmm1,
+    Xmm2,
+    Xmm3,
+    Xmm4,
+    Xmm5,
+    Xmm6,
+    Xmm7,
+    Xmm8,
+    Xmm9,
+    Xmm10,
+    Xmm11,
+    Xmm12,
+    Xmm13,
+    Xmm14,
+    Xmm15,
+}
+
+impl Xmm {
+    pub const ALL: [Self; 16] = [
+        Self::Xmm0,
+        Self::Xmm1,
+        Self::Xmm2,
+        Self::Xmm3,
+        Self::Xmm4,
+        Self::Xmm5,
+        Self::Xmm6,
+        Self::Xmm7,
+        Self::Xmm8,
+        Self::Xmm9,
+        Self::Xmm10,
+        Self::Xmm11,
+        Self::Xmm12,
+        Self::Xmm13,
+        Self::Xmm14,
+        Self::Xmm15,
+    ];
+}
+
+/// A memory operand, written as e.g. `Rsp + 0x20`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Mem {
+    base: Reg,
+    index: Option<(Reg, u8)>,
+    displacement: i32,
+}
+
+#[allow(dead_code)]
+impl Mem {
+    /// `[base + index * scale + displacement]`, where `scale` is 1, 2, 4 or 8.
+    pub fn indexed(base: Reg, index: Reg, scale: u8, displacement: i32) -> Self {
+        assert!(
+            matches!(scale, 1 | 2 | 4 | 8),
+            "Invalid scale {scale} for an indexed operand"
+        );
+        assert_ne!(index, Reg::Rsp, "RSP cannot be used as an index");
+
+        Self {
+            base,
+            index: Some((index, scale)),
+            displacement,
+        }
+    }
+}
+
+impl From<Reg> for Mem {
+    fn from(base: Reg) -> Self {
+        base + 0
+    }
+}
+
+impl Add<i32> for Reg {
+    type Output = Mem;
+
+    fn add(self, displacement: i32) -> Mem {
+        Mem {
+            base: self,
+            index: None,
+            displacement,
+        }
+    }
+}
+
+impl Sub<i32> for Reg {
+    type Output = Mem;
+
+    fn sub(self, displacement: i32) -> Mem {
+        self + -displacement
+    }
+}
+
+/// The condition of a conditional jump, encoded as in `Jcc`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(dead_code)]
+pub enum Condition {
+    Below = 0x2,
+    AboveOrEqual = 0x3,
+    Zero = 0x4,
+    NotZero = 0x5,
+    BelowOrEqual = 0x6,
+    Above = 0x7,
+    Less = 0xc,
+    GreaterOrEqual = 0xd,
+    LessOrEqual = 0xe,
+    Greater = 0xf,
+}
+
+/// A position in the code, bound with [`Asm::bind`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Label(usize);
+
+enum Target {
+    Label(Label),
+    Address(usize),
+}
+
+enum Fixup {
+    /// A displacement at `position`, relative to the instruction ending at `next_instruction`.
+    Relative {
+        position: usize,
+        next_instruction: usize,
+        target: Target,
+        short: bool,
+    },
+    /// The absolute address of `label`, stored at `position`.
+    Absolute { position: usize, label: Label },
+}
+
+/// Emits machine code into a buffer.
+#[derive(Default)]
+pub struct Asm {
+    code: Vec<u8>,
+    labels: Vec<Option<usize>>,
+    fixups: Vec<Fixup>,
+}
+
+// Opcode extensions of the 0x81/0x83 immediate group.
+const ADD: u8 = 0;
+const AND: u8 = 4;
+const SUB: u8 = 5;
+const CMP: u8 = 7;
+
+#[allow(dead_code)]
+impl Asm {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of bytes emitted so far.
+    pub fn len(&self) -> usize {
+        self.code.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.code.is_empty()
+    }
+
+    /// Creates a label to be bound later.
+    pub fn new_label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    /// Binds `label` to the current position.
+    pub fn bind(&mut self, label: Label) -> &mut Self {
+        let slot = &mut self.labels[label.0];
+        assert!(slot.is_none(), "Label {} is bound twice", label.0);
+        *slot = Some(self.code.len());
+        self
+    }
+
+    /// Appends already encoded instructions.
+    pub fn bytes(&mut self, bytes: &[u8]) -> &mut Self {
+        self.code.extend_from_slice(bytes);
+        self
+    }
+
+    /// Pads with INT3 until the position is a multiple of `alignment`.
+    pub fn align(&mut self, alignment: usize) -> &mut Self {
+        let aligned = self.code.len().next_multiple_of(alignment);
+        self.code.resize(aligned, 0xcc);
+        self
+    }
+
+    pub fn qword(&mut self, value: u64) -> &mut Self {
+        self.bytes(&value.to_le_bytes())
+    }
+
+    /// Stores the absolute address of `label`.
+    pub fn qword_label(&mut self, label: Label) -> &mut Self {
+        self.fixups.push(Fixup::Absolute {
+            position: self.code.len(),
+            label,
+        });
+        self.qword(0)
+    }
+
+    pub fn push(&mut self, register: Reg) -> &mut Self {
+        self.rex(false, 0, 0, register as u8, false);
+        self.bytes(&[0x50 | low(register as u8)])
+    }
+
+    pub fn pop(&mut self, register: Reg) -> &mut Self {
+        self.rex(false, 0, 0, register as u8, false);
+        self.bytes(&[0x58 | low(register as u8)])
+    }
+
+    pub fn pushfq(&mut self) -> &mut Self {
+        self.bytes(&[0x9c])
+    }
+
+    pub fn popfq(&mut self) -> &mut Self {
+        self.bytes(&[0x9d])
+    }
+
+    /// `MOV destination, source`
+    pub fn mov(&mut self, destination: Reg, source: Reg) -> &mut Self {
+        self.register_operand(None, true, &[0x89], source as u8, destination as u8)
+    }
+
+    /// `MOV destination, [source]`
+    pub fn mov_load(&mut self, destination: Reg, source: Mem) -> &mut Self {
+        self.memory_operand(None, true, &[0x8b], destination as u8, source)
+    }
+
+    /// `MOV [destination], source`
+    pub fn mov_store(&mut self, destination: Mem, source: Reg) -> &mut Self {
+        self.memory_operand(None, true, &[0x89], source as u8, destination)
+    }
+
+    /// `LEA destination, [source]`
+    pub fn lea(&mut self, destination: Reg, source: Mem) -> &mut Self {
+        self.memory_operand(None, true, &[0x8d], destination as u8, source)
+    }
+
+    /// `LEA destination, [RIP + label]`
+    pub fn lea_label(&mut self, destination: Reg, label: Label) -> &mut Self {
+        self.rex(true, destination as u8, 0, 0, false);
+        self.bytes(&[0x8d, low(destination as u8) << 3 | 0b101]);
+        self.relative(Target::Label(label), false)
+    }
+
+    pub fn add(&mut self, register: Reg, immediate: i32) -> &mut Self {
+        self.immediate_group(ADD, register, immediate)
+    }
+
+    pub fn sub(&mut self, register: Reg, immediate: i32) -> &mut Self {
+        self.immediate_group(SUB, register, immediate)
+    }
+
+    pub fn and(&mut self, register: Reg, immediate: i32) -> &mut Self {
+        self.immediate_group(AND, register, immediate)
+    }
+
+    pub fn cmp(&mut self, register: Reg, immediate: i32) -> &mut Self {
+        self.immediate_group(CMP, register, immediate)
+    }
+
+    /// `TEST first, second` on the full registers.
+    pub fn test(&mut self, first: Reg, second: Reg) -> &mut Self {
+        self.register_operand(None, true, &[0x85], second as u8, first as u8)
+    }
+
+    /// `TEST first, second` on the low bytes of the registers, e.g. `TEST AL, AL`.
+    pub fn test8(&mut self, first: Reg, second: Reg) -> &mut Self {
+        // Without a REX prefix, 4-7 select AH-BH instead of SPL-DIL.
+        let byte_registers = (first as u8) >= 4 || (second as u8) >= 4;
+        self.rex(false, second as u8, 0, first as u8, byte_registers);
+        self.bytes(&[0x84, 0xc0 | low(second as u8) << 3 | low(first as u8)])
+    }
+
+    /// `XOR first, second` on the 32-bit registers, which clears the upper halves.
+    pub fn xor32(&mut self, first: Reg, second: Reg) -> &mut Self {
+        self.register_operand(None, false, &[0x31], second as u8, first as u8)
+    }
+
+    /// `MOVDQU [destination], source`
+    pub fn movdqu_store(&mut self, destination: Mem, source: Xmm) -> &mut Self {
+        self.memory_operand(Some(0xf3), false, &[0x0f, 0x7f], source as u8, destination)
+    }
+
+    /// `MOVDQU destination, [source]`
+    pub fn movdqu_load(&mut self, destination: Xmm, source: Mem) -> &mut Self {
+        self.memory_operand(Some(0xf3), false, &[0x0f, 0x6f], destination as u8, source)
+    }
+
+    /// Calls `function` through a pointer stored in the code, so it is reachable
+    /// from anywhere.
+    pub fn call_abs(&mut self, function: usize) -> &mut Self {
+        // CALL [RIP + 2]; JMP +8 over the pointer
+        self.bytes(&[0xff, 0x15, 0x02, 0x00, 0x00, 0x00, 0xeb, 0x08]);
+        self.qword(function as u64)
+    }
+
+    /// Jumps to `destination` through a pointer stored in the code.
+    pub fn jmp_abs(&mut self, destination: usize) -> &mut Self {
+        // JMP [RIP]
+        self.bytes(&[0xff, 0x25, 0x00, 0x00, 0x00, 0x00]);
+        self.qword(destination as u64)
+    }
+
+    /// `JMP rel32` to `label`.
+    pub fn jmp(&mut self, label: Label) -> &mut Self {
+        self.bytes(&[0xe9]);
+        self.relative(Target::Label(label), false)
+    }
+
+    /// `JMP rel8` to `label`.
+    pub fn jmp_short(&mut self, label: Label) -> &mut Self {
+        self.bytes(&[0xeb]);
+        self.relative(Target::Label(label), true)
+    }
+
+    /// `JMP rel32` to an absolute address, which must be within 2 GiB of the code.
+    pub fn jmp_to(&mut self, destination: usize) -> &mut Self {
+        self.bytes(&[0xe9]);
+        self.relative(Target::Address(destination), false)
+    }
+
+    /// `Jcc rel32` to `label`.
+    pub fn jcc(&mut self, condition: Condition, label: Label) -> &mut Self {
+        self.bytes(&[0x0f, 0x80 | condition as u8]);
+        self.relative(Target::Label(label), false)
+    }
+
+    /// `Jcc rel8` to `label`.
+    pub fn jcc_short(&mut self, condition: Condition, label: Label) -> &mut Self {
+        self.bytes(&[0x70 | condition as u8]);
+        self.relative(Target::Label(label), true)
+    }
+
+    pub fn ret(&mut self) -> &mut Self {
+        self.bytes(&[0xc3])
+    }
+
+    /// Resolves every label and relative target for code placed at `address`.
+    ///
+    /// Fails with [`PatchError::RelativeJumpOutOfRange`] if an absolute target is
+    /// out of reach from `address`.
+    pub fn finish(&self, address: usize) -> Result<Vec<u8>, PatchError> {
+        let mut code = self.code.clone();
+        for fixup in &self.fixups {
+            match *fixup {
+                Fixup::Relative {
+                    position,
+                    next_instruction,
+                    ref target,
+                    short,
+                } => {
+                    let next_instruction = address
+                        .checked_add(next_instruction)
+                        .ok_or(PatchError::AddressOverflow)?;
+                    let destination = match *target {
+                        Target::Label(label) => address
+                            .checked_add(bound(&self.labels, label))
+                            .ok_or(PatchError::AddressOverflow)?,
+                        Target::Address(destination) => destination,
+                    };
+                    let displacement = relative_offset(next_instruction, destination)?;
+                    if short {
+                        code[position] = i8::try_from(displacement).map_err(|_| {
+                            PatchError::ShortJumpOutOfRange {
+                                next_instruction,
+                                destination,
+                            }
+                        })? as u8;
+                    } else {
+                        code[position..position + 4].copy_from_slice(&displacement.to_le_bytes());
+                    }
+                }
+                Fixup::Absolute { position, label } => {
+                    let destination = address
+                        .checked_add(bound(&self.labels, label))
+                        .ok_or(PatchError::AddressOverflow)?;
+                    code[position..position + 8]
+                        .copy_from_slice(&(destination as u64).to_le_bytes());
+                }
+            }
+        }
+
+        Ok(code)
+    }
+
+    fn relative(&mut self, target: Target, short: bool) -> &mut Self {
+        let position = self.code.len();
+        let size = if short { 1 } else { 4 };
+        self.fixups.push(Fixup::Relative {
+            position,
+            next_instruction: position + size,
+            target,
+            short,
+        });
+        self.code.resize(position + size, 0);
+        self
+    }
+
+    fn immediate_group(&mut self, extension: u8, register: Reg, immediate: i32) -> &mut Self {
+        match i8::try_from(immediate) {
+            Ok(immediate) => {
+                self.register_operand(None, true, &[0x83], extension, register as u8);
+                self.bytes(&[immediate as u8])
+            }
+            Err(_) => {
+                self.register_operand(None, true, &[0x81], extension, register as u8);
+                self.bytes(&immediate.to_le_bytes())
+            }
+        }
+    }
+
+    /// Emits an instruction whose ModRM operand is the register `rm`.
+    fn register_operand(
+        &mut self,
+        prefix: Option<u8>,
+        wide: bool,
+        opcode: &[u8],
+        reg: u8,
+        rm: u8,
+    ) -> &mut Self {
+        self.code.extend(prefix);
+        self.rex(wide, reg, 0, rm, false);
+        self.bytes(opcode);
+        self.bytes(&[0xc0 | low(reg) << 3 | low(rm)])
+    }
+
+    /// Emits an instruction whose ModRM operand is `memory`.
+    fn memory_operand(
+        &mut self,
+        prefix: Option<u8>,
+        wide: bool,
+        opcode: &[u8],
+        reg: u8,
+        memory: Mem,
+    ) -> &mut Self {
+        let base = memory.base as u8;
+        let index = memory.index.map_or(0, |(index, _)| index as u8);
+        self.code.extend(prefix);
+        self.rex(wide, reg, index, base, false);
+        self.bytes(opcode);
+
+        // RBP and R13 have no form without a displacement.
+        let mode = if memory.displacement == 0 && low(base) != 5 {
+            0b00
+        } else if i8::try_from(memory.displacement).is_ok() {
+            0b01
+        } else {
+            0b10
+        };
+        let needs_sib = memory.index.is_some() || low(base) == 4;
+        let rm = if needs_sib { 0b100 } else { low(base) };
+        self.bytes(&[mode << 6 | low(reg) << 3 | rm]);
+        if needs_sib {
+            let (index, scale) = memory.index.map_or((0b100, 0), |(index, scale)| {
+                (low(index as u8), scale.trailing_zeros() as u8)
+            });
+            self.bytes(&[scale << 6 | index << 3 | low(base)]);
+        }
+
+        match mode {
+            0b01 => self.bytes(&[memory.displacement as u8]),
+            0b10 => self.bytes(&memory.displacement.to_le_bytes()),
+            _ => self,
+        }
+    }
+
+    /// Emits a REX prefix if any operand needs one.
+    fn rex(&mut self, wide: bool, reg: u8, index: u8, base: u8, force: bool) {
+        let rex = 0x40
+            | u8::from(wide) << 3
+            | u8::from(reg >= 8) << 2
+            | u8::from(index >= 8) << 1
+            | u8::from(base >= 8);
+        if rex != 0x40 || force {
+            self.code.push(rex);
+        }
+    }
+}
+
+fn low(register: u8) -> u8 {
+    register & 7
+}
+
+fn bound(labels: &[Option<usize>], label: Label) -> usize {
+    labels[label.0].unwrap_or_else(|| panic!("Label {} is used but never bound", label.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Condition::*, Reg::*, Xmm::*, *};
+
+    type Case = (&'static str, fn(&mut Asm) -> &mut Asm, &'static [u8]);
+
+    #[test]
+    fn instructions_are_encoded() {
+        let cases: &[Case] = &[
+            ("push r8", |asm| asm.push(R8), &[0x41, 0x50]),
+            ("pop rcx", |asm| asm.pop(Rcx), &[0x59]),
+            ("mov rcx, rdi", |asm| asm.mov(Rcx, Rdi), &[0x48, 0x89, 0xf9]),
+            ("mov rsp, rbx", |asm| asm.mov(Rsp, Rbx), &[0x48, 0x89, 0xdc]),
+            (
+                "sub rsp, 0x30",
+                |asm| asm.sub(Rsp, 0x30),
+                &[0x48, 0x83, 0xec, 0x30],
+            ),
+            (
+                "sub rsp, 0x100",
+                |asm| asm.sub(Rsp, 0x100),
+                &[0x48, 0x81, 0xec, 0x00, 0x01, 0x00, 0x00],
+            ),
+            (
+                "and rsp, -0x10",
+                |asm| asm.and(Rsp, -0x10),
+                &[0x48, 0x83, 0xe4, 0xf0],
+            ),
+            ("cmp r9, 3", |asm| asm.cmp(R9, 3), &[0x49, 0x83, 0xf9, 0x03]),
+            ("test al, al", |asm| asm.test8(Rax, Rax), &[0x84, 0xc0]),
+            (
+                "test sil, sil",
+                |asm| asm.test8(Rsi, Rsi),
+                &[0x40, 0x84, 0xf6],
+            ),
+            (
+                "test rax, rax",
+                |asm| asm.test(Rax, Rax),
+                &[0x48, 0x85, 0xc0],
+            ),
+            ("xor eax, eax", |asm| asm.xor32(Rax, Rax), &[0x31, 0xc0]),
+            (
+                "movdqu [rsp], xmm0",
+                |asm| asm.movdqu_store(Rsp.into(), Xmm0),
+                &[0xf3, 0x0f, 0x7f, 0x04, 0x24],
+            ),
+            (
+                "movdqu xmm9, [rsp + 0x90]",
+                |asm| asm.movdqu_load(Xmm9, Rsp + 0x90),
+                &[0xf3, 0x44, 0x0f, 0x6f, 0x8c, 0x24, 0x90, 0x00, 0x00, 0x00],
+            ),
+            (
+                "lea rsp, [rsp - 8]",
+                |asm| asm.lea(Rsp, Rsp - 8),
+                &[0x48, 0x8d, 0x64, 0x24, 0xf8],
+            ),
+            (
+                "mov rax, [rbp]",
+                |asm| asm.mov_load(Rax, Rbp.into()),
+                &[0x48, 0x8b, 0x45, 0x00],
+            ),
+            (
+                "mov rax, [rcx + rax * 8]",
+                |asm| asm.mov_load(Rax, Mem::indexed(Rcx, Rax, 8, 0)),
+                &[0x48, 0x8b, 0x04, 0xc1],
+            ),
+            (
+                "mov [r12 + 0x10], r15",
+                |asm| asm.mov_store(R12 + 0x10, R15),
+                &[0x4d, 0x89, 0x7c, 0x24, 0x10],
+            ),
+        ];
+
+        for (name, emit, expected) in cases {
+            let mut asm = Asm::new();
+            emit(&mut asm);
+            assert_eq!(asm.finish(0).unwrap(), *expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn labels_are_resolved_forwards_and_backwards() {
+        let mut asm = Asm::new();
+        let start = asm.new_label();
+        let end = asm.new_label();
+        asm.bind(start)
+            .jcc_short(NotZero, end)
+            .jcc(Zero, start)
+            .jmp(end)
+            .lea_label(Rcx, end)
+            .bind(end)
+            .qword_label(start);
+
+        let address = 0x1_4000_0000;
+        let code = asm.finish(address).unwrap();
+
+        assert_eq!(
+            code,
+            [
+                0x75, 0x12, // JNZ end
+                0x0f, 0x84, 0xf8, 0xff, 0xff, 0xff, // JZ start
+                0xe9, 0x07, 0x00, 0x00, 0x00, // JMP end
+                0x48, 0x8d, 0x0d, 0x00, 0x00, 0x00, 0x00, // LEA RCX, [RIP + end]
+                0x00, 0x00, 0x00, 0x40, 0x01, 0x00, 0x00, 0x00, // start
+            ]
+        );
+    }
+
+    #[test]
+    fn unreachable_targets_are_reported() {
+        let mut asm = Asm::new();
+        asm.jmp_to(0x7_0000_0000);
+        assert!(matches!(
+            asm.finish(0x1_0000_0000),
+            Err(PatchError::RelativeJumpOutOfRange { .. })
+        ));
+
+        let mut asm = Asm::new();
+        let far = asm.new_label();
+        asm.jmp_short(far).bytes(&[0x90; 0x80]).bind(far);
+        assert!(matches!(
+            asm.finish(0),
+            Err(PatchError::ShortJumpOutOfRange { .. })
+        ));
+    }
+
+    /// The gun-blocking cave that guns.rs used to write by hand.
+    #[test]
+    fn hand_written_gun_blocking_cave_is_reproduced() {
+        let function = 0x7ff6_1234_5678usize;
+        let address = 0x1_4010_0000;
+        let resume = 0x1_4003_2f29;
+        let blocked_exit = 0x1_4003_2ef0;
+
+        let mut asm = Asm::new();
+        let blocked = asm.new_label();
+        asm.sub(Rsp, 0x30)
+            .movdqu_store(Rsp + 0x20, Xmm1)
+            .mov(Rcx, Rdi)
+            .call_abs(function)
+            .movdqu_load(Xmm1, Rsp + 0x20)
+            .add(Rsp, 0x30)
+            .test8(Rax, Rax)
+            .jcc_short(NotZero, blocked)
+            // SUBSS XMM1, XMM7; COMISS XMM6, XMM1
+            .bytes(&[0xf3, 0x0f, 0x5c, 0xcf, 0x0f, 0x2f, 0xf1])
+            .jmp_to(resume)
+            .bind(blocked)
+            .jmp_to(blocked_exit);
+        let code = asm.finish(address).unwrap();
+
+        let mut expected = vec![
+            0x48, 0x83, 0xEC, 0x30, // SUB RSP, 0x30
+            0xF3, 0x0F, 0x7F, 0x4C, 0x24, 0x20, // MOVDQU [RSP+0x20], XMM1
+            0x48, 0x89, 0xF9, // MOV RCX, RDI
+            0xFF, 0x15, 0x02, 0x00, 0x00, 0x00, 0xEB, 0x08, // CALL [RIP+2]; JMP +8
+        ];
+        expected.extend_from_slice(&function.to_le_bytes());
+        expected.extend_from_slice(&[
+            0xF3, 0x0F, 0x6F, 0x4C, 0x24, 0x20, // MOVDQU XMM1, [RSP+0x20]
+            0x48, 0x83, 0xC4, 0x30, // ADD RSP, 0x30
+            0x84, 0xC0, // TEST AL, AL
+            0x75, 0x0C, // JNZ blocked
+            0xF3, 0x0F, 0x5C, 0xCF, // SUBSS XMM1, XMM7
+            0x0F, 0x2F, 0xF1, // COMISS XMM6, XMM1
+        ]);
+        let jump_back = expected.len();
+        expected.push(0xE9);
+        expected.extend_from_slice(
+            &relative_offset(address + jump_back + 5, resume)
+                .unwrap()
+                .to_le_bytes(),
+        );
+        expected.push(0xE9);
+        expected.extend_from_slice(
+            &relative_offset(address + jump_back + 10, blocked_exit)
+                .unwrap()
+                .to_le_bytes(),
+        );
+
+        assert_eq!(code, expected);
+    }
+
+    /// The null check that flare_crash.rs used to write by hand.
+    #[test]
+    fn hand_written_flare_crash_trampoline_is_reproduced() {
+        let original = [0x48, 0x8b, 0xd8, 0x48, 0x8b, 0xce];
+        let null_exit = 0x1_4003_6ca2usize;
+        let resume = 0x1_4003_6bbbusize;
+
+        let mut asm = Asm::new();
+        let non_null = asm.new_label();
+        asm.test(Rax, Rax)
+            .jcc_short(NotZero, non_null)
+            .jmp_abs(null_exit)
+            .bind(non_null)
+            .bytes(&original)
+            .jmp_abs(resume);
+        let code = asm.finish(0x1_4010_0000).unwrap();
+
+        let mut expected = vec![0x48, 0x85, 0xc0, 0x75, 0x0e];
+        expected.extend_from_slice(&[0xff, 0x25, 0x00, 0x00, 0x00, 0x00]);
+        expected.extend_from_slice(&null_exit.to_le_bytes());
+        expected.extend_from_slice(&original);
+        expected.extend_from_slice(&[0xff, 0x25, 0x00, 0x00, 0x00, 0x00]);
+        expected.extend_from_slice(&resume.to_le_bytes());
+
+        assert_eq!(code, expected);
+    }
+}
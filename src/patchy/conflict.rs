@@ -0,0 +1,174 @@
+//! Recognising patches that other mods placed at our patch sites.
+//!
+//! Mods loaded by the Modloader patch the game the same way this one does, most
+//! often with a jump into their own code or by replacing instructions with NOPs.
+//! Before a site is patched its current bytes are checked for either, so a site
+//! that another mod already patched is reported instead of being overwritten.
+
+use std::{fmt, slice};
+
+use super::memory::{Memory, Native};
+
+/// Bytes read to recognise a jump; the longest is `MOV RAX, imm64; JMP RAX`.
+const JUMP_WINDOW: usize = 12;
+const NOP: u8 = 0x90;
+
+/// Code that another mod placed at a patch site.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ForeignCode {
+    /// A jump out of the game executable, into `module` if the destination
+    /// belongs to one.
+    Jump {
+        destination: usize,
+        module: Option<String>,
+    },
+    /// NOPs covering the whole site.
+    NopSled,
+}
+
+impl fmt::Display for ForeignCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Jump {
+                destination,
+                module: Some(module),
+            } => write!(f, "a jump to {destination:#x} in {module}"),
+            Self::Jump {
+                destination,
+                module: None,
+            } => write!(f, "a jump to {destination:#x}"),
+            Self::NopSled => write!(f, "NOPs"),
+        }
+    }
+}
+
+/// A code range patched by this mod, as published to other mods.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct PatchRange {
+    pub address: usize,
+    pub size: usize,
+    /// False while the patch is disabled and the original bytes are in place.
+    pub enabled: bool,
+}
+
+/// Returns the code another mod placed over the `size` bytes at `address`, if any.
+///
+/// A jump only counts when it leaves the game executable, as the game's own
+/// code jumps within it.
+///
+/// # Safety
+/// The `size` bytes at `address` must be readable, and at least [`JUMP_WINDOW`]
+/// bytes if the site starts like a jump.
+pub(crate) unsafe fn foreign_code(address: usize, size: usize) -> Option<ForeignCode> {
+    let site = slice::from_raw_parts(address as *const u8, size);
+    if is_nop_sled(site) {
+        return Some(ForeignCode::NopSled);
+    }
+
+    // A jump written by another mod may extend past the end of the site.
+    let code = match site.first() {
+        Some(0xe9 | 0xff | 0x48) => {
+            slice::from_raw_parts(address as *const u8, size.max(JUMP_WINDOW))
+        }
+        _ => site,
+    };
+    let destination = jump_destination(address, code, |slot| {
+        Some((slot as *const usize).read_unaligned())
+    })?;
+
+    let module = Native::module_containing(destination);
+    let game = Native::main_module().ok();
+    if module
+        .as_ref()
+        .is_some_and(|module| Some(module.base) == game)
+    {
+        return None;
+    }
+    Some(ForeignCode::Jump {
+        destination,
+        module: module.and_then(|module| module.name),
+    })
+}
+
+/// Returns true if `site` is made only of NOPs.
+fn is_nop_sled(site: &[u8]) -> bool {
+    !site.is_empty() && site.iter().all(|&byte| byte == NOP)
+}
+
+/// Decodes the jump at the start of `code`, which was read from `address`, and
+/// returns its destination.
+///
+/// `read_pointer` reads the destination of an indirect jump from its slot.
+fn jump_destination(
+    address: usize,
+    code: &[u8],
+    read_pointer: impl FnOnce(usize) -> Option<usize>,
+) -> Option<usize> {
+    match code {
+        // JMP rel32
+        [0xe9, a, b, c, d, ..] => {
+            let displacement = i32::from_le_bytes([*a, *b, *c, *d]) as isize;
+            address.checked_add(5)?.checked_add_signed(displacement)
+        }
+        // JMP [RIP + disp32]
+        [0xff, 0x25, a, b, c, d, ..] => {
+            let displacement = i32::from_le_bytes([*a, *b, *c, *d]) as isize;
+            read_pointer(address.checked_add(6)?.checked_add_signed(displacement)?)
+        }
+        // MOV RAX, imm64; JMP RAX
+        [0x48, 0xb8, rest @ ..] if rest.get(8..10) == Some(&[0xff, 0xe0]) => {
+            Some(usize::from_le_bytes(rest[..8].try_into().ok()?))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SITE: usize = 0x1400_1000;
+
+    #[test]
+    fn jumps_are_followed_to_their_destination() {
+        let no_pointer = |_| None;
+
+        // JMP 0x14000f00 (backwards)
+        let relative = [0xe9, 0xfb, 0xfe, 0xff, 0xff, 0x90];
+        assert_eq!(
+            jump_destination(SITE, &relative, no_pointer),
+            Some(0x1400_0f00)
+        );
+
+        // JMP [RIP + 0x10]
+        let indirect = [0xff, 0x25, 0x10, 0x00, 0x00, 0x00];
+        let destination = jump_destination(SITE, &indirect, |slot| {
+            assert_eq!(slot, SITE + 6 + 0x10);
+            Some(0x7ff8_1234_5678)
+        });
+        assert_eq!(destination, Some(0x7ff8_1234_5678));
+
+        // MOV RAX, 0x7ff812345678; JMP RAX; INT3
+        let absolute = [
+            0x48, 0xb8, 0x78, 0x56, 0x34, 0x12, 0xf8, 0x7f, 0x00, 0x00, 0xff, 0xe0, 0xcc,
+        ];
+        assert_eq!(
+            jump_destination(SITE, &absolute, no_pointer),
+            Some(0x7ff8_1234_5678)
+        );
+
+        // MOV RBX, RAX; MOV RCX, RSI
+        let original = [0x48, 0x8b, 0xd8, 0x48, 0x8b, 0xce];
+        assert_eq!(jump_destination(SITE, &original, no_pointer), None);
+        // MOV RAX, imm64 without the jump
+        assert_eq!(jump_destination(SITE, &absolute[..10], no_pointer), None);
+    }
+
+    #[test]
+    fn only_whole_sites_of_nops_are_sleds() {
+        assert!(is_nop_sled(&[NOP; 6]));
+        assert!(!is_nop_sled(&[NOP, NOP, 0x0f, 0x2f, 0xf1]));
+        assert!(!is_nop_sled(&[]));
+    }
+}
@@ -0,0 +1,513 @@
+//! Hooks that hand the complete register state to a Rust callback.
+
+use std::mem::{offset_of, size_of};
+
+use core::slice;
+
+use super::{
+    asm::{
+        Asm, Condition, Mem,
+        Reg::{self, *},
+        Xmm,
+    },
+    check_expected_bytes, check_instruction_boundary, check_jump_room, relocate, Patch, PatchError,
+};
+
+/// Registers at a context hook, laid out the way the trampoline saves them.
+///
+/// Changes made by the callback are written back before the hooked code resumes,
+/// except for `rsp`, which always holds the stack pointer at the hook.
+#[repr(C)]
+#[derive(Debug, Clone, Default)]
+pub struct Context {
+    pub xmm: [[u8; 16]; 16],
+    pub rax: u64,
+    pub rbx: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub rbp: u64,
+    pub rsp: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    pub rflags: u64,
+}
+
+#[allow(dead_code)]
+impl Context {
+    /// Returns the lowest single-precision lane of `xmm[index]`.
+    pub fn xmm_f32(&self, index: usize) -> f32 {
+        f32::from_le_bytes(self.xmm[index][..4].try_into().expect("lane is four bytes"))
+    }
+
+    /// Replaces the lowest single-precision lane of `xmm[index]`.
+    pub fn set_xmm_f32(&mut self, index: usize, value: f32) {
+        self.xmm[index][..4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// Returns the lowest double-precision lane of `xmm[index]`.
+    pub fn xmm_f64(&self, index: usize) -> f64 {
+        f64::from_le_bytes(
+            self.xmm[index][..8]
+                .try_into()
+                .expect("lane is eight bytes"),
+        )
+    }
+
+    /// Replaces the lowest double-precision lane of `xmm[index]`.
+    pub fn set_xmm_f64(&mut self, index: usize, value: f64) {
+        self.xmm[index][..8].copy_from_slice(&value.to_le_bytes());
+    }
+}
+
+const XMM_SIZE: usize = size_of::<[[u8; 16]; 16]>();
+
+/// Pushed so that the last register lands on the first field after [`Context::xmm`].
+const PUSHED_REGISTERS: [Reg; 16] = [
+    R15, R14, R13, R12, R11, R10, R9, R8, Rsp, Rbp, Rdi, Rsi, Rdx, Rcx, Rbx, Rax,
+];
+
+impl Patch {
+    /// Creates a patch at `address` that passes every register to `callback`.
+    ///
+    /// The general-purpose registers, the flags and XMM0–XMM15 are saved into a
+    /// [`Context`]; whatever the callback leaves there is loaded back before the
+    /// code after the patch resumes. `expected`, `size` and `save_overwritten`
+    /// work as in [`Patch::patch_call`].
+    ///
+    /// # Safety
+    /// The callback must leave the registers in a state the hooked code can continue with.
+    pub unsafe fn hook_with_context(
+        address: usize,
+        expected: Option<&str>,
+        size: usize,
+        save_overwritten: bool,
+        callback: extern "C" fn(&mut Context),
+    ) -> Result<Self, PatchError> {
+        let callback = callback as usize;
+        Self::prepare_hook(address, expected, size, save_overwritten, move |asm| {
+            context_body(asm, callback)
+        })
+    }
+
+    /// Creates a patch at `address` whose `callback` picks where execution continues.
+    ///
+    /// The callback receives the registers as in [`Patch::hook_with_context`] and
+    /// returns [`Exit::CONTINUE`] to resume after the overwritten bytes, replaying
+    /// them first with `save_overwritten`, or [`Exit::named`] to jump to the exit
+    /// registered under that name in `exits`. The registers are restored either way.
+    ///
+    /// # Safety
+    /// Every exit must be able to continue with the registers at the hook.
+    pub unsafe fn hook_with_exits(
+        address: usize,
+        expected: Option<&str>,
+        size: usize,
+        save_overwritten: bool,
+        exits: &[(&'static str, usize)],
+        callback: fn(&mut Context) -> Exit,
+    ) -> Result<Self, PatchError> {
+        for (index, (name, exit)) in exits.iter().enumerate() {
+            if exits[..index].iter().any(|(other, _)| other == name) {
+                return Err(PatchError::DuplicateExit { address, name });
+            }
+            log::debug!("Hook at {address:#x} may exit through `{name}` to {exit:#x}");
+        }
+
+        Self::prepare_exit_hook(address, expected, size, save_overwritten, exits, callback)
+    }
+
+    unsafe fn prepare_exit_hook(
+        address: usize,
+        expected: Option<&str>,
+        size: usize,
+        save_overwritten: bool,
+        exits: &[(&'static str, usize)],
+        callback: fn(&mut Context) -> Exit,
+    ) -> Result<Self, PatchError> {
+        check_jump_room(address, size)?;
+        check_expected_bytes(address, expected, size)?;
+        check_instruction_boundary(address, size)?;
+
+        let overwritten = slice::from_raw_parts(address as *const u8, size).to_vec();
+        let source = overwritten.clone();
+        // The trampoline refers to the hook for as long as the game runs.
+        let hook: &'static ExitHook = Box::leak(Box::new(ExitHook {
+            address,
+            callback,
+            names: exits.iter().map(|(name, _)| *name).collect(),
+        }));
+        let exits = exits.iter().map(|(_, exit)| *exit).collect::<Vec<_>>();
+        let build = move |trampoline_address| {
+            exit_trampoline(
+                hook as *const ExitHook as usize,
+                address,
+                &source,
+                save_overwritten,
+                &exits,
+                trampoline_address,
+            )
+        };
+        // Only the displacements depend on where the trampoline ends up.
+        let trampoline_size = build(address)?.len();
+
+        Self::prepare_detour(address, overwritten, trampoline_size, build)
+    }
+}
+
+/// The exit taken by a [`Patch::hook_with_exits`] callback.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Exit(Option<&'static str>);
+
+impl Exit {
+    /// Resumes after the overwritten bytes.
+    pub const CONTINUE: Self = Self(None);
+
+    /// Jumps to the exit registered as `name`.
+    pub const fn named(name: &'static str) -> Self {
+        Self(Some(name))
+    }
+}
+
+/// The callback of a [`Patch::hook_with_exits`] trampoline and the names of its exits.
+struct ExitHook {
+    address: usize,
+    callback: fn(&mut Context) -> Exit,
+    names: Vec<&'static str>,
+}
+
+/// Runs the callback of `hook` and returns the position of the chosen exit in the
+/// trampoline's table, where 0 is the continuation.
+extern "C" fn dispatch_exit(context: &mut Context, hook: &ExitHook) -> usize {
+    let Exit(Some(name)) = (hook.callback)(context) else {
+        return 0;
+    };
+    match hook.names.iter().position(|exit| *exit == name) {
+        Some(index) => index + 1,
+        None => {
+            log::error!(
+                "Hook at {:#x} chose the unregistered exit `{name}`; continuing",
+                hook.address
+            );
+            0
+        }
+    }
+}
+
+/// Emits the code that saves the registers, calls `callback` with them and
+/// loads them back.
+fn context_body(asm: &mut Asm, callback: usize) {
+    save_context(asm, 0);
+    call_with_context(asm, callback);
+    restore_context(asm);
+}
+
+/// Saves the registers into a [`Context`] at RSP. `pushed` is the number of bytes
+/// the trampoline pushed before, so the context records the RSP of the hook.
+fn save_context(asm: &mut Asm, pushed: i32) {
+    asm.pushfq();
+    for register in PUSHED_REGISTERS {
+        asm.push(register);
+    }
+    asm.sub(Rsp, XMM_SIZE as i32);
+    for (slot, register) in (0..).zip(Xmm::ALL) {
+        asm.movdqu_store(Rsp + slot * 16, register);
+    }
+
+    // The pushed RSP is already lower than at the hook; store the original instead.
+    asm.lea(Rax, Rsp + (size_of::<Context>() as i32 + pushed))
+        .mov_store(Rsp + offset_of!(Context, rsp) as i32, Rax);
+}
+
+/// Calls `callback` with the context saved by [`save_context`] as its first
+/// argument, leaving its address in RBX and the return value in RAX.
+fn call_with_context(asm: &mut Asm, callback: usize) {
+    // The context goes in RCX for Windows and in RDI for System V. RBX is
+    // non-volatile in both, so it keeps the context across the call.
+    asm.mov(Rcx, Rsp)
+        .mov(Rdi, Rsp)
+        .mov(Rbx, Rsp)
+        // Align the stack and reserve the shadow space.
+        .and(Rsp, -0x10)
+        .sub(Rsp, 0x20)
+        .call_abs(callback);
+}
+
+/// Loads the registers back from the context at RBX and releases it.
+fn restore_context(asm: &mut Asm) {
+    asm.mov(Rsp, Rbx);
+    for (slot, register) in (0..).zip(Xmm::ALL) {
+        asm.movdqu_load(register, Rsp + slot * 16);
+    }
+    asm.add(Rsp, XMM_SIZE as i32);
+    for register in PUSHED_REGISTERS.into_iter().rev() {
+        if register == Rsp {
+            asm.add(Rsp, 0x8);
+        } else {
+            asm.pop(register);
+        }
+    }
+    asm.popfq();
+}
+
+/// Builds the trampoline of [`Patch::hook_with_exits`] for `trampoline_address`.
+///
+/// [`dispatch_exit`] runs the callback of the [`ExitHook`] at `hook` and picks an
+/// entry of the exit table. A slot reserved above the context receives the address
+/// of that exit, which the final `RET` jumps to once every register is restored.
+/// Exit 0 is the continuation, which replays the overwritten instructions if
+/// requested and jumps back behind them.
+fn exit_trampoline(
+    hook: usize,
+    source: usize,
+    overwritten: &[u8],
+    save_overwritten: bool,
+    exits: &[usize],
+    trampoline_address: usize,
+) -> Result<Vec<u8>, PatchError> {
+    let mut asm = Asm::new();
+    let valid = asm.new_label();
+    let continuation = asm.new_label();
+    let table = asm.new_label();
+    let hook_slot = asm.new_label();
+
+    asm.lea(Rsp, Rsp - 0x8);
+    save_context(&mut asm, 8);
+    // The hook is the second argument: RDX for Windows and RSI for System V.
+    asm.lea_label(Rdx, hook_slot)
+        .mov_load(Rdx, Rdx + 0)
+        .mov(Rsi, Rdx);
+    call_with_context(&mut asm, dispatch_exit as *const () as usize);
+    // Unknown exits continue like exit 0.
+    asm.cmp(Rax, exits.len() as i32 + 1)
+        .jcc_short(Condition::Below, valid)
+        .xor32(Rax, Rax)
+        .bind(valid)
+        .lea_label(Rcx, table)
+        .mov_load(Rax, Mem::indexed(Rcx, Rax, 8, 0))
+        .mov_store(Rbx + size_of::<Context>() as i32, Rax);
+    restore_context(&mut asm);
+    asm.ret().bind(continuation);
+
+    if save_overwritten {
+        let replayed = trampoline_address
+            .checked_add(asm.len())
+            .ok_or(PatchError::AddressOverflow)?;
+        asm.bytes(&relocate::relocate(overwritten, source, replayed)?);
+    }
+    let resume = source
+        .checked_add(overwritten.len())
+        .ok_or(PatchError::AddressOverflow)?;
+    asm.jmp_to(resume)
+        .align(8)
+        .bind(table)
+        .qword_label(continuation);
+    for exit in exits {
+        asm.qword(*exit as u64);
+    }
+    asm.bind(hook_slot).qword(hook as u64);
+
+    asm.finish(trampoline_address)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::patchy::memory::{Memory, Native};
+
+    static SEEN_RAX: AtomicU64 = AtomicU64::new(0);
+    static SEEN_RFLAGS: AtomicU64 = AtomicU64::new(0);
+    static CHOSEN_EXIT: AtomicUsize = AtomicUsize::new(0);
+
+    extern "C" fn double_rax(context: &mut Context) {
+        SEEN_RAX.store(context.rax, Ordering::Relaxed);
+        SEEN_RFLAGS.store(context.rflags, Ordering::Relaxed);
+        context.rax *= 2;
+        context.set_xmm_f32(0, 1.5);
+    }
+
+    const CHOICES: [Exit; 3] = [
+        Exit::CONTINUE,
+        Exit::named("seven"),
+        Exit::named("unregistered"),
+    ];
+
+    fn choose_exit(context: &mut Context) -> Exit {
+        context.rax = 41;
+        CHOICES[CHOSEN_EXIT.load(Ordering::Relaxed)]
+    }
+
+    #[test]
+    fn context_matches_the_pushed_registers() {
+        // PUSHFQ; PUSH R15 .. PUSH R8; PUSH RSP; PUSH RBP, RDI, RSI, RDX, RCX, RBX, RAX
+        const PUSH_REGISTERS: [u8; 25] = [
+            0x9c, 0x41, 0x57, 0x41, 0x56, 0x41, 0x55, 0x41, 0x54, 0x41, 0x53, 0x41, 0x52, 0x41,
+            0x51, 0x41, 0x50, 0x54, 0x55, 0x57, 0x56, 0x52, 0x51, 0x53, 0x50,
+        ];
+        let mut asm = Asm::new();
+        save_context(&mut asm, 0);
+        let code = asm.finish(0).unwrap();
+
+        assert_eq!(code[..PUSH_REGISTERS.len()], PUSH_REGISTERS);
+        // Every push lands on its own field, followed by RSP-relative XMM slots.
+        assert_eq!(offset_of!(Context, rax), XMM_SIZE);
+        assert_eq!(offset_of!(Context, rsp), XMM_SIZE + 7 * 8);
+        assert_eq!(offset_of!(Context, rflags), size_of::<Context>() - 8);
+        assert_eq!(
+            size_of::<Context>(),
+            XMM_SIZE + (PUSHED_REGISTERS.len() + 1) * 8
+        );
+    }
+
+    #[test]
+    fn callbacks_see_and_change_the_registers() {
+        // STC; MOV EAX, 21; the trampoline body; RET
+        let mut asm = Asm::new();
+        asm.bytes(&[0xf9, 0xb8, 0x15, 0x00, 0x00, 0x00]);
+        context_body(&mut asm, double_rax as *const () as usize);
+        let code = asm.ret().finish(0).unwrap();
+
+        unsafe {
+            let memory = Native::allocate_code(code.len()).expect("Failed to allocate test memory");
+            std::ptr::copy_nonoverlapping(code.as_ptr(), memory as *mut u8, code.len());
+
+            let returns_rax: extern "C" fn() -> u64 = std::mem::transmute(memory);
+            assert_eq!(returns_rax(), 42);
+            assert_eq!(SEEN_RAX.load(Ordering::Relaxed), 21);
+            // The carry flag set before the hook.
+            assert_eq!(SEEN_RFLAGS.load(Ordering::Relaxed) & 1, 1);
+
+            let returns_xmm0: extern "C" fn() -> f32 = std::mem::transmute(memory);
+            assert_eq!(returns_xmm0(), 1.5);
+
+            Native::free_code(memory, code.len());
+        }
+    }
+
+    #[test]
+    fn exit_trampolines_dispatch_through_a_table() {
+        let source = 0x140001000;
+        // MOV RBX, RAX; MOV RCX, RSI
+        let overwritten = [0x48, 0x8b, 0xd8, 0x48, 0x8b, 0xce];
+        let exits = [0x140002000, 0x140003000];
+        let trampoline_address = source + 0x10000;
+
+        let hook = 0x1122334455667788;
+        let code =
+            exit_trampoline(hook, source, &overwritten, true, &exits, trampoline_address).unwrap();
+
+        // LEA RSP, [RSP - 0x8] reserves the slot for the exit address.
+        assert_eq!(code[..5], [0x48, 0x8d, 0x64, 0x24, 0xf8]);
+        assert_eq!(code.len() % 8, 0);
+        let table = code.len() - 32;
+        let entry = |index: usize| {
+            usize::from_le_bytes(
+                code[table + index * 8..table + index * 8 + 8]
+                    .try_into()
+                    .unwrap(),
+            )
+        };
+        assert_eq!(entry(1), exits[0]);
+        assert_eq!(entry(2), exits[1]);
+        // The hook follows the table.
+        assert_eq!(entry(3), hook);
+
+        // The continuation follows the RET, replays the overwritten bytes and jumps back.
+        let continuation = entry(0) - trampoline_address;
+        assert_eq!(code[continuation - 1], 0xc3);
+        assert_eq!(code[continuation..continuation + 6], overwritten);
+        assert_eq!(code[continuation + 6], 0xe9);
+        let displacement = i32::from_le_bytes(
+            code[continuation + 7..continuation + 11]
+                .try_into()
+                .unwrap(),
+        );
+        assert_eq!(
+            (trampoline_address + continuation + 11).wrapping_add_signed(displacement as isize),
+            source + overwritten.len()
+        );
+
+        // CMP RAX, 3 bounds the index before LEA RCX, [RIP + table] reads the table.
+        let compare = code
+            .windows(3)
+            .position(|bytes| bytes == [0x48, 0x83, 0xf8])
+            .unwrap();
+        assert_eq!(code[compare + 3], 3);
+        let lea = compare + 8;
+        assert_eq!(code[lea..lea + 3], [0x48, 0x8d, 0x0d]);
+        let displacement = i32::from_le_bytes(code[lea + 3..lea + 7].try_into().unwrap());
+        assert_eq!(lea + 7 + displacement as usize, table);
+    }
+
+    #[test]
+    fn callbacks_choose_the_exit() {
+        unsafe {
+            let base = Native::allocate_code(4096).expect("Failed to allocate test memory");
+
+            // The hooked code: ADD EAX, 1; NOP; NOP; RET
+            let source = base + 0x900;
+            let hooked = [0x83, 0xc0, 0x01, 0x90, 0x90, 0xc3];
+            std::ptr::copy_nonoverlapping(hooked.as_ptr(), source as *mut u8, hooked.len());
+            // The exit: MOV EAX, 7; RET
+            let exit = base + 0x800;
+            let stub = [0xb8, 0x07, 0x00, 0x00, 0x00, 0xc3];
+            std::ptr::copy_nonoverlapping(stub.as_ptr(), exit as *mut u8, stub.len());
+
+            let hook = ExitHook {
+                address: source,
+                callback: choose_exit,
+                names: vec!["seven"],
+            };
+            let code = exit_trampoline(
+                &hook as *const ExitHook as usize,
+                source,
+                &hooked[..5],
+                true,
+                &[exit],
+                base,
+            )
+            .unwrap();
+            std::ptr::copy_nonoverlapping(code.as_ptr(), base as *mut u8, code.len());
+            let run: extern "C" fn() -> u64 = std::mem::transmute(base);
+
+            // Exits that were not registered continue like `Exit::CONTINUE`.
+            for (choice, result) in [(0, 42), (1, 7), (2, 42)] {
+                CHOSEN_EXIT.store(choice, Ordering::Relaxed);
+                assert_eq!(run(), result);
+            }
+
+            Native::free_code(base, 4096);
+        }
+    }
+
+    #[test]
+    fn exits_must_have_distinct_names() {
+        let result = unsafe {
+            Patch::hook_with_exits(
+                0x140001000,
+                Some("90 90 90 90 90"),
+                5,
+                true,
+                &[("blocked", 0x140002000), ("blocked", 0x140003000)],
+                choose_exit,
+            )
+        };
+
+        assert!(matches!(
+            result,
+            Err(PatchError::DuplicateExit {
+                address: 0x140001000,
+                name: "blocked"
+            })
+        ));
+    }
+}
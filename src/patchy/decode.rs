@@ -0,0 +1,580 @@
+//! Length decoding for x86-64 instructions.
+//!
+//! Patches replace whole instructions, so the number of bytes a patch overwrites
+//! must end on an instruction boundary. The decoder only determines where each
+//! instruction ends; it does not validate operands or privilege levels.
+
+/// The architectural limit on the length of a single instruction.
+pub(crate) const MAX_INSTRUCTION_LENGTH: usize = 15;
+
+/// The operand that follows the opcode and ModRM bytes of an instruction.
+#[derive(Clone, Copy)]
+enum Operand {
+    None,
+    Imm8,
+    Imm16,
+    /// A 16-bit or 32-bit immediate, depending on the operand size.
+    ImmZ,
+    /// A 16-bit, 32-bit or 64-bit immediate, depending on the operand size.
+    ImmV,
+    /// The 16-bit and 8-bit immediates of `ENTER`.
+    Enter,
+    /// An absolute memory offset, sized by the address size.
+    Moffs,
+    Rel8,
+    Rel32,
+}
+
+/// How an opcode is encoded after its prefixes.
+#[derive(Clone, Copy)]
+struct Encoding {
+    modrm: bool,
+    operand: Operand,
+}
+
+const fn plain(operand: Operand) -> Option<Encoding> {
+    Some(Encoding {
+        modrm: false,
+        operand,
+    })
+}
+
+const fn modrm(operand: Operand) -> Option<Encoding> {
+    Some(Encoding {
+        modrm: true,
+        operand,
+    })
+}
+
+/// A decoded instruction.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) struct Instruction {
+    pub(crate) length: usize,
+    /// Offset of the 32-bit displacement of a RIP-relative memory operand.
+    pub(crate) rip_displacement: Option<usize>,
+    /// The relative branch performed by the instruction, if any.
+    pub(crate) branch: Option<Branch>,
+}
+
+/// A branch whose displacement is stored at the end of the instruction.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Branch {
+    /// `JMP rel8` or `Jcc rel8`.
+    Short,
+    /// `LOOP`, `LOOPcc` and `JRCXZ`, which have no 32-bit form.
+    ShortOnly,
+    /// `JMP rel32`, `CALL rel32` or `Jcc rel32`.
+    Near,
+}
+
+#[derive(Default)]
+struct Prefixes {
+    operand_size: bool,
+    address_size: bool,
+    repeat: bool,
+    rex_w: bool,
+    rex: bool,
+}
+
+/// Returns the length of the instruction at the start of `code`.
+///
+/// Returns `None` if the instruction is truncated, invalid in 64-bit mode, or
+/// uses an encoding the decoder does not understand (such as EVEX).
+pub(crate) fn instruction_length(code: &[u8]) -> Option<usize> {
+    decode(code).map(|instruction| instruction.length)
+}
+
+/// Decodes the instruction at the start of `code`.
+///
+/// Returns `None` under the same conditions as [`instruction_length`].
+pub(crate) fn decode(code: &[u8]) -> Option<Instruction> {
+    let mut prefixes = Prefixes::default();
+    let mut offset = 0;
+
+    let opcode = loop {
+        let byte = *code.get(offset)?;
+        offset += 1;
+        if (0x40..=0x4f).contains(&byte) {
+            prefixes.rex = true;
+            prefixes.rex_w = byte & 0x08 != 0;
+            continue;
+        }
+        if !is_legacy_prefix(byte) {
+            break byte;
+        }
+
+        // A REX prefix only applies when it immediately precedes the opcode.
+        prefixes.rex = false;
+        prefixes.rex_w = false;
+        match byte {
+            0x66 => prefixes.operand_size = true,
+            0x67 => prefixes.address_size = true,
+            0xf2 | 0xf3 => prefixes.repeat = true,
+            _ => {}
+        }
+    };
+
+    let encoding = match opcode {
+        0x0f => {
+            let second = *code.get(offset)?;
+            offset += 1;
+            match second {
+                0x38 => {
+                    offset += 1;
+                    modrm(Operand::None)
+                }
+                0x3a => {
+                    offset += 1;
+                    modrm(Operand::Imm8)
+                }
+                _ => two_byte(second),
+            }
+        }
+        0xc4 | 0xc5 => {
+            if prefixes.operand_size || prefixes.repeat || prefixes.rex {
+                return None;
+            }
+            let (map, vex_size) = if opcode == 0xc5 {
+                (1, 1)
+            } else {
+                (*code.get(offset)? & 0x1f, 2)
+            };
+            offset += vex_size;
+            let vex_opcode = *code.get(offset)?;
+            offset += 1;
+            vex(map, vex_opcode)
+        }
+        _ => one_byte(opcode),
+    }?;
+
+    let mut rip_displacement = None;
+    if encoding.modrm {
+        let modrm = *code.get(offset)?;
+        offset += 1;
+        if modrm & 0xc7 == 0x05 {
+            rip_displacement = Some(offset);
+        }
+        offset += modrm_extension(code, offset, modrm)?;
+
+        // TEST is the only form of its group with an immediate.
+        if matches!(opcode, 0xf6 | 0xf7) && (modrm >> 3) & 0x07 < 2 {
+            offset += if opcode == 0xf6 {
+                1
+            } else {
+                immz_size(&prefixes)
+            };
+        }
+    }
+
+    offset += match encoding.operand {
+        Operand::None => 0,
+        Operand::Imm8 | Operand::Rel8 => 1,
+        Operand::Imm16 => 2,
+        Operand::Enter => 3,
+        Operand::ImmZ => immz_size(&prefixes),
+        Operand::ImmV if prefixes.rex_w => 8,
+        Operand::ImmV => immz_size(&prefixes),
+        Operand::Moffs if prefixes.address_size => 4,
+        Operand::Moffs => 8,
+        Operand::Rel32 => 4,
+    };
+
+    let branch = match encoding.operand {
+        Operand::Rel8 if matches!(opcode, 0xe0..=0xe3) => Some(Branch::ShortOnly),
+        Operand::Rel8 => Some(Branch::Short),
+        Operand::Rel32 => Some(Branch::Near),
+        _ => None,
+    };
+
+    (offset <= MAX_INSTRUCTION_LENGTH && offset <= code.len()).then_some(Instruction {
+        length: offset,
+        rip_displacement,
+        branch,
+    })
+}
+
+/// Returns the length of the whole instructions at the start of `code` that
+/// span at least `minimum` bytes.
+///
+/// On failure, returns the offset of the first instruction that could not be decoded.
+pub(crate) fn covering_length(code: &[u8], minimum: usize) -> Result<usize, usize> {
+    let mut offset = 0;
+    while offset < minimum {
+        offset += code
+            .get(offset..)
+            .and_then(instruction_length)
+            .ok_or(offset)?;
+    }
+
+    Ok(offset)
+}
+
+fn is_legacy_prefix(byte: u8) -> bool {
+    matches!(
+        byte,
+        0x26 | 0x2e | 0x36 | 0x3e | 0x64 | 0x65 | 0x66 | 0x67 | 0xf0 | 0xf2 | 0xf3
+    )
+}
+
+fn immz_size(prefixes: &Prefixes) -> usize {
+    if prefixes.operand_size && !prefixes.rex_w {
+        2
+    } else {
+        4
+    }
+}
+
+/// Returns the number of SIB and displacement bytes following `modrm`.
+fn modrm_extension(code: &[u8], offset: usize, modrm: u8) -> Option<usize> {
+    let mode = modrm >> 6;
+    let rm = modrm & 0x07;
+    if mode == 3 {
+        return Some(0);
+    }
+
+    let mut size = 0;
+    let mut base = rm;
+    if rm == 4 {
+        base = *code.get(offset)? & 0x07;
+        size += 1;
+    }
+
+    size += match mode {
+        0 if rm == 5 || base == 5 => 4,
+        0 => 0,
+        1 => 1,
+        _ => 4,
+    };
+    Some(size)
+}
+
+fn one_byte(opcode: u8) -> Option<Encoding> {
+    match opcode {
+        0x00..=0x3f => match opcode & 0x07 {
+            0..=3 => modrm(Operand::None),
+            4 => plain(Operand::Imm8),
+            5 => plain(Operand::ImmZ),
+            // PUSH/POP of segment registers, DAA and friends are invalid in 64-bit mode.
+            _ => None,
+        },
+        0x50..=0x5f => plain(Operand::None),
+        0x63 => modrm(Operand::None),
+        0x68 => plain(Operand::ImmZ),
+        0x69 => modrm(Operand::ImmZ),
+        0x6a => plain(Operand::Imm8),
+        0x6b => modrm(Operand::Imm8),
+        0x6c..=0x6f => plain(Operand::None),
+        0x70..=0x7f => plain(Operand::Rel8),
+        0x80 | 0x83 => modrm(Operand::Imm8),
+        0x81 => modrm(Operand::ImmZ),
+        0x84..=0x8f => modrm(Operand::None),
+        0x90..=0x99 | 0x9b..=0x9f => plain(Operand::None),
+        0xa0..=0xa3 => plain(Operand::Moffs),
+        0xa4..=0xa7 | 0xaa..=0xaf => plain(Operand::None),
+        0xa8 => plain(Operand::Imm8),
+        0xa9 => plain(Operand::ImmZ),
+        0xb0..=0xb7 => plain(Operand::Imm8),
+        0xb8..=0xbf => plain(Operand::ImmV),
+        0xc0 | 0xc1 | 0xc6 => modrm(Operand::Imm8),
+        0xc2 | 0xca => plain(Operand::Imm16),
+        0xc3 | 0xc9 | 0xcb | 0xcc | 0xcf => plain(Operand::None),
+        0xc7 => modrm(Operand::ImmZ),
+        0xc8 => plain(Operand::Enter),
+        0xcd => plain(Operand::Imm8),
+        0xd0..=0xd3 | 0xd8..=0xdf => modrm(Operand::None),
+        0xd7 => plain(Operand::None),
+        0xe0..=0xe3 | 0xeb => plain(Operand::Rel8),
+        0xe4..=0xe7 => plain(Operand::Imm8),
+        0xe8 | 0xe9 => plain(Operand::Rel32),
+        0xec..=0xef | 0xf1 | 0xf4 | 0xf5 | 0xf8..=0xfd => plain(Operand::None),
+        0xf6 | 0xf7 | 0xfe | 0xff => modrm(Operand::None),
+        _ => None,
+    }
+}
+
+fn two_byte(opcode: u8) -> Option<Encoding> {
+    match opcode {
+        0x04 | 0x0a | 0x0c | 0x36 | 0x39 | 0x3b..=0x3f | 0xff => None,
+        0x05..=0x09 | 0x0b | 0x0e | 0x30..=0x35 | 0x37 | 0x77 => plain(Operand::None),
+        // 3DNow! instructions encode their opcode in a trailing byte.
+        0x0f => modrm(Operand::Imm8),
+        0x70..=0x73 | 0xa4 | 0xac | 0xba | 0xc2 | 0xc4..=0xc6 => modrm(Operand::Imm8),
+        0x80..=0x8f => plain(Operand::Rel32),
+        0xa0..=0xa2 | 0xa8..=0xaa | 0xc8..=0xcf => plain(Operand::None),
+        _ => modrm(Operand::None),
+    }
+}
+
+fn vex(map: u8, opcode: u8) -> Option<Encoding> {
+    match (map, opcode) {
+        (1, 0x77) => plain(Operand::None),
+        (1, 0x70..=0x73 | 0xc2 | 0xc4..=0xc6) | (3, _) => modrm(Operand::Imm8),
+        (1 | 2, _) => modrm(Operand::None),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instruction_lengths_are_decoded() {
+        let cases: &[(&str, &[u8], usize)] = &[
+            ("nop", &[0x90], 1),
+            ("push rbx", &[0x53], 1),
+            ("push r15", &[0x41, 0x57], 2),
+            ("ret", &[0xc3], 1),
+            ("ret 0x10", &[0xc2, 0x10, 0x00], 3),
+            ("int3", &[0xcc], 1),
+            ("mov rbx, rax", &[0x48, 0x8b, 0xd8], 3),
+            ("mov rcx, rsi", &[0x48, 0x8b, 0xce], 3),
+            ("test rax, rax", &[0x48, 0x85, 0xc0], 3),
+            ("mov eax, imm32", &[0xb8, 0x78, 0x56, 0x34, 0x12], 5),
+            ("mov ax, imm16", &[0x66, 0xb8, 0x34, 0x12], 4),
+            ("mov rax, imm64", &[0x48, 0xb8, 1, 2, 3, 4, 5, 6, 7, 8], 10),
+            ("mov al, imm8", &[0xb0, 0x01], 2),
+            ("add rsp, 8", &[0x48, 0x83, 0xc4, 0x08], 4),
+            ("sub rsp, 0x60", &[0x48, 0x83, 0xec, 0x60], 4),
+            (
+                "sub rsp, imm32",
+                &[0x48, 0x81, 0xec, 0x00, 0x01, 0x00, 0x00],
+                7,
+            ),
+            ("add ax, imm16", &[0x66, 0x05, 0x34, 0x12], 4),
+            ("add eax, imm32", &[0x05, 0x78, 0x56, 0x34, 0x12], 5),
+            ("mov [rsp+0x10], rbx", &[0x48, 0x89, 0x5c, 0x24, 0x10], 5),
+            ("mov rax, [rsp]", &[0x48, 0x8b, 0x04, 0x24], 4),
+            ("mov rax, [rbp]", &[0x48, 0x8b, 0x45, 0x00], 4),
+            ("mov rax, [r13]", &[0x49, 0x8b, 0x45, 0x00], 4),
+            (
+                "mov rax, [rbx+disp32]",
+                &[0x48, 0x8b, 0x83, 0x60, 0x02, 0x00, 0x00],
+                7,
+            ),
+            (
+                "mov rax, [rip+disp32]",
+                &[0x48, 0x8b, 0x05, 0x10, 0x00, 0x00, 0x00],
+                7,
+            ),
+            (
+                "mov rax, [disp32+rcx*8]",
+                &[0x48, 0x8b, 0x04, 0xcd, 0x00, 0x10, 0x00, 0x00],
+                8,
+            ),
+            (
+                "mov rax, [rsp+rcx*8+disp32]",
+                &[0x48, 0x8b, 0x84, 0xcc, 0x00, 0x01, 0x00, 0x00],
+                8,
+            ),
+            ("lea rsp, [rsp-8]", &[0x48, 0x8d, 0x64, 0x24, 0xf8], 5),
+            (
+                "mov dword [rip+disp32], imm32",
+                &[0xc7, 0x05, 1, 0, 0, 0, 2, 0, 0, 0],
+                10,
+            ),
+            (
+                "mov byte [rsi+disp32], imm8",
+                &[0xc6, 0x86, 0x10, 0x01, 0x00, 0x00, 0x00],
+                7,
+            ),
+            ("mov word [rax], imm16", &[0x66, 0xc7, 0x00, 0x34, 0x12], 5),
+            ("test byte [rax], imm8", &[0xf6, 0x00, 0x01], 3),
+            ("test ecx, imm32", &[0xf7, 0xc1, 1, 0, 0, 0], 6),
+            ("not ecx", &[0xf7, 0xd1], 2),
+            ("jmp rel8", &[0xeb, 0x08], 2),
+            ("jnz rel8", &[0x75, 0x0e], 2),
+            ("jmp rel32", &[0xe9, 1, 2, 3, 4], 5),
+            ("call rel32", &[0xe8, 1, 2, 3, 4], 5),
+            ("jz rel32", &[0x0f, 0x84, 1, 2, 3, 4], 6),
+            ("call [rip+2]", &[0xff, 0x15, 0x02, 0x00, 0x00, 0x00], 6),
+            ("jmp [rip+0]", &[0xff, 0x25, 0x00, 0x00, 0x00, 0x00], 6),
+            ("call rax", &[0xff, 0xd0], 2),
+            ("mov eax, [moffs64]", &[0xa1, 1, 2, 3, 4, 5, 6, 7, 8], 9),
+            ("mov eax, [moffs32]", &[0x67, 0xa1, 1, 2, 3, 4], 6),
+            ("enter", &[0xc8, 0x10, 0x00, 0x00], 4),
+            ("imul eax, ecx, imm8", &[0x6b, 0xc1, 0x10], 3),
+            ("pushfq", &[0x9c], 1),
+            ("lock xadd [rcx], eax", &[0xf0, 0x0f, 0xc1, 0x01], 4),
+            ("rep movsb", &[0xf3, 0xa4], 2),
+            ("fld dword [rax]", &[0xd9, 0x00], 2),
+            ("subss xmm1, xmm7", &[0xf3, 0x0f, 0x5c, 0xcf], 4),
+            ("comiss xmm6, xmm1", &[0x0f, 0x2f, 0xf1], 3),
+            (
+                "movdqu [rsp+0x10], xmm1",
+                &[0xf3, 0x0f, 0x7f, 0x4c, 0x24, 0x10],
+                6,
+            ),
+            (
+                "movss xmm0, [rip+disp32]",
+                &[0xf3, 0x0f, 0x10, 0x05, 1, 2, 3, 4],
+                8,
+            ),
+            ("movaps xmm8, xmm0", &[0x44, 0x0f, 0x28, 0xc0], 4),
+            (
+                "pshufd xmm0, xmm1, imm8",
+                &[0x66, 0x0f, 0x70, 0xc1, 0x1b],
+                5,
+            ),
+            ("cmpps xmm0, xmm1, imm8", &[0x0f, 0xc2, 0xc1, 0x01], 4),
+            ("bt eax, imm8", &[0x0f, 0xba, 0xe0, 0x03], 4),
+            ("cpuid", &[0x0f, 0xa2], 2),
+            ("syscall", &[0x0f, 0x05], 2),
+            ("bswap rax", &[0x48, 0x0f, 0xc8], 3),
+            ("nop dword [rax+rax+0]", &[0x0f, 0x1f, 0x44, 0x00, 0x00], 5),
+            (
+                "nop word [rax+rax+0]",
+                &[0x66, 0x0f, 0x1f, 0x84, 0x00, 0, 0, 0, 0],
+                9,
+            ),
+            ("pshufb xmm0, xmm1", &[0x66, 0x0f, 0x38, 0x00, 0xc1], 5),
+            (
+                "roundss xmm0, xmm1, imm8",
+                &[0x66, 0x0f, 0x3a, 0x0a, 0xc1, 0x04],
+                6,
+            ),
+            ("vzeroupper", &[0xc5, 0xf8, 0x77], 3),
+            ("vmovups ymm0, [rcx]", &[0xc5, 0xfc, 0x10, 0x01], 4),
+            (
+                "vfmadd231ps xmm0, xmm1, xmm2",
+                &[0xc4, 0xe2, 0x71, 0xb8, 0xc2],
+                5,
+            ),
+            (
+                "vpermq ymm0, ymm1, imm8",
+                &[0xc4, 0xe3, 0xfd, 0x00, 0xc1, 0x1b],
+                6,
+            ),
+            (
+                "vshufps xmm0, xmm1, xmm2, imm8",
+                &[0xc5, 0xf0, 0xc6, 0xc2, 0x44],
+                5,
+            ),
+        ];
+
+        for (name, code, length) in cases {
+            assert_eq!(instruction_length(code), Some(*length), "{name}");
+        }
+    }
+
+    #[test]
+    fn relative_operands_are_located() {
+        type Case = (&'static str, &'static [u8], Option<usize>, Option<Branch>);
+        let cases: &[Case] = &[
+            ("mov rbx, rax", &[0x48, 0x8b, 0xd8], None, None),
+            ("mov rax, [rbp]", &[0x48, 0x8b, 0x45, 0x00], None, None),
+            (
+                "mov rax, [disp32]",
+                &[0x48, 0x8b, 0x04, 0x25, 0, 0, 0, 0],
+                None,
+                None,
+            ),
+            (
+                "mov rax, [rip+disp32]",
+                &[0x48, 0x8b, 0x05, 0, 0, 0, 0],
+                Some(3),
+                None,
+            ),
+            (
+                "mov dword [rip+disp32], imm32",
+                &[0xc7, 0x05, 0, 0, 0, 0, 1, 0, 0, 0],
+                Some(2),
+                None,
+            ),
+            (
+                "movss xmm0, [rip+disp32]",
+                &[0xf3, 0x0f, 0x10, 0x05, 0, 0, 0, 0],
+                Some(4),
+                None,
+            ),
+            (
+                "vmovups ymm0, [rip+disp32]",
+                &[0xc5, 0xfc, 0x10, 0x05, 0, 0, 0, 0],
+                Some(4),
+                None,
+            ),
+            ("call [rip+2]", &[0xff, 0x15, 2, 0, 0, 0], Some(2), None),
+            ("jmp rel8", &[0xeb, 0x08], None, Some(Branch::Short)),
+            ("jnz rel8", &[0x75, 0x0e], None, Some(Branch::Short)),
+            ("loop rel8", &[0xe2, 0xfe], None, Some(Branch::ShortOnly)),
+            ("jrcxz rel8", &[0xe3, 0x10], None, Some(Branch::ShortOnly)),
+            ("call rel32", &[0xe8, 0, 0, 0, 0], None, Some(Branch::Near)),
+            ("jmp rel32", &[0xe9, 0, 0, 0, 0], None, Some(Branch::Near)),
+            (
+                "jz rel32",
+                &[0x0f, 0x84, 0, 0, 0, 0],
+                None,
+                Some(Branch::Near),
+            ),
+            ("call rax", &[0xff, 0xd0], None, None),
+        ];
+
+        for (name, code, rip_displacement, branch) in cases {
+            let instruction = decode(code).unwrap_or_else(|| panic!("{name}"));
+            assert_eq!(instruction.rip_displacement, *rip_displacement, "{name}");
+            assert_eq!(instruction.branch, *branch, "{name}");
+        }
+    }
+
+    #[test]
+    fn rex_before_a_legacy_prefix_is_ignored() {
+        // The REX.W prefix is cancelled by the operand-size prefix that follows it.
+        assert_eq!(instruction_length(&[0x48, 0x66, 0xb8, 0x34, 0x12]), Some(5));
+    }
+
+    #[test]
+    fn trailing_bytes_are_not_part_of_the_instruction() {
+        assert_eq!(instruction_length(&[0x90, 0x90, 0x90]), Some(1));
+        assert_eq!(instruction_length(&[0xeb, 0x08, 0xcc, 0xcc]), Some(2));
+    }
+
+    #[test]
+    fn unsupported_and_truncated_instructions_are_rejected() {
+        let cases: &[(&str, &[u8])] = &[
+            ("empty", &[]),
+            ("prefix only", &[0x66]),
+            ("rex only", &[0x48]),
+            ("missing modrm", &[0x48, 0x8b]),
+            ("missing sib", &[0x48, 0x8b, 0x04]),
+            ("missing displacement", &[0x48, 0x8b, 0x05, 0x10, 0x00]),
+            ("missing immediate", &[0xe8, 0x01, 0x02]),
+            ("missing escape", &[0x0f]),
+            ("push es", &[0x06]),
+            ("daa", &[0x27]),
+            ("far call", &[0x9a, 1, 2, 3, 4, 5, 6]),
+            ("evex", &[0x62, 0xf1, 0x7c, 0x48, 0x10, 0x01]),
+            ("ud0", &[0x0f, 0xff, 0xc0]),
+            ("vex after rex", &[0x48, 0xc5, 0xf8, 0x77]),
+            (
+                "too long",
+                &[
+                    0x2e, 0x2e, 0x2e, 0x2e, 0x2e, 0x2e, 0x2e, 0x2e, 0x2e, 0x2e, 0x2e, 0x05, 1, 2,
+                    3, 4,
+                ],
+            ),
+        ];
+
+        for (name, code) in cases {
+            assert_eq!(instruction_length(code), None, "{name}");
+        }
+    }
+
+    #[test]
+    fn covering_length_ends_on_an_instruction_boundary() {
+        // SUBSS XMM1, XMM7; COMISS XMM6, XMM1; JBE rel8
+        let code = [0xf3, 0x0f, 0x5c, 0xcf, 0x0f, 0x2f, 0xf1, 0x76, 0x10];
+        let cases = [(0, 0), (1, 4), (4, 4), (5, 7), (7, 7), (8, 9), (9, 9)];
+
+        for (minimum, length) in cases {
+            assert_eq!(covering_length(&code, minimum), Ok(length), "{minimum}");
+        }
+    }
+
+    #[test]
+    fn covering_length_reports_the_undecodable_instruction() {
+        let code = [0x48, 0x8b, 0xd8, 0x06, 0x90];
+
+        assert_eq!(covering_length(&code, 5), Err(3));
+        assert_eq!(covering_length(&code[..2], 1), Err(0));
+    }
+}
@@ -0,0 +1,212 @@
+//! Hooks on the entry of a function that keep the original callable.
+
+use std::mem::{size_of, transmute_copy};
+
+use super::{asm::Asm, check_expected_bytes, relocate, Patch, PatchError, NEAR_JUMP_SIZE};
+
+/// Size of [`Asm::jmp_abs`], which precedes the gateway.
+const ABSOLUTE_JUMP_SIZE: usize = 6 + size_of::<usize>();
+
+/// A `CALL rel32`, the only call a call site hook can take the callee from.
+const NEAR_CALL: &str = "E8 ?? ?? ?? ??";
+
+impl Patch {
+    /// Returns the function called by the `CALL rel32` at `address`, whose bytes
+    /// must match `expected`, as an `F`.
+    ///
+    /// Hooking the call site with [`Patch::patch_call`] only affects that caller,
+    /// while the detour can still call the original function through the result.
+    ///
+    /// # Safety
+    /// `F` must be a function pointer type matching the called function, and
+    /// `address` must be readable.
+    pub unsafe fn call_target<F: Copy>(
+        address: usize,
+        expected: Option<&str>,
+    ) -> Result<F, PatchError> {
+        assert_eq!(
+            size_of::<F>(),
+            size_of::<usize>(),
+            "A call target requires a function pointer"
+        );
+        check_expected_bytes(address, expected, NEAR_JUMP_SIZE)?;
+        check_expected_bytes(address, Some(NEAR_CALL), NEAR_JUMP_SIZE)?;
+
+        let target = decode_call(address, *(address as *const [u8; NEAR_JUMP_SIZE]))?;
+        Ok(transmute_copy::<usize, F>(&target))
+    }
+
+    /// Redirects every call of the function at `address` to `detour`.
+    ///
+    /// The instructions replaced at the entry, [`Patch::hook_size`] bytes that must
+    /// match `expected`, are relocated into a gateway that continues into the rest
+    /// of the function. The gateway is returned as an `F`, so the detour can call
+    /// the original function once patches are installed.
+    ///
+    /// # Safety
+    /// `F` must be a function pointer type matching the hooked function, and no code
+    /// may jump into the first instructions of the function from elsewhere.
+    #[allow(dead_code)]
+    pub unsafe fn hook_function<F: Copy>(
+        address: usize,
+        expected: Option<&str>,
+        detour: F,
+    ) -> Result<(Self, F), PatchError> {
+        assert_eq!(
+            size_of::<F>(),
+            size_of::<usize>(),
+            "A function hook requires a function pointer"
+        );
+        let detour = transmute_copy::<F, usize>(&detour);
+
+        let (patch, gateway) = Self::prepare_function_hook(address, expected, detour)?;
+        Ok((patch, transmute_copy::<usize, F>(&gateway)))
+    }
+
+    unsafe fn prepare_function_hook(
+        address: usize,
+        expected: Option<&str>,
+        detour: usize,
+    ) -> Result<(Self, usize), PatchError> {
+        let size = Self::hook_size(address)?;
+        check_expected_bytes(address, expected, size)?;
+        let prologue = std::slice::from_raw_parts(address as *const u8, size).to_vec();
+        let source = prologue.clone();
+        let build = move |trampoline_address| {
+            function_trampoline(detour, address, &source, trampoline_address)
+        };
+        // Only the displacements depend on where the trampoline ends up.
+        let trampoline_size = build(address)?.len();
+
+        let patch = Self::prepare_detour(address, prologue, trampoline_size, build)?;
+        let gateway = patch
+            .trampoline_address()
+            .expect("function hook has no trampoline")
+            + ABSOLUTE_JUMP_SIZE;
+        Ok((patch, gateway))
+    }
+}
+
+/// Returns the destination of the `CALL rel32` encoded by `call` at `address`.
+fn decode_call(address: usize, call: [u8; NEAR_JUMP_SIZE]) -> Result<usize, PatchError> {
+    let displacement = i32::from_le_bytes([call[1], call[2], call[3], call[4]]);
+    address
+        .checked_add(NEAR_JUMP_SIZE)
+        .and_then(|next_instruction| next_instruction.checked_add_signed(displacement as isize))
+        .ok_or(PatchError::AddressOverflow)
+}
+
+/// Builds the trampoline of [`Patch::hook_function`] for `trampoline_address`: an
+/// absolute jump to `detour`, followed by the gateway that runs the relocated
+/// `prologue` and jumps back into the function.
+fn function_trampoline(
+    detour: usize,
+    address: usize,
+    prologue: &[u8],
+    trampoline_address: usize,
+) -> Result<Vec<u8>, PatchError> {
+    let mut asm = Asm::new();
+    asm.jmp_abs(detour);
+
+    let gateway = trampoline_address
+        .checked_add(ABSOLUTE_JUMP_SIZE)
+        .ok_or(PatchError::AddressOverflow)?;
+    asm.bytes(&relocate::relocate(prologue, address, gateway)?);
+
+    let resume = address
+        .checked_add(prologue.len())
+        .ok_or(PatchError::AddressOverflow)?;
+    asm.jmp_to(resume).finish(trampoline_address)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::OnceLock;
+
+    use super::*;
+    use crate::patchy::{
+        build_near_jump,
+        memory::{Memory, Native},
+    };
+
+    type Answer = extern "C" fn() -> u32;
+
+    static ORIGINAL: OnceLock<Answer> = OnceLock::new();
+
+    extern "C" fn doubled() -> u32 {
+        ORIGINAL.get().expect("original not set")() * 2
+    }
+
+    #[test]
+    fn call_targets_are_decoded_from_the_call() {
+        // The zoom value calls of the 1.163 and 1.151 profiles.
+        assert_eq!(
+            decode_call(0x14026b03f, [0xe8, 0x2c, 0x41, 0xfe, 0xff]).unwrap(),
+            0x14024f170
+        );
+        assert_eq!(
+            decode_call(0x140249371, [0xe8, 0x1a, 0x47, 0xfe, 0xff]).unwrap(),
+            0x14022da90
+        );
+        assert!(matches!(
+            decode_call(usize::MAX - 4, [0xe8, 0, 0, 0, 0]),
+            Err(PatchError::AddressOverflow)
+        ));
+
+        // MOV EAX, 1; CALL -5; NOP
+        let code: [u8; 11] = [
+            0xb8, 0x01, 0x00, 0x00, 0x00, 0xe8, 0xfb, 0xff, 0xff, 0xff, 0x90,
+        ];
+        unsafe {
+            let address = code.as_ptr() as usize;
+            let target: Answer = Patch::call_target(address + 5, Some("E8 FB FF FF FF")).unwrap();
+            assert_eq!(target as usize, address + 5);
+            assert!(matches!(
+                Patch::call_target::<Answer>(address, Some("B8 01 00 00 00")),
+                Err(PatchError::UnexpectedBytes { .. })
+            ));
+            assert!(matches!(
+                Patch::call_target::<Answer>(address + 5, Some("E8 00 00 00 00")),
+                Err(PatchError::UnexpectedBytes { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn detours_can_call_the_original() {
+        // MOV EAX, 20; ADD EAX, 1; RET
+        let function = [0xb8, 0x14, 0x00, 0x00, 0x00, 0x83, 0xc0, 0x01, 0xc3];
+
+        unsafe {
+            let address = Native::allocate_code(4096).expect("Failed to allocate test memory");
+            let trampoline_address = address + 0x100;
+            std::ptr::copy_nonoverlapping(function.as_ptr(), address as *mut u8, function.len());
+
+            let size = Patch::hook_size(address).unwrap();
+            assert_eq!(size, 5);
+            let trampoline = function_trampoline(
+                doubled as *const () as usize,
+                address,
+                &function[..size],
+                trampoline_address,
+            )
+            .unwrap();
+            std::ptr::copy_nonoverlapping(
+                trampoline.as_ptr(),
+                trampoline_address as *mut u8,
+                trampoline.len(),
+            );
+            let jump = build_near_jump(address, trampoline_address, size).unwrap();
+            std::ptr::copy_nonoverlapping(jump.as_ptr(), address as *mut u8, jump.len());
+
+            let gateway: Answer = transmute_copy(&(trampoline_address + ABSOLUTE_JUMP_SIZE));
+            ORIGINAL.set(gateway).unwrap();
+            let hooked: Answer = std::mem::transmute(address);
+
+            assert_eq!(gateway(), 21);
+            assert_eq!(hooked(), 42);
+
+            Native::free_code(address, 4096);
+        }
+    }
+}
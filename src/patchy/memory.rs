@@ -0,0 +1,612 @@
+//! The operating system calls the patch engine relies on.
+//!
+//! HighFleet only runs on Windows, but the engine also builds for Linux so that
+//! preparing, installing and validating patches can be tested end to end with
+//! `cargo test --target x86_64-unknown-linux-gnu` on a Linux x86-64 host.
+//! Everything else in `patchy` goes through [`Native`].
+
+use std::ops::Range;
+
+/// Page protection, instruction cache and module queries of one operating system.
+///
+/// Errors are returned as the operating system's message, which callers attach
+/// to the address they were working on.
+pub(crate) trait Memory {
+    /// Protection of a memory range, saved to be restored later.
+    type Protection: Copy;
+
+    /// A thread stopped by [`Memory::suspend_other_threads`], resumed when dropped.
+    type Thread;
+
+    /// Makes the `size` bytes at `address` readable, writable and executable and
+    /// returns their previous protection.
+    ///
+    /// # Safety
+    /// The range must be mapped, and nothing may rely on its current protection.
+    unsafe fn make_writable(address: usize, size: usize) -> Result<Self::Protection, String>;
+
+    /// Restores the `protection` returned by [`Memory::make_writable`].
+    ///
+    /// # Safety
+    /// The range must still be mapped.
+    unsafe fn restore(
+        address: usize,
+        size: usize,
+        protection: Self::Protection,
+    ) -> Result<(), String>;
+
+    /// Makes the processor execute the code just written to the `size` bytes at `address`.
+    ///
+    /// # Safety
+    /// The range must be mapped.
+    unsafe fn flush_instruction_cache(address: usize, size: usize) -> Result<(), String>;
+
+    /// Suspends every other thread of the process and returns each one with the
+    /// address of the instruction it stopped at.
+    ///
+    /// Threads started after the call are not suspended. Nothing may allocate
+    /// until the threads are resumed, as a suspended thread may hold the heap lock;
+    /// the returned list resumes its threads before its own memory is released.
+    ///
+    /// # Safety
+    /// The calling thread must not wait on anything a suspended thread may hold.
+    unsafe fn suspend_other_threads() -> Result<Vec<(Self::Thread, usize)>, String>;
+
+    /// Returns the base address of the game executable.
+    ///
+    /// # Safety
+    /// Only queries the operating system, but its bindings are unsafe.
+    unsafe fn main_module() -> Result<usize, String>;
+
+    /// Returns the module whose image contains `address`, if any.
+    ///
+    /// # Safety
+    /// Only queries the operating system, but its bindings are unsafe.
+    unsafe fn module_containing(address: usize) -> Option<Module>;
+
+    /// Returns the committed region containing `address`, or `None` if the
+    /// address is not backed by memory.
+    ///
+    /// # Safety
+    /// Only queries the operating system, but its bindings are unsafe.
+    unsafe fn region(address: usize) -> Option<Region>;
+
+    /// Allocates `size` bytes of readable, writable and executable memory.
+    ///
+    /// # Safety
+    /// The memory must be released with [`Memory::free_code`].
+    #[cfg(test)]
+    unsafe fn allocate_code(size: usize) -> Result<usize, String>;
+
+    /// Releases memory returned by [`Memory::allocate_code`].
+    ///
+    /// # Safety
+    /// Nothing may use the memory afterwards.
+    #[cfg(test)]
+    unsafe fn free_code(address: usize, size: usize);
+}
+
+/// A module loaded into the game process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Module {
+    pub base: usize,
+    /// File name of the module, e.g. `other_mod.dll`, if it can be read.
+    pub name: Option<String>,
+}
+
+/// A range of committed memory sharing one protection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Region {
+    pub range: Range<usize>,
+    pub readable: bool,
+    pub writable: bool,
+}
+
+/// The backend of the operating system the mod is built for.
+#[cfg(windows)]
+pub(crate) type Native = Windows;
+/// The backend of the operating system the mod is built for.
+#[cfg(target_os = "linux")]
+pub(crate) type Native = Linux;
+
+/// Returns the file name at the end of `path`.
+fn file_name(path: &str) -> Option<String> {
+    path.rsplit(['\\', '/'])
+        .next()
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+}
+
+#[cfg(windows)]
+pub(crate) use windows_backend::Windows;
+
+#[cfg(windows)]
+mod windows_backend {
+    use std::ffi::c_void;
+
+    use windows::{
+        core::PCWSTR,
+        Win32::{
+            Foundation::{CloseHandle, HANDLE, HMODULE},
+            System::{
+                Diagnostics::{
+                    Debug::{
+                        FlushInstructionCache, GetThreadContext, CONTEXT, CONTEXT_CONTROL_AMD64,
+                    },
+                    ToolHelp::{
+                        CreateToolhelp32Snapshot, Thread32First, Thread32Next, TH32CS_SNAPTHREAD,
+                        THREADENTRY32,
+                    },
+                },
+                LibraryLoader::{
+                    GetModuleFileNameW, GetModuleHandleExW, GetModuleHandleW,
+                    GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS,
+                    GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT,
+                },
+                Memory::{
+                    VirtualProtect, VirtualQuery, MEMORY_BASIC_INFORMATION, MEM_COMMIT,
+                    PAGE_EXECUTE_READ, PAGE_EXECUTE_READWRITE, PAGE_EXECUTE_WRITECOPY, PAGE_GUARD,
+                    PAGE_NOACCESS, PAGE_PROTECTION_FLAGS, PAGE_READONLY, PAGE_READWRITE,
+                    PAGE_WRITECOPY,
+                },
+                Threading::{
+                    GetCurrentProcess, GetCurrentProcessId, GetCurrentThreadId, OpenThread,
+                    ResumeThread, SuspendThread, THREAD_GET_CONTEXT, THREAD_SUSPEND_RESUME,
+                },
+            },
+        },
+    };
+
+    use super::{file_name, Memory, Module, Region};
+
+    /// Longest module path returned by `GetModuleFileNameW` without long path support.
+    const MAX_PATH: usize = 260;
+
+    /// Backend using the Win32 memory and module APIs.
+    pub(crate) struct Windows;
+
+    /// A thread suspended with `SuspendThread`.
+    pub(crate) struct SuspendedThread(HANDLE);
+
+    impl Drop for SuspendedThread {
+        fn drop(&mut self) {
+            unsafe {
+                ResumeThread(self.0);
+                let _ = CloseHandle(self.0);
+            }
+        }
+    }
+
+    impl Memory for Windows {
+        type Protection = PAGE_PROTECTION_FLAGS;
+        type Thread = SuspendedThread;
+
+        unsafe fn make_writable(address: usize, size: usize) -> Result<Self::Protection, String> {
+            let mut old = PAGE_PROTECTION_FLAGS(0);
+            VirtualProtect(
+                address as *const c_void,
+                size,
+                PAGE_EXECUTE_READWRITE,
+                &mut old,
+            )
+            .map_err(|error| error.to_string())?;
+            Ok(old)
+        }
+
+        unsafe fn restore(
+            address: usize,
+            size: usize,
+            protection: Self::Protection,
+        ) -> Result<(), String> {
+            let mut ignored = PAGE_PROTECTION_FLAGS(0);
+            VirtualProtect(address as *const c_void, size, protection, &mut ignored)
+                .map_err(|error| error.to_string())
+        }
+
+        unsafe fn flush_instruction_cache(address: usize, size: usize) -> Result<(), String> {
+            FlushInstructionCache(GetCurrentProcess(), Some(address as *const c_void), size)
+                .map_err(|error| error.to_string())
+        }
+
+        unsafe fn suspend_other_threads() -> Result<Vec<(Self::Thread, usize)>, String> {
+            // Threads are listed up front, as nothing may allocate once they are suspended.
+            let others = other_threads()?;
+            let mut suspended = Vec::with_capacity(others.len());
+            for id in others {
+                // Threads that exited in the meantime cannot be opened.
+                let Ok(handle) = OpenThread(THREAD_SUSPEND_RESUME | THREAD_GET_CONTEXT, false, id)
+                else {
+                    continue;
+                };
+                if SuspendThread(handle) == u32::MAX {
+                    let _ = CloseHandle(handle);
+                    continue;
+                }
+                let thread = SuspendedThread(handle);
+
+                // Reading the context also waits until the thread has stopped.
+                let mut context = CONTEXT {
+                    ContextFlags: CONTEXT_CONTROL_AMD64,
+                    ..Default::default()
+                };
+                if let Err(error) = GetThreadContext(handle, &mut context) {
+                    drop(thread);
+                    drop(suspended);
+                    return Err(error.to_string());
+                }
+                suspended.push((thread, context.Rip as usize));
+            }
+
+            Ok(suspended)
+        }
+
+        unsafe fn main_module() -> Result<usize, String> {
+            GetModuleHandleW(None)
+                .map(|module| module.0 as usize)
+                .map_err(|error| error.to_string())
+        }
+
+        unsafe fn module_containing(address: usize) -> Option<Module> {
+            let mut module = HMODULE::default();
+            GetModuleHandleExW(
+                GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS
+                    | GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT,
+                PCWSTR(address as *const u16),
+                &mut module,
+            )
+            .ok()?;
+
+            let mut path = [0u16; MAX_PATH];
+            let length = GetModuleFileNameW(Some(module), &mut path) as usize;
+            Some(Module {
+                base: module.0 as usize,
+                name: file_name(&String::from_utf16_lossy(&path[..length])),
+            })
+        }
+
+        unsafe fn region(address: usize) -> Option<Region> {
+            let mut info = MEMORY_BASIC_INFORMATION::default();
+            let written = VirtualQuery(
+                Some(address as *const c_void),
+                &mut info,
+                size_of::<MEMORY_BASIC_INFORMATION>(),
+            );
+            if written == 0 || info.State != MEM_COMMIT {
+                return None;
+            }
+
+            let allows = |flags: &[PAGE_PROTECTION_FLAGS]| {
+                flags.iter().any(|flag| info.Protect.0 & flag.0 != 0)
+            };
+            let accessible = !allows(&[PAGE_NOACCESS, PAGE_GUARD]);
+            let start = info.BaseAddress as usize;
+            Some(Region {
+                range: start..start + info.RegionSize,
+                readable: accessible
+                    && allows(&[
+                        PAGE_READONLY,
+                        PAGE_READWRITE,
+                        PAGE_WRITECOPY,
+                        PAGE_EXECUTE_READ,
+                        PAGE_EXECUTE_READWRITE,
+                        PAGE_EXECUTE_WRITECOPY,
+                    ]),
+                writable: accessible
+                    && allows(&[
+                        PAGE_READWRITE,
+                        PAGE_WRITECOPY,
+                        PAGE_EXECUTE_READWRITE,
+                        PAGE_EXECUTE_WRITECOPY,
+                    ]),
+            })
+        }
+
+        #[cfg(test)]
+        unsafe fn allocate_code(size: usize) -> Result<usize, String> {
+            use windows::Win32::System::Memory::{VirtualAlloc, MEM_RESERVE};
+
+            let memory = VirtualAlloc(None, size, MEM_COMMIT | MEM_RESERVE, PAGE_EXECUTE_READWRITE);
+            if memory.is_null() {
+                Err(windows::core::Error::from_win32().to_string())
+            } else {
+                Ok(memory as usize)
+            }
+        }
+
+        #[cfg(test)]
+        unsafe fn free_code(address: usize, _size: usize) {
+            use windows::Win32::System::Memory::{VirtualFree, MEM_RELEASE};
+
+            let _ = VirtualFree(address as *mut c_void, 0, MEM_RELEASE);
+        }
+    }
+
+    /// Returns the identifiers of the threads of this process besides the calling one.
+    unsafe fn other_threads() -> Result<Vec<u32>, String> {
+        let snapshot =
+            CreateToolhelp32Snapshot(TH32CS_SNAPTHREAD, 0).map_err(|error| error.to_string())?;
+        let process = GetCurrentProcessId();
+        let current = GetCurrentThreadId();
+
+        let mut threads = Vec::new();
+        let mut entry = THREADENTRY32 {
+            dwSize: size_of::<THREADENTRY32>() as u32,
+            ..Default::default()
+        };
+        let mut listed = Thread32First(snapshot, &mut entry);
+        while listed.is_ok() {
+            if entry.th32OwnerProcessID == process && entry.th32ThreadID != current {
+                threads.push(entry.th32ThreadID);
+            }
+            listed = Thread32Next(snapshot, &mut entry);
+        }
+        let _ = CloseHandle(snapshot);
+
+        Ok(threads)
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) use linux_backend::Linux;
+
+#[cfg(target_os = "linux")]
+mod linux_backend {
+    use std::{ffi::CStr, fs, io, mem::MaybeUninit};
+
+    use mmap_rs::MmapOptions;
+
+    use super::{file_name, Memory, Module, Region};
+
+    /// Backend using `mprotect`, `mmap` and the process's memory map.
+    pub(crate) struct Linux;
+
+    impl Memory for Linux {
+        /// The `PROT_*` flags of the page containing the start of the range.
+        type Protection = libc::c_int;
+        /// No thread is ever suspended, see [`Linux::suspend_other_threads`].
+        type Thread = ();
+
+        unsafe fn make_writable(address: usize, size: usize) -> Result<Self::Protection, String> {
+            let maps = fs::read_to_string("/proc/self/maps").map_err(|error| error.to_string())?;
+            let old = super::mapping_protection(&maps, address)
+                .ok_or_else(|| format!("{address:#x} is not mapped"))?;
+            protect(
+                address,
+                size,
+                libc::PROT_READ | libc::PROT_WRITE | libc::PROT_EXEC,
+            )?;
+            Ok(old)
+        }
+
+        unsafe fn restore(
+            address: usize,
+            size: usize,
+            protection: Self::Protection,
+        ) -> Result<(), String> {
+            protect(address, size, protection)
+        }
+
+        unsafe fn flush_instruction_cache(_address: usize, _size: usize) -> Result<(), String> {
+            // x86-64 keeps instruction fetches coherent with writes to code.
+            Ok(())
+        }
+
+        /// Suspends nothing: the backend only serves the tests, which never run
+        /// patched code on other threads.
+        unsafe fn suspend_other_threads() -> Result<Vec<(Self::Thread, usize)>, String> {
+            Ok(Vec::new())
+        }
+
+        unsafe fn main_module() -> Result<usize, String> {
+            let executable = fs::read_link("/proc/self/exe").map_err(|error| error.to_string())?;
+            let maps = fs::read_to_string("/proc/self/maps").map_err(|error| error.to_string())?;
+            super::image_base(&maps, &executable.to_string_lossy())
+                .ok_or_else(|| "the executable is not in the memory map".to_string())
+        }
+
+        unsafe fn module_containing(address: usize) -> Option<Module> {
+            let mut info = MaybeUninit::<libc::Dl_info>::zeroed();
+            if libc::dladdr(address as *const libc::c_void, info.as_mut_ptr()) == 0 {
+                return None;
+            }
+
+            let info = info.assume_init();
+            let name = (!info.dli_fname.is_null())
+                .then(|| file_name(&CStr::from_ptr(info.dli_fname).to_string_lossy()))
+                .flatten();
+            Some(Module {
+                base: info.dli_fbase as usize,
+                name,
+            })
+        }
+
+        unsafe fn region(address: usize) -> Option<Region> {
+            let maps = fs::read_to_string("/proc/self/maps").ok()?;
+            super::mapping_region(&maps, address)
+        }
+
+        #[cfg(test)]
+        unsafe fn allocate_code(size: usize) -> Result<usize, String> {
+            let memory = libc::mmap(
+                std::ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE | libc::PROT_EXEC,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if memory == libc::MAP_FAILED {
+                Err(io::Error::last_os_error().to_string())
+            } else {
+                Ok(memory as usize)
+            }
+        }
+
+        #[cfg(test)]
+        unsafe fn free_code(address: usize, size: usize) {
+            libc::munmap(address as *mut libc::c_void, size);
+        }
+    }
+
+    /// Changes the protection of every page overlapping the `size` bytes at `address`.
+    unsafe fn protect(address: usize, size: usize, protection: libc::c_int) -> Result<(), String> {
+        let page_size = MmapOptions::page_size();
+        let start = address & !(page_size - 1);
+        let end = address
+            .checked_add(size)
+            .and_then(|end| end.checked_next_multiple_of(page_size))
+            .ok_or_else(|| "address calculation overflowed".to_string())?;
+
+        if libc::mprotect(start as *mut libc::c_void, end - start, protection) == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error().to_string())
+        }
+    }
+}
+
+/// Returns the `PROT_*` flags of the mapping containing `address` in the
+/// `/proc/self/maps` listing `maps`.
+#[cfg(target_os = "linux")]
+fn mapping_protection(maps: &str, address: usize) -> Option<libc::c_int> {
+    maps.lines().find_map(|line| {
+        let mapping = Mapping::parse(line)?;
+        if !mapping.range.contains(&address) {
+            return None;
+        }
+
+        let flags = [
+            (b'r', libc::PROT_READ),
+            (b'w', libc::PROT_WRITE),
+            (b'x', libc::PROT_EXEC),
+        ];
+        Some(
+            mapping
+                .permissions
+                .bytes()
+                .zip(flags)
+                .filter(|(permission, (flag, _))| permission == flag)
+                .fold(libc::PROT_NONE, |protection, (_, (_, bit))| {
+                    protection | bit
+                }),
+        )
+    })
+}
+
+/// Returns the mapping containing `address` in the `/proc/self/maps` listing `maps`.
+#[cfg(target_os = "linux")]
+fn mapping_region(maps: &str, address: usize) -> Option<Region> {
+    let mapping = maps
+        .lines()
+        .filter_map(Mapping::parse)
+        .find(|mapping| mapping.range.contains(&address))?;
+    Some(Region {
+        readable: mapping.permissions.starts_with('r'),
+        writable: mapping.permissions.get(1..2) == Some("w"),
+        range: mapping.range,
+    })
+}
+
+/// Returns the lowest address mapped from the file at `path` in the
+/// `/proc/self/maps` listing `maps`.
+#[cfg(target_os = "linux")]
+fn image_base(maps: &str, path: &str) -> Option<usize> {
+    maps.lines()
+        .filter_map(Mapping::parse)
+        .filter(|mapping| mapping.path == path)
+        .map(|mapping| mapping.range.start)
+        .min()
+}
+
+/// A line of `/proc/self/maps`, e.g.
+/// `7f2c4a000000-7f2c4a021000 r-xp 00000000 08:01 1234 /usr/lib/libc.so.6`.
+#[cfg(target_os = "linux")]
+struct Mapping<'a> {
+    range: std::ops::Range<usize>,
+    permissions: &'a str,
+    path: &'a str,
+}
+
+#[cfg(target_os = "linux")]
+impl<'a> Mapping<'a> {
+    fn parse(line: &'a str) -> Option<Self> {
+        let mut fields = line.splitn(6, ' ');
+        let (start, end) = fields.next()?.split_once('-')?;
+        let permissions = fields.next()?;
+        let path = fields.nth(3).unwrap_or_default().trim_start();
+
+        Some(Self {
+            range: usize::from_str_radix(start, 16).ok()?..usize::from_str_radix(end, 16).ok()?,
+            permissions,
+            path,
+        })
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    const MAPS: &str = "\
+55d0c0000000-55d0c0001000 r--p 00000000 08:01 1234                       /opt/game/game
+55d0c0001000-55d0c0005000 r-xp 00001000 08:01 1234                       /opt/game/game
+7f2c4a000000-7f2c4a021000 rw-p 00000000 00:00 0
+7ffd1b3e0000-7ffd1b401000 rw-p 00000000 00:00 0                          [stack]
+";
+
+    #[test]
+    fn protections_are_read_from_the_memory_map() {
+        assert_eq!(
+            mapping_protection(MAPS, 0x55d0_c000_2345),
+            Some(libc::PROT_READ | libc::PROT_EXEC)
+        );
+        assert_eq!(
+            mapping_protection(MAPS, 0x7f2c_4a00_0000),
+            Some(libc::PROT_READ | libc::PROT_WRITE)
+        );
+        assert_eq!(mapping_protection(MAPS, 0x55d0_c000_5000), None);
+
+        assert_eq!(
+            mapping_region(MAPS, 0x7f2c_4a00_0010),
+            Some(Region {
+                range: 0x7f2c_4a00_0000..0x7f2c_4a02_1000,
+                readable: true,
+                writable: true,
+            })
+        );
+        assert!(!mapping_region(MAPS, 0x55d0_c000_0010).unwrap().writable);
+        assert_eq!(mapping_region(MAPS, 0x1000), None);
+
+        assert_eq!(image_base(MAPS, "/opt/game/game"), Some(0x55d0_c000_0000));
+        assert_eq!(image_base(MAPS, "/opt/game/other"), None);
+    }
+
+    #[test]
+    fn protections_are_restored() {
+        unsafe {
+            let size = 4096;
+            let address = Native::allocate_code(size).unwrap();
+            libc::mprotect(address as *mut libc::c_void, size, libc::PROT_READ);
+
+            let old = Native::make_writable(address + 16, 4).unwrap();
+            assert_eq!(old, libc::PROT_READ);
+            *((address + 16) as *mut u32) = 0xdead_beef;
+
+            Native::restore(address + 16, 4, old).unwrap();
+            let maps = std::fs::read_to_string("/proc/self/maps").unwrap();
+            assert_eq!(mapping_protection(&maps, address), Some(libc::PROT_READ));
+            assert_eq!(*((address + 16) as *const u32), 0xdead_beef);
+
+            Native::free_code(address, size);
+        }
+    }
+
+    #[test]
+    fn modules_are_found_by_address() {
+        unsafe {
+            let module = Native::module_containing(libc::malloc as *const () as usize).unwrap();
+            assert!(module.name.unwrap().starts_with("libc"));
+            assert!(Native::main_module().is_ok());
+        }
+    }
+}
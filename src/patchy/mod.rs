@@ -0,0 +1,2105 @@
+//! Simple patching library for Windows processes.
+
+use core::slice;
+use std::{
+    collections::BTreeSet,
+    fmt,
+    ops::Range,
+    path::Path,
+    sync::{Mutex, MutexGuard, OnceLock},
+};
+
+use mmap_rs::{Mmap, MmapMut, MmapOptions};
+
+pub(crate) mod asm;
+mod conflict;
+mod context;
+mod decode;
+mod function;
+pub(crate) mod memory;
+mod plan;
+mod relocate;
+pub(crate) mod scan;
+
+use asm::{
+    Asm,
+    Reg::{self, *},
+    Xmm::{self, *},
+};
+
+pub(crate) use conflict::PatchRange;
+pub use context::{Context, Exit};
+use memory::{Memory, Native};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ReturnType {
+    None,
+    Rax,
+    Xmm0,
+}
+
+const NEAR_JUMP: u8 = 0xe9;
+const TRAMPOLINE_ALIGNMENT: usize = 16;
+const NEAR_JUMP_SIZE: usize = 5;
+
+/// A patch prepared for installation.
+///
+/// The patch belongs to the patch manager, so dropping a `Patch` does not
+/// withdraw it. Keep its [`PatchHandle`] to disable and re-enable it once
+/// patches have been installed.
+pub struct Patch {
+    trampoline: Option<CodeAllocation>,
+    handle: PatchHandle,
+}
+
+/// Identifies an installed patch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PatchHandle {
+    id: usize,
+    address: usize,
+}
+
+#[derive(Clone, Copy)]
+struct CodeAllocation {
+    address: usize,
+}
+
+#[derive(Debug)]
+pub(crate) enum PatchError {
+    AddressOverflow,
+    AlreadyFinalized,
+    AmbiguousPattern {
+        pattern: String,
+        count: usize,
+        matches: Vec<usize>,
+    },
+    DryRunOutput {
+        path: String,
+        error: String,
+    },
+    DuplicateExit {
+        address: usize,
+        name: &'static str,
+    },
+    EmptyPatch,
+    EmptyTrampoline {
+        address: usize,
+    },
+    ForeignPatch {
+        address: usize,
+        code: conflict::ForeignCode,
+    },
+    InstallFailed {
+        feature: Option<&'static str>,
+        address: usize,
+        error: Box<PatchError>,
+    },
+    InstructionCache {
+        address: usize,
+        error: String,
+    },
+    InvalidPattern {
+        pattern: String,
+        reason: String,
+    },
+    MalformedImage(String),
+    Mapping(String),
+    ModifiedPatch {
+        address: usize,
+    },
+    NoMemoryCave {
+        hook: usize,
+        last_error: Option<String>,
+    },
+    NotInstalled {
+        address: usize,
+    },
+    OverlappingPatch {
+        first: usize,
+        second: usize,
+    },
+    PatchInUse {
+        address: usize,
+    },
+    PatternNotFound {
+        pattern: String,
+    },
+    Protection {
+        address: usize,
+        error: String,
+    },
+    RelativeJumpOutOfRange {
+        next_instruction: usize,
+        destination: usize,
+    },
+    SectionNotFound {
+        name: String,
+    },
+    ShortJumpOutOfRange {
+        next_instruction: usize,
+        destination: usize,
+    },
+    SourceChanged {
+        address: usize,
+    },
+    SplitInstruction {
+        address: usize,
+        size: usize,
+        instruction_end: usize,
+    },
+    ThreadSuspension {
+        error: String,
+    },
+    TooSmallForJump {
+        address: usize,
+        size: usize,
+    },
+    TrampolineTooLarge {
+        size: usize,
+        capacity: usize,
+    },
+    UnexpectedBytes {
+        address: usize,
+        expected: String,
+        found: Vec<u8>,
+    },
+    UnexpectedResume {
+        hook: usize,
+        resume: usize,
+    },
+    UnexpectedTrampolineSize {
+        expected: usize,
+        actual: usize,
+    },
+    UnknownInstruction {
+        address: usize,
+    },
+    UnrelocatableInstruction {
+        address: usize,
+        reason: &'static str,
+    },
+    UnresolvedSite {
+        site: String,
+        reason: String,
+    },
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AddressOverflow => write!(f, "address calculation overflowed"),
+            Self::AlreadyFinalized => write!(f, "patch installation has already been finalized"),
+            Self::AmbiguousPattern {
+                pattern,
+                count,
+                matches,
+            } => {
+                write!(f, "pattern `{pattern}` matched {count} locations:")?;
+                for address in matches {
+                    write!(f, " {address:#x}")?;
+                }
+                if *count > matches.len() {
+                    write!(f, " ...")?;
+                }
+                Ok(())
+            }
+            Self::DryRunOutput { path, error } => {
+                write!(f, "unable to write the patch plan to {path}: {error}")
+            }
+            Self::DuplicateExit { address, name } => {
+                write!(f, "the hook at {address:#x} registers the exit `{name}` twice")
+            }
+            Self::EmptyPatch => write!(f, "a patch must overwrite at least one byte"),
+            Self::EmptyTrampoline { address } => {
+                write!(f, "the detour at {address:#x} has an empty trampoline")
+            }
+            Self::ForeignPatch { address, code } => {
+                write!(f, "another mod already placed {code} at {address:#x}")
+            }
+            Self::InstallFailed {
+                feature: Some(feature),
+                address,
+                error,
+            } => write!(
+                f,
+                "unable to install the {feature} patch at {address:#x}: {error}"
+            ),
+            Self::InstallFailed {
+                feature: None,
+                address,
+                error,
+            } => write!(f, "unable to install the patch at {address:#x}: {error}"),
+            Self::InstructionCache { address, error } => write!(
+                f,
+                "unable to flush the instruction cache at {address:#x}: {error}"
+            ),
+            Self::InvalidPattern { pattern, reason } => {
+                write!(f, "invalid pattern `{pattern}`: {reason}")
+            }
+            Self::MalformedImage(error) => write!(f, "unable to read the game image: {error}"),
+            Self::Mapping(error) => write!(f, "memory mapping failed: {error}"),
+            Self::ModifiedPatch { address } => write!(
+                f,
+                "the patch at {address:#x} was modified after it was installed"
+            ),
+            Self::NoMemoryCave { hook, last_error } => {
+                write!(f, "no usable trampoline page found near {hook:#x}")?;
+                if let Some(error) = last_error {
+                    write!(f, ": {error}")?;
+                }
+                Ok(())
+            }
+            Self::NotInstalled { address } => {
+                write!(f, "the patch at {address:#x} has not been installed")
+            }
+            Self::OverlappingPatch { first, second } => write!(
+                f,
+                "patch at {second:#x} overlaps the patch prepared at {first:#x}"
+            ),
+            Self::PatchInUse { address } => write!(
+                f,
+                "a game thread stopped inside the patch at {address:#x}, try again"
+            ),
+            Self::PatternNotFound { pattern } => {
+                write!(f, "pattern `{pattern}` did not match anything")
+            }
+            Self::Protection { address, error } => {
+                write!(f, "unable to change protection at {address:#x}: {error}")
+            }
+            Self::RelativeJumpOutOfRange {
+                next_instruction,
+                destination,
+            } => write!(
+                f,
+                "relative jump from {next_instruction:#x} to {destination:#x} exceeds 32 bits"
+            ),
+            Self::SectionNotFound { name } => {
+                write!(f, "the game image has no `{name}` section")
+            }
+            Self::ShortJumpOutOfRange {
+                next_instruction,
+                destination,
+            } => write!(
+                f,
+                "short jump from {next_instruction:#x} to {destination:#x} exceeds 8 bits"
+            ),
+            Self::SourceChanged { address } => write!(
+                f,
+                "patch source at {address:#x} changed while patches were being prepared"
+            ),
+            Self::SplitInstruction {
+                address,
+                size,
+                instruction_end,
+            } => write!(
+                f,
+                "overwriting {size} bytes at {address:#x} splits the instruction ending at {instruction_end:#x}"
+            ),
+            Self::ThreadSuspension { error } => {
+                write!(f, "unable to suspend the game threads: {error}")
+            }
+            Self::TooSmallForJump { address, size } => write!(
+                f,
+                "overwriting {size} bytes at {address:#x} leaves no room for a {NEAR_JUMP_SIZE}-byte jump"
+            ),
+            Self::TrampolineTooLarge { size, capacity } => write!(
+                f,
+                "trampoline requires {size} bytes but a page holds only {capacity}"
+            ),
+            Self::UnexpectedBytes {
+                address,
+                expected,
+                found,
+            } => write!(
+                f,
+                "expected `{expected}` at {address:#x}, found `{}`",
+                plan::hex_bytes(found)
+            ),
+            Self::UnexpectedResume { hook, resume } => write!(
+                f,
+                "resume address {resume:#x} does not follow the hook at {hook:#x}"
+            ),
+            Self::UnexpectedTrampolineSize { expected, actual } => write!(
+                f,
+                "trampoline builder produced {actual} bytes instead of {expected}"
+            ),
+            Self::UnknownInstruction { address } => {
+                write!(f, "unable to decode the instruction at {address:#x}")
+            }
+            Self::UnrelocatableInstruction { address, reason } => write!(
+                f,
+                "the instruction at {address:#x} cannot be moved to a trampoline: it {reason}"
+            ),
+            Self::UnresolvedSite { site, reason } => {
+                write!(f, "the profile could not locate `{site}`: {reason}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+#[derive(Default)]
+struct PatchSession {
+    pages: Vec<MutableCodePage>,
+    pending: Vec<PendingPatch>,
+    next_id: usize,
+    feature: Option<&'static str>,
+}
+
+struct MutableCodePage {
+    mapping: MmapMut,
+    used: usize,
+}
+
+struct PendingPatch {
+    id: usize,
+    feature: Option<&'static str>,
+    address: usize,
+    overwritten: Vec<u8>,
+    replacement: Vec<u8>,
+    trampoline: Option<TrampolineCode>,
+    enabled: bool,
+}
+
+/// A trampoline as it was written to its page.
+struct TrampolineCode {
+    address: usize,
+    code: Vec<u8>,
+}
+
+struct PatchRuntime {
+    pages: Vec<Mmap>,
+    patches: Vec<PendingPatch>,
+}
+
+struct PatchManager {
+    session: Option<PatchSession>,
+    runtime: Option<PatchRuntime>,
+}
+
+impl PatchManager {
+    fn new() -> Self {
+        Self {
+            session: Some(PatchSession::default()),
+            runtime: None,
+        }
+    }
+
+    fn session_mut(&mut self) -> Result<&mut PatchSession, PatchError> {
+        if self.runtime.is_some() {
+            return Err(PatchError::AlreadyFinalized);
+        }
+
+        self.session.as_mut().ok_or(PatchError::AlreadyFinalized)
+    }
+}
+
+static PATCH_MANAGER: OnceLock<Mutex<PatchManager>> = OnceLock::new();
+
+fn patch_manager() -> MutexGuard<'static, PatchManager> {
+    PATCH_MANAGER
+        .get_or_init(|| Mutex::new(PatchManager::new()))
+        .lock()
+        .expect("patch manager mutex poisoned")
+}
+
+impl Patch {
+    /// Creates a patch at `address` so `function` can be run.
+    /// `size` determines how many bytes are overwritten for the call and must be at least five.
+    ///
+    /// The overwritten bytes must match `expected`, a pattern in the syntax of
+    /// [`scan::Pattern`] covering exactly `size` bytes. Without a pattern, they are
+    /// only checked for code left by another mod.
+    /// Sizes that end in the middle of an instruction are rejected; see [`Patch::hook_size`].
+    /// With `save_overwritten`, the overwritten instructions run before `function`. Their
+    /// RIP-relative operands and relative branches are rewritten to keep their targets.
+    ///
+    /// # Safety
+    /// It is the responsibility of the caller to ensure that the inserted function is compatible with the original ASM.
+    pub unsafe fn patch_call(
+        address: usize,
+        expected: Option<&str>,
+        function: *const (),
+        size: usize,
+        save_overwritten: bool,
+        allow_return: ReturnType,
+    ) -> Result<Self, PatchError> {
+        let function = function as usize;
+        Self::prepare_hook(address, expected, size, save_overwritten, move |asm| {
+            call_body(asm, function, allow_return)
+        })
+    }
+
+    /// Detours `size` bytes at `address` to a trampoline that runs the code emitted
+    /// by `body` and then resumes after the overwritten bytes, optionally replaying
+    /// them first.
+    unsafe fn prepare_hook<E>(
+        address: usize,
+        expected: Option<&str>,
+        size: usize,
+        save_overwritten: bool,
+        body: E,
+    ) -> Result<Self, PatchError>
+    where
+        E: Fn(&mut Asm),
+    {
+        patch_manager()
+            .session_mut()?
+            .hook(address, expected, size, save_overwritten, body)
+    }
+
+    /// Replaces `size` bytes at `address`, which must match `expected`, with a
+    /// near jump to `trampoline`.
+    ///
+    /// The trampoline is allocated within range of a 32-bit relative jump and
+    /// remains executable for the lifetime of the process. The trampoline must
+    /// transfer control to the appropriate continuation itself.
+    ///
+    /// # Safety
+    /// The caller must provide valid machine code and ensure every trampoline
+    /// exit preserves the surrounding function state.
+    #[allow(dead_code)]
+    pub unsafe fn detour(
+        address: usize,
+        expected: Option<&str>,
+        size: usize,
+        trampoline: &[u8],
+    ) -> Result<Self, PatchError> {
+        let trampoline = trampoline.to_vec();
+        Self::detour_with(address, expected, size, trampoline.len(), move |_| {
+            Ok(trampoline.clone())
+        })
+    }
+
+    #[allow(dead_code)]
+    pub(crate) unsafe fn detour_with<F>(
+        address: usize,
+        expected: Option<&str>,
+        size: usize,
+        trampoline_size: usize,
+        build: F,
+    ) -> Result<Self, PatchError>
+    where
+        F: Fn(usize) -> Result<Vec<u8>, PatchError>,
+    {
+        check_jump_room(address, size)?;
+        if trampoline_size == 0 {
+            return Err(PatchError::EmptyTrampoline { address });
+        }
+        check_expected_bytes(address, expected, size)?;
+        check_instruction_boundary(address, size)?;
+
+        let overwritten = slice::from_raw_parts(address as *const u8, size).to_vec();
+        Self::prepare_detour(address, overwritten, trampoline_size, build)
+    }
+
+    unsafe fn prepare_detour<F>(
+        address: usize,
+        overwritten: Vec<u8>,
+        trampoline_size: usize,
+        build: F,
+    ) -> Result<Self, PatchError>
+    where
+        F: Fn(usize) -> Result<Vec<u8>, PatchError>,
+    {
+        patch_manager()
+            .session_mut()?
+            .detour(address, overwritten, trampoline_size, build)
+    }
+
+    /// Replaces the bytes at `address`, which must match `expected`, with `data`.
+    pub unsafe fn overwrite(
+        address: usize,
+        expected: Option<&str>,
+        data: &[u8],
+    ) -> Result<Self, PatchError> {
+        if data.is_empty() {
+            return Err(PatchError::EmptyPatch);
+        }
+        check_expected_bytes(address, expected, data.len())?;
+
+        let overwritten = slice::from_raw_parts(address as *const u8, data.len()).to_vec();
+        let mut manager = patch_manager();
+        let session = manager.session_mut()?;
+        session.ensure_patch_does_not_overlap(address, data.len())?;
+        let handle = session.push(address, overwritten, data.to_vec(), None);
+
+        Ok(Self {
+            trampoline: None,
+            handle,
+        })
+    }
+
+    /// Returns the smallest number of bytes at `address` that covers whole
+    /// instructions and leaves room for the near jump of a call patch or detour.
+    ///
+    /// # Safety
+    /// `address` must point to readable machine code.
+    #[allow(dead_code)]
+    pub(crate) unsafe fn hook_size(address: usize) -> Result<usize, PatchError> {
+        let code = slice::from_raw_parts(
+            address as *const u8,
+            NEAR_JUMP_SIZE + decode::MAX_INSTRUCTION_LENGTH - 1,
+        );
+        decode::covering_length(code, NEAR_JUMP_SIZE).map_err(|offset| {
+            PatchError::UnknownInstruction {
+                address: address + offset,
+            }
+        })
+    }
+
+    pub(crate) fn trampoline_address(&self) -> Option<usize> {
+        self.trampoline.map(|allocation| allocation.address)
+    }
+
+    pub(crate) fn handle(&self) -> PatchHandle {
+        self.handle
+    }
+}
+
+impl PatchHandle {
+    /// Restores the bytes the patch replaced.
+    ///
+    /// Does nothing if the patch is already disabled. The other threads of the
+    /// process are suspended while the bytes are written, see
+    /// [`PatchError::PatchInUse`] for when they are left alone.
+    ///
+    /// # Safety
+    /// No code may jump into the patched bytes past their first instruction.
+    pub(crate) unsafe fn disable(self) -> Result<(), PatchError> {
+        set_patch_enabled(self, false)
+    }
+
+    /// Reinstalls a patch disabled by [`PatchHandle::disable`].
+    ///
+    /// Does nothing if the patch is already enabled. The other threads of the
+    /// process are suspended while the bytes are written.
+    ///
+    /// # Safety
+    /// No code may jump into the patched bytes past their first instruction, and
+    /// no thread may be inside a function called from the middle of them, as it
+    /// would return into the patch.
+    pub(crate) unsafe fn enable(self) -> Result<(), PatchError> {
+        set_patch_enabled(self, true)
+    }
+
+    /// Reports whether the patch is currently installed.
+    pub(crate) fn is_enabled(self) -> bool {
+        patch_manager()
+            .runtime
+            .as_ref()
+            .is_some_and(|runtime| runtime.is_enabled(self))
+    }
+}
+
+impl PatchSession {
+    fn push(
+        &mut self,
+        address: usize,
+        overwritten: Vec<u8>,
+        replacement: Vec<u8>,
+        trampoline: Option<TrampolineCode>,
+    ) -> PatchHandle {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.pending.push(PendingPatch {
+            id,
+            feature: self.feature,
+            address,
+            overwritten,
+            replacement,
+            trampoline,
+            enabled: false,
+        });
+
+        PatchHandle { id, address }
+    }
+
+    /// Withdraws the patches prepared for `feature` and releases their trampolines.
+    ///
+    /// Pages are only ever filled from the end of their last trampoline, so a page
+    /// is unmapped once it holds no trampoline, while slots freed in front of a
+    /// remaining trampoline stay allocated until the page is released.
+    fn discard(&mut self, feature: &'static str) {
+        self.pending.retain(|patch| patch.feature != Some(feature));
+
+        let pending = &self.pending;
+        self.pages.retain_mut(|page| {
+            let start = page.mapping.as_ptr() as usize;
+            page.used = trampolines_end(pending, start, page.mapping.len());
+            page.used > 0
+        });
+    }
+
+    /// Prepares a hook over `size` bytes at `address` whose trampoline runs the code
+    /// emitted by `body` and resumes after the hook; see [`Patch::prepare_hook`].
+    unsafe fn hook<E>(
+        &mut self,
+        address: usize,
+        expected: Option<&str>,
+        size: usize,
+        save_overwritten: bool,
+        body: E,
+    ) -> Result<Patch, PatchError>
+    where
+        E: Fn(&mut Asm),
+    {
+        check_jump_room(address, size)?;
+        check_expected_bytes(address, expected, size)?;
+        check_instruction_boundary(address, size)?;
+
+        let overwritten = slice::from_raw_parts(address as *const u8, size).to_vec();
+        let continuation = address
+            .checked_add(size)
+            .ok_or(PatchError::AddressOverflow)?;
+        let source = overwritten.clone();
+        let build = move |trampoline_address| {
+            let mut asm = Asm::new();
+            if save_overwritten {
+                asm.bytes(&relocate::relocate(&source, address, trampoline_address)?);
+            }
+            body(&mut asm);
+            asm.jmp_to(continuation);
+            asm.finish(trampoline_address)
+        };
+        // Only the displacements depend on where the trampoline ends up.
+        let trampoline_size = build(address)?.len();
+
+        self.detour(address, overwritten, trampoline_size, build)
+    }
+
+    /// Prepares a near jump over `overwritten` at `address` to a trampoline built
+    /// by `build` in one of the session's pages.
+    unsafe fn detour<F>(
+        &mut self,
+        address: usize,
+        overwritten: Vec<u8>,
+        trampoline_size: usize,
+        build: F,
+    ) -> Result<Patch, PatchError>
+    where
+        F: Fn(usize) -> Result<Vec<u8>, PatchError>,
+    {
+        self.ensure_patch_does_not_overlap(address, overwritten.len())?;
+
+        let trampoline_address = self.allocate_trampoline(address, trampoline_size, &build)?;
+        let replacement = build_near_jump(address, trampoline_address, overwritten.len())?;
+        let trampoline = TrampolineCode {
+            address: trampoline_address,
+            code: slice::from_raw_parts(trampoline_address as *const u8, trampoline_size).to_vec(),
+        };
+        let handle = self.push(address, overwritten, replacement, Some(trampoline));
+
+        Ok(Patch {
+            trampoline: Some(CodeAllocation {
+                address: trampoline_address,
+            }),
+            handle,
+        })
+    }
+
+    fn ensure_patch_does_not_overlap(&self, address: usize, size: usize) -> Result<(), PatchError> {
+        if size == 0 {
+            return Err(PatchError::EmptyPatch);
+        }
+
+        let end = address
+            .checked_add(size)
+            .ok_or(PatchError::AddressOverflow)?;
+        for patch in &self.pending {
+            let patch_end = patch
+                .address
+                .checked_add(patch.replacement.len())
+                .ok_or(PatchError::AddressOverflow)?;
+            if address < patch_end && patch.address < end {
+                return Err(PatchError::OverlappingPatch {
+                    first: patch.address,
+                    second: address,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn allocate_trampoline<F>(
+        &mut self,
+        hook: usize,
+        trampoline_size: usize,
+        build: &F,
+    ) -> Result<usize, PatchError>
+    where
+        F: Fn(usize) -> Result<Vec<u8>, PatchError>,
+    {
+        let page_size = MmapOptions::page_size();
+        if trampoline_size > page_size {
+            return Err(PatchError::TrampolineTooLarge {
+                size: trampoline_size,
+                capacity: page_size,
+            });
+        }
+
+        let hook_next_instruction = hook
+            .checked_add(NEAR_JUMP_SIZE)
+            .ok_or(PatchError::AddressOverflow)?;
+
+        for page in &mut self.pages {
+            let Some(range) = slot_range(page.used, trampoline_size, page_size) else {
+                continue;
+            };
+            let trampoline_address = (page.mapping.as_ptr() as usize)
+                .checked_add(range.start)
+                .ok_or(PatchError::AddressOverflow)?;
+            if relative_offset(hook_next_instruction, trampoline_address).is_err() {
+                continue;
+            }
+
+            let code = match build(trampoline_address) {
+                Ok(code) => code,
+                Err(PatchError::RelativeJumpOutOfRange { .. }) => continue,
+                Err(error) => return Err(error),
+            };
+            validate_trampoline_size(trampoline_size, code.len())?;
+            page.mapping[range.clone()].copy_from_slice(&code);
+            page.used = range.end;
+            return Ok(trampoline_address);
+        }
+
+        self.allocate_trampoline_page(hook, trampoline_size, build)
+    }
+
+    fn allocate_trampoline_page<F>(
+        &mut self,
+        hook: usize,
+        trampoline_size: usize,
+        build: &F,
+    ) -> Result<usize, PatchError>
+    where
+        F: Fn(usize) -> Result<Vec<u8>, PatchError>,
+    {
+        let page_size = MmapOptions::page_size();
+        let granularity = MmapOptions::allocation_granularity();
+        let hook_next_instruction = hook
+            .checked_add(NEAR_JUMP_SIZE)
+            .ok_or(PatchError::AddressOverflow)?;
+        let (minimum, maximum) = candidate_bounds(hook_next_instruction, page_size, granularity)?;
+        let mut last_error = None;
+
+        for candidate in CandidateAddresses::new(hook, minimum, maximum, granularity)? {
+            if let Some(address) = self.try_allocate_page(
+                candidate,
+                hook_next_instruction,
+                trampoline_size,
+                build,
+                &mut last_error,
+            )? {
+                return Ok(address);
+            }
+        }
+
+        Err(PatchError::NoMemoryCave { hook, last_error })
+    }
+
+    fn try_allocate_page<F>(
+        &mut self,
+        candidate: usize,
+        hook_next_instruction: usize,
+        trampoline_size: usize,
+        build: &F,
+        last_error: &mut Option<String>,
+    ) -> Result<Option<usize>, PatchError>
+    where
+        F: Fn(usize) -> Result<Vec<u8>, PatchError>,
+    {
+        let page_size = MmapOptions::page_size();
+        let options =
+            MmapOptions::new(page_size).map_err(|error| PatchError::Mapping(error.to_string()))?;
+        let mut mapping = match options.with_address(candidate).map_mut() {
+            Ok(mapping) => mapping,
+            Err(error) => {
+                *last_error = Some(error.to_string());
+                return Ok(None);
+            }
+        };
+
+        let actual_address = mapping.as_ptr() as usize;
+        if actual_address != candidate {
+            *last_error = Some(format!(
+                "requested {candidate:#x}, but the mapping was placed at {actual_address:#x}"
+            ));
+            return Ok(None);
+        }
+        if relative_offset(hook_next_instruction, actual_address).is_err() {
+            *last_error = Some(format!(
+                "mapping at {actual_address:#x} was outside rel32 range"
+            ));
+            return Ok(None);
+        }
+
+        let code = match build(actual_address) {
+            Ok(code) => code,
+            Err(PatchError::RelativeJumpOutOfRange { .. }) => {
+                *last_error = Some(format!(
+                    "trampoline exits were unreachable from {actual_address:#x}"
+                ));
+                return Ok(None);
+            }
+            Err(error) => return Err(error),
+        };
+        validate_trampoline_size(trampoline_size, code.len())?;
+        mapping[..trampoline_size].copy_from_slice(&code);
+        self.pages.push(MutableCodePage {
+            mapping,
+            used: trampoline_size,
+        });
+
+        Ok(Some(actual_address))
+    }
+}
+
+/// Checks that the `size` bytes at `address` match the `expected` pattern.
+///
+/// Without a pattern, the bytes are only checked for code left by another mod.
+unsafe fn check_expected_bytes(
+    address: usize,
+    expected: Option<&str>,
+    size: usize,
+) -> Result<(), PatchError> {
+    let Some(expected) = expected else {
+        return match conflict::foreign_code(address, size) {
+            Some(code) => Err(PatchError::ForeignPatch { address, code }),
+            None => Ok(()),
+        };
+    };
+    let pattern = scan::Pattern::parse(expected)?;
+    if pattern.len() != size {
+        return Err(PatchError::InvalidPattern {
+            pattern: expected.to_string(),
+            reason: format!(
+                "it covers {} bytes, but the patch replaces {size}",
+                pattern.len()
+            ),
+        });
+    }
+
+    let found = slice::from_raw_parts(address as *const u8, size);
+    // Bytes that only match wildcards may still belong to another mod.
+    if pattern.matches(found) && !pattern.starts_with_wildcard() {
+        return Ok(());
+    }
+    if let Some(code) = conflict::foreign_code(address, size) {
+        return Err(PatchError::ForeignPatch { address, code });
+    }
+    if pattern.matches(found) {
+        Ok(())
+    } else {
+        Err(PatchError::UnexpectedBytes {
+            address,
+            expected: pattern.to_string(),
+            found: found.to_vec(),
+        })
+    }
+}
+
+/// Checks that `size` bytes at `address` leave room for the near jump of a detour.
+fn check_jump_room(address: usize, size: usize) -> Result<(), PatchError> {
+    if size < NEAR_JUMP_SIZE {
+        Err(PatchError::TooSmallForJump { address, size })
+    } else {
+        Ok(())
+    }
+}
+
+/// Checks that overwriting `size` bytes at `address` replaces whole instructions.
+unsafe fn check_instruction_boundary(address: usize, size: usize) -> Result<(), PatchError> {
+    let code = slice::from_raw_parts(
+        address as *const u8,
+        size + decode::MAX_INSTRUCTION_LENGTH - 1,
+    );
+    match decode::covering_length(code, size) {
+        Ok(length) if length == size => Ok(()),
+        Ok(length) => Err(PatchError::SplitInstruction {
+            address,
+            size,
+            instruction_end: address + length,
+        }),
+        Err(offset) => Err(PatchError::UnknownInstruction {
+            address: address + offset,
+        }),
+    }
+}
+
+/// Emits the call of a patch call's `function`, preserving the volatile registers
+/// except for the one carrying the return value.
+fn call_body(asm: &mut Asm, function: usize, allow_return: ReturnType) {
+    // An odd number of pushes is evened out to keep the stack 16-byte aligned.
+    let needs_alignment = allow_return != ReturnType::Rax;
+    if needs_alignment {
+        asm.push(Rax);
+    }
+    save_registers(asm);
+    if needs_alignment {
+        asm.sub(Rsp, 0x8);
+    }
+    if allow_return != ReturnType::Xmm0 {
+        asm.movdqu_store(Rsp.into(), Xmm0);
+    }
+
+    asm.call_abs(function);
+
+    if allow_return != ReturnType::Xmm0 {
+        asm.movdqu_load(Xmm0, Rsp.into());
+    }
+    if needs_alignment {
+        asm.add(Rsp, 0x8);
+    }
+    load_registers(asm);
+    if needs_alignment {
+        asm.pop(Rax);
+    }
+}
+
+const SAVED_REGISTERS: [Reg; 6] = [Rcx, Rdx, R8, R9, R10, R11];
+const SAVED_XMM: [Xmm; 5] = [Xmm1, Xmm2, Xmm3, Xmm4, Xmm5];
+
+/// Saves the volatile registers other than RAX and XMM0, leaving a slot for XMM0
+/// at RSP.
+fn save_registers(asm: &mut Asm) {
+    for register in SAVED_REGISTERS {
+        asm.push(register);
+    }
+    asm.sub(Rsp, 0x60);
+    for (slot, register) in (1..).zip(SAVED_XMM) {
+        asm.movdqu_store(Rsp + 0x10 * slot, register);
+    }
+}
+
+/// Restores the registers saved by [`save_registers`].
+fn load_registers(asm: &mut Asm) {
+    for (slot, register) in (1..).zip(SAVED_XMM) {
+        asm.movdqu_load(register, Rsp + 0x10 * slot);
+    }
+    asm.add(Rsp, 0x60);
+    for register in SAVED_REGISTERS.into_iter().rev() {
+        asm.pop(register);
+    }
+}
+
+/// Returns the offset just past the last trampoline of `patches` in the page of
+/// `size` bytes at `start`, or 0 if the page holds none of them.
+fn trampolines_end(patches: &[PendingPatch], start: usize, size: usize) -> usize {
+    patches
+        .iter()
+        .filter_map(|patch| patch.trampoline.as_ref())
+        .filter(|trampoline| (start..start + size).contains(&trampoline.address))
+        .map(|trampoline| trampoline.address - start + trampoline.code.len())
+        .max()
+        .unwrap_or(0)
+}
+
+fn validate_trampoline_size(expected: usize, actual: usize) -> Result<(), PatchError> {
+    if expected == actual {
+        Ok(())
+    } else {
+        Err(PatchError::UnexpectedTrampolineSize { expected, actual })
+    }
+}
+
+fn slot_range(used: usize, size: usize, capacity: usize) -> Option<Range<usize>> {
+    let start = align_up(used, TRAMPOLINE_ALIGNMENT)?;
+    let end = start.checked_add(size)?;
+    (end <= capacity).then_some(start..end)
+}
+
+fn align_down(value: usize, alignment: usize) -> usize {
+    value - value % alignment
+}
+
+fn align_up(value: usize, alignment: usize) -> Option<usize> {
+    let remainder = value % alignment;
+    if remainder == 0 {
+        Some(value)
+    } else {
+        value.checked_add(alignment - remainder)
+    }
+}
+
+struct CandidateAddresses {
+    hook: usize,
+    minimum: usize,
+    maximum: usize,
+    granularity: usize,
+    lower: Option<usize>,
+    upper: Option<usize>,
+}
+
+impl CandidateAddresses {
+    fn new(
+        hook: usize,
+        minimum: usize,
+        maximum: usize,
+        granularity: usize,
+    ) -> Result<Self, PatchError> {
+        let lower = align_down(hook.min(maximum), granularity);
+        let lower = (lower >= minimum).then_some(lower);
+        let upper = align_up(hook.max(minimum), granularity).ok_or(PatchError::AddressOverflow)?;
+        let mut upper = (upper <= maximum).then_some(upper);
+
+        if upper == lower {
+            upper = upper
+                .and_then(|candidate| candidate.checked_add(granularity))
+                .filter(|candidate| *candidate <= maximum);
+        }
+
+        Ok(Self {
+            hook,
+            minimum,
+            maximum,
+            granularity,
+            lower,
+            upper,
+        })
+    }
+}
+
+impl Iterator for CandidateAddresses {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let take_lower = match (self.lower, self.upper) {
+            (Some(lower), Some(upper)) => lower.abs_diff(self.hook) <= upper.abs_diff(self.hook),
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => return None,
+        };
+
+        if take_lower {
+            let candidate = self.lower?;
+            self.lower = candidate
+                .checked_sub(self.granularity)
+                .filter(|next| *next >= self.minimum);
+            Some(candidate)
+        } else {
+            let candidate = self.upper?;
+            self.upper = candidate
+                .checked_add(self.granularity)
+                .filter(|next| *next <= self.maximum);
+            Some(candidate)
+        }
+    }
+}
+
+fn candidate_bounds(
+    next_instruction: usize,
+    page_size: usize,
+    granularity: usize,
+) -> Result<(usize, usize), PatchError> {
+    let minimum = (next_instruction as i128 + i32::MIN as i128).max(0) as usize;
+    let maximum_mapping_base = usize::MAX
+        .checked_sub(page_size.saturating_sub(1))
+        .ok_or(PatchError::AddressOverflow)?;
+    let maximum =
+        (next_instruction as i128 + i32::MAX as i128).min(maximum_mapping_base as i128) as usize;
+    let minimum = align_up(minimum, granularity).ok_or(PatchError::AddressOverflow)?;
+    let maximum = align_down(maximum, granularity);
+
+    if minimum > maximum {
+        Err(PatchError::NoMemoryCave {
+            hook: next_instruction.saturating_sub(NEAR_JUMP_SIZE),
+            last_error: None,
+        })
+    } else {
+        Ok((minimum, maximum))
+    }
+}
+
+fn build_near_jump(source: usize, destination: usize, size: usize) -> Result<Vec<u8>, PatchError> {
+    if size < NEAR_JUMP_SIZE {
+        return Err(PatchError::UnexpectedTrampolineSize {
+            expected: NEAR_JUMP_SIZE,
+            actual: size,
+        });
+    }
+
+    let next_instruction = source
+        .checked_add(NEAR_JUMP_SIZE)
+        .ok_or(PatchError::AddressOverflow)?;
+    let displacement = relative_offset(next_instruction, destination)?;
+    let mut patch = Vec::with_capacity(size);
+    patch.push(NEAR_JUMP);
+    patch.extend_from_slice(&displacement.to_le_bytes());
+    patch.resize(size, 0x90);
+    Ok(patch)
+}
+
+pub(crate) fn relative_offset(
+    next_instruction: usize,
+    destination: usize,
+) -> Result<i32, PatchError> {
+    let displacement = destination as i128 - next_instruction as i128;
+    i32::try_from(displacement).map_err(|_| PatchError::RelativeJumpOutOfRange {
+        next_instruction,
+        destination,
+    })
+}
+
+struct SourceProtection {
+    address: usize,
+    old: <Native as Memory>::Protection,
+}
+
+unsafe fn protect_patch_sources(
+    patches: &[PendingPatch],
+) -> Result<Vec<SourceProtection>, PatchError> {
+    let page_size = MmapOptions::page_size();
+    let mut source_pages = BTreeSet::new();
+
+    for patch in patches {
+        let last_byte = patch
+            .address
+            .checked_add(patch.replacement.len() - 1)
+            .ok_or(PatchError::AddressOverflow)?;
+        let first_page = align_down(patch.address, page_size);
+        let last_page = align_down(last_byte, page_size);
+        let mut page = first_page;
+
+        loop {
+            source_pages.insert(page);
+            if page == last_page {
+                break;
+            }
+            page = page
+                .checked_add(page_size)
+                .ok_or(PatchError::AddressOverflow)?;
+        }
+    }
+
+    let mut protections = Vec::with_capacity(source_pages.len());
+    for address in source_pages {
+        match Native::make_writable(address, page_size) {
+            Ok(old) => protections.push(SourceProtection { address, old }),
+            Err(error) => {
+                restore_patch_sources(&protections)?;
+                return Err(PatchError::Protection { address, error });
+            }
+        }
+    }
+
+    Ok(protections)
+}
+
+unsafe fn restore_patch_sources(protections: &[SourceProtection]) -> Result<(), PatchError> {
+    let page_size = MmapOptions::page_size();
+    let mut first_error = None;
+
+    for protection in protections.iter().rev() {
+        if let Err(error) = Native::restore(protection.address, page_size, protection.old) {
+            first_error.get_or_insert(PatchError::Protection {
+                address: protection.address,
+                error,
+            });
+        }
+    }
+
+    match first_error {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
+
+unsafe fn validate_patch_sources(patches: &[PendingPatch]) -> Result<(), PatchError> {
+    for patch in patches {
+        let current = slice::from_raw_parts(patch.address as *const u8, patch.overwritten.len());
+        if current != patch.overwritten {
+            let error = match conflict::foreign_code(patch.address, patch.overwritten.len()) {
+                Some(code) => PatchError::ForeignPatch {
+                    address: patch.address,
+                    code,
+                },
+                None => PatchError::SourceChanged {
+                    address: patch.address,
+                },
+            };
+            return Err(patch_failed(patch, error));
+        }
+    }
+
+    Ok(())
+}
+
+unsafe fn flush_instruction_cache(address: usize, size: usize) -> Result<(), PatchError> {
+    Native::flush_instruction_cache(address, size)
+        .map_err(|error| PatchError::InstructionCache { address, error })
+}
+
+/// Attributes an installation `error` to `patch`.
+fn patch_failed(patch: &PendingPatch, error: PatchError) -> PatchError {
+    PatchError::InstallFailed {
+        feature: patch.feature,
+        address: patch.address,
+        error: Box::new(error),
+    }
+}
+
+/// Installs `patches` one feature at a time, returning the installed patches and
+/// the features that were left out.
+///
+/// # Safety
+/// The patch sources must be writable.
+unsafe fn install_by_feature<F>(
+    patches: Vec<PendingPatch>,
+    flush: F,
+) -> (Vec<PendingPatch>, Vec<FailedFeature>)
+where
+    F: Fn(usize, usize) -> Result<(), PatchError>,
+{
+    let mut installed = Vec::with_capacity(patches.len());
+    let mut failures = Vec::new();
+
+    for mut group in group_by_feature(patches) {
+        let feature = group[0].feature;
+        match validate_patch_sources(&group).and_then(|()| write_patches(&mut group, &flush)) {
+            Ok(()) => installed.append(&mut group),
+            Err(error) => failures.push(FailedFeature { feature, error }),
+        }
+    }
+
+    (installed, failures)
+}
+
+/// Splits `patches` by feature, keeping the order in which features first appear.
+fn group_by_feature(patches: Vec<PendingPatch>) -> Vec<Vec<PendingPatch>> {
+    let mut groups: Vec<Vec<PendingPatch>> = Vec::new();
+    for patch in patches {
+        match groups
+            .iter_mut()
+            .find(|group| group[0].feature == patch.feature)
+        {
+            Some(group) => group.push(patch),
+            None => groups.push(vec![patch]),
+        }
+    }
+    groups
+}
+
+/// Writes the replacement of every patch and flushes it with `flush`.
+///
+/// If a flush fails, the original bytes of every patch are written back and the
+/// error names the patch that failed.
+///
+/// # Safety
+/// The patch sources must be writable.
+unsafe fn write_patches<F>(patches: &mut [PendingPatch], flush: F) -> Result<(), PatchError>
+where
+    F: Fn(usize, usize) -> Result<(), PatchError>,
+{
+    for patch in patches.iter_mut() {
+        std::ptr::copy_nonoverlapping(
+            patch.replacement.as_ptr(),
+            patch.address as *mut u8,
+            patch.replacement.len(),
+        );
+        patch.enabled = true;
+    }
+
+    for patch in patches.iter() {
+        if let Err(error) = flush(patch.address, patch.replacement.len()) {
+            let error = patch_failed(patch, error);
+            restore_originals(patches, &flush);
+            return Err(error);
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes back the original bytes of every enabled patch.
+///
+/// Failures to flush are only logged, as the original bytes are in place either way.
+///
+/// # Safety
+/// The patch sources must be writable.
+unsafe fn restore_originals<F>(patches: &mut [PendingPatch], flush: F)
+where
+    F: Fn(usize, usize) -> Result<(), PatchError>,
+{
+    for patch in patches.iter_mut().filter(|patch| patch.enabled) {
+        std::ptr::copy_nonoverlapping(
+            patch.overwritten.as_ptr(),
+            patch.address as *mut u8,
+            patch.overwritten.len(),
+        );
+        patch.enabled = false;
+        if let Err(error) = flush(patch.address, patch.overwritten.len()) {
+            log::error!("Rolled back the patch at {:#x}, but {error}", patch.address);
+        }
+    }
+}
+
+unsafe fn set_patch_enabled(handle: PatchHandle, enabled: bool) -> Result<(), PatchError> {
+    match patch_manager().runtime.as_mut() {
+        Some(runtime) => runtime.set_enabled(handle, enabled),
+        None => Err(PatchError::NotInstalled {
+            address: handle.address,
+        }),
+    }
+}
+
+impl PatchRuntime {
+    /// Writes the replacement or the original bytes of the patch behind `handle`.
+    ///
+    /// # Safety
+    /// No other thread may execute the patched instructions while they are replaced.
+    unsafe fn set_enabled(&mut self, handle: PatchHandle, enabled: bool) -> Result<(), PatchError> {
+        let patch = self
+            .patches
+            .iter_mut()
+            .find(|patch| patch.id == handle.id)
+            .ok_or(PatchError::NotInstalled {
+                address: handle.address,
+            })?;
+        if patch.enabled == enabled {
+            return Ok(());
+        }
+
+        let (expected, bytes) = if enabled {
+            (&patch.overwritten, &patch.replacement)
+        } else {
+            (&patch.replacement, &patch.overwritten)
+        };
+        let protections = protect_patch_sources(slice::from_ref(patch))?;
+        let replaced = replace_code(patch.address, expected, bytes);
+        restore_patch_sources(&protections)?;
+        replaced?;
+
+        patch.enabled = enabled;
+        Ok(())
+    }
+
+    fn is_enabled(&self, handle: PatchHandle) -> bool {
+        self.patches
+            .iter()
+            .find(|patch| patch.id == handle.id)
+            .is_some_and(|patch| patch.enabled)
+    }
+}
+
+/// Replaces the `expected` bytes at `address` with `bytes` while the other
+/// threads of the process are suspended.
+///
+/// Fails with [`PatchError::PatchInUse`] if a thread stopped inside the bytes past
+/// their first instruction, as it would resume in the middle of the new code.
+///
+/// # Safety
+/// The bytes must be writable. Nothing may allocate while the threads are
+/// suspended, see [`Memory::suspend_other_threads`].
+unsafe fn replace_code(address: usize, expected: &[u8], bytes: &[u8]) -> Result<(), PatchError> {
+    let threads =
+        Native::suspend_other_threads().map_err(|error| PatchError::ThreadSuspension { error })?;
+    let current = slice::from_raw_parts(address as *const u8, expected.len());
+    if current != expected {
+        return Err(PatchError::ModifiedPatch { address });
+    }
+    if stopped_inside(address, bytes.len(), threads.iter().map(|(_, rip)| *rip)) {
+        return Err(PatchError::PatchInUse { address });
+    }
+    std::ptr::copy_nonoverlapping(bytes.as_ptr(), address as *mut u8, bytes.len());
+    drop(threads);
+
+    // A failed flush allocates its message, so it waits for the threads to resume.
+    flush_instruction_cache(address, bytes.len())
+}
+
+/// Reports whether an instruction pointer in `stopped_at` lies inside the `size`
+/// bytes at `address`, past the first one.
+fn stopped_inside(
+    address: usize,
+    size: usize,
+    mut stopped_at: impl Iterator<Item = usize>,
+) -> bool {
+    stopped_at.any(|rip| rip > address && rip - address < size)
+}
+
+/// Attributes the patches prepared from now on to `feature`.
+pub(crate) fn begin_feature(feature: &'static str) {
+    if let Ok(session) = patch_manager().session_mut() {
+        session.feature = Some(feature);
+    }
+}
+
+/// Returns the number of patches prepared for `feature` that have not been installed.
+pub(crate) fn prepared_patches(feature: &'static str) -> usize {
+    patch_manager().session_mut().map_or(0, |session| {
+        session
+            .pending
+            .iter()
+            .filter(|patch| patch.feature == Some(feature))
+            .count()
+    })
+}
+
+/// Withdraws every patch prepared for `feature` and releases its trampolines,
+/// so a feature that failed halfway leaves nothing behind.
+pub(crate) fn discard_feature(feature: &'static str) {
+    if let Ok(session) = patch_manager().session_mut() {
+        session.discard(feature);
+    }
+}
+
+/// Reports whether `feature` has patches that were installed, enabled or not.
+pub(crate) fn feature_installed(feature: &'static str) -> bool {
+    patch_manager().runtime.as_ref().is_some_and(|runtime| {
+        runtime
+            .patches
+            .iter()
+            .any(|patch| patch.feature == Some(feature))
+    })
+}
+
+/// Returns the code range of every installed patch, in the order it was prepared.
+pub(crate) fn patch_map() -> Vec<PatchRange> {
+    patch_manager()
+        .runtime
+        .iter()
+        .flat_map(|runtime| &runtime.patches)
+        .map(|patch| PatchRange {
+            address: patch.address,
+            size: patch.replacement.len(),
+            enabled: patch.enabled,
+        })
+        .collect()
+}
+
+/// A feature whose patches were left out when the others were installed.
+#[derive(Debug)]
+pub(crate) struct FailedFeature {
+    pub feature: Option<&'static str>,
+    pub error: PatchError,
+}
+
+/// Seals all prepared trampoline pages as executable and installs every prepared patch.
+///
+/// Patches are installed per feature: a feature with a patch that cannot be
+/// installed is left out entirely and returned, while the other features are
+/// still installed. Trampoline pages that only hold trampolines of features that
+/// were left out, or of every feature after a rollback, are released. With
+/// `dry_run`, nothing is installed. The prepared patches are logged and written to
+/// `dry_run` as JSON, and their trampoline pages are released.
+///
+/// # Safety
+/// No other thread may execute a patch source while its instructions are being replaced.
+pub(crate) unsafe fn finalize_patches(
+    dry_run: Option<&Path>,
+) -> Result<Vec<FailedFeature>, PatchError> {
+    let mut manager = patch_manager();
+    if manager.runtime.is_some() || manager.session.is_none() {
+        return Err(PatchError::AlreadyFinalized);
+    }
+
+    let session = manager.session.take().ok_or(PatchError::AlreadyFinalized)?;
+    if let Some(path) = dry_run {
+        plan::dump(&session.plan(), path)?;
+        log::info!(
+            "Dry run: {} patch(es) written to {}, nothing was installed",
+            session.pending.len(),
+            path.display()
+        );
+        return Ok(Vec::new());
+    }
+
+    let (runtime, failures) = session.install()?;
+    let page_count = runtime.pages.len();
+    let patch_count = runtime.patches.len();
+    manager.runtime = Some(runtime);
+
+    log::info!("Installed {patch_count} patch(es) using {page_count} shared trampoline page(s)");
+    Ok(failures)
+}
+
+impl PatchSession {
+    /// Seals the trampoline pages as executable and installs the prepared patches
+    /// feature by feature, returning the installed patches and the features that
+    /// were left out.
+    ///
+    /// # Safety
+    /// No other thread may execute a patch source while its instructions are being replaced.
+    unsafe fn install(self) -> Result<(PatchRuntime, Vec<FailedFeature>), PatchError> {
+        let mut executable_pages = Vec::with_capacity(self.pages.len());
+        for page in self.pages {
+            match page.mapping.make_exec() {
+                Ok(mapping) => executable_pages.push(mapping),
+                Err((_mapping, error)) => return Err(PatchError::Mapping(error.to_string())),
+            }
+        }
+
+        for page in &executable_pages {
+            flush_instruction_cache(page.as_ptr() as usize, page.len())?;
+        }
+
+        let protections = protect_patch_sources(&self.pending)?;
+        let flush = |address: usize, size: usize| flush_instruction_cache(address, size);
+        let (mut patches, failures) = install_by_feature(self.pending, flush);
+        if let Err(error) = restore_patch_sources(&protections) {
+            // Pages restored before the failure are no longer writable.
+            match protect_patch_sources(&patches) {
+                Ok(writable) => {
+                    restore_originals(&mut patches, flush);
+                    if let Err(restore_error) = restore_patch_sources(&writable) {
+                        log::error!("Rolled back every patch, but {restore_error}");
+                    }
+                    return Err(error);
+                }
+                Err(protect_error) => log::error!(
+                    "Patches stay installed because they cannot be rolled back: {error}; {protect_error}"
+                ),
+            }
+        }
+
+        // The trampolines of the features that were left out are never reached.
+        executable_pages
+            .retain(|page| trampolines_end(&patches, page.as_ptr() as usize, page.len()) > 0);
+
+        let runtime = PatchRuntime {
+            pages: executable_pages,
+            patches,
+        };
+        Ok((runtime, failures))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // MOV EAX, 0xefbeadde
+    const MOV_EAX: [u8; 5] = [0xb8, 0xde, 0xad, 0xbe, 0xef];
+
+    fn dummy() {
+        println!("Dummy function");
+    }
+
+    #[test]
+    fn rel32_boundaries_are_checked() {
+        let next_instruction = 0x2_0000_0000usize;
+
+        assert_eq!(
+            relative_offset(next_instruction, next_instruction - 0x8000_0000).unwrap(),
+            i32::MIN
+        );
+        assert_eq!(
+            relative_offset(next_instruction, next_instruction + 0x7fff_ffff).unwrap(),
+            i32::MAX
+        );
+        assert!(relative_offset(next_instruction, next_instruction - 0x8000_0001).is_err());
+        assert!(relative_offset(next_instruction, next_instruction + 0x8000_0000).is_err());
+    }
+
+    #[test]
+    fn slots_are_aligned_and_do_not_overlap() {
+        let first = slot_range(0, 135, 4096).unwrap();
+        let second = slot_range(first.end, 129, 4096).unwrap();
+
+        assert_eq!(first, 0..135);
+        assert_eq!(second, 144..273);
+        assert!(first.end <= second.start);
+        assert!(slot_range(4090, 16, 4096).is_none());
+    }
+
+    #[test]
+    fn mapping_candidates_are_ordered_by_distance_from_hook() {
+        let candidates = CandidateAddresses::new(0x1f000, 0, 0x40000, 0x10000)
+            .unwrap()
+            .collect::<Vec<_>>();
+
+        assert_eq!(candidates, [0x20000, 0x10000, 0x30000, 0, 0x40000]);
+    }
+
+    #[test]
+    fn call_bodies_match_the_hand_written_bytes() {
+        // The register save sequence patch_call used to copy from byte tables.
+        const SAVE_REGISTERS: [u8; 44] = [
+            0x51, // PUSH RCX
+            0x52, // PUSH RDX
+            0x41, 0x50, // PUSH R8
+            0x41, 0x51, // PUSH R9
+            0x41, 0x52, // PUSH R10
+            0x41, 0x53, // PUSH R11
+            0x48, 0x83, 0xEC, 0x60, // SUB RSP, 0x60
+            0xF3, 0x0F, 0x7F, 0x4C, 0x24, 0x10, // MOVDQU [RSP + 0x10], XMM1
+            0xF3, 0x0F, 0x7F, 0x54, 0x24, 0x20, // MOVDQU [RSP + 0x20], XMM2
+            0xF3, 0x0F, 0x7F, 0x5C, 0x24, 0x30, // MOVDQU [RSP + 0x30], XMM3
+            0xF3, 0x0F, 0x7F, 0x64, 0x24, 0x40, // MOVDQU [RSP + 0x40], XMM4
+            0xF3, 0x0F, 0x7F, 0x6C, 0x24, 0x50, // MOVDQU [RSP + 0x50], XMM5
+        ];
+        const LOAD_REGISTERS: [u8; 44] = [
+            0xF3, 0x0F, 0x6F, 0x4C, 0x24, 0x10, // MOVDQU XMM1, [RSP + 0x10]
+            0xF3, 0x0F, 0x6F, 0x54, 0x24, 0x20, // MOVDQU XMM2, [RSP + 0x20]
+            0xF3, 0x0F, 0x6F, 0x5C, 0x24, 0x30, // MOVDQU XMM3, [RSP + 0x30]
+            0xF3, 0x0F, 0x6F, 0x64, 0x24, 0x40, // MOVDQU XMM4, [RSP + 0x40]
+            0xF3, 0x0F, 0x6F, 0x6C, 0x24, 0x50, // MOVDQU XMM5, [RSP + 0x50]
+            0x48, 0x83, 0xC4, 0x60, // ADD RSP, 0x60
+            0x41, 0x5B, // POP R11
+            0x41, 0x5A, // POP R10
+            0x41, 0x59, // POP R9
+            0x41, 0x58, // POP R8
+            0x5A, // POP RDX
+            0x59, // POP RCX
+        ];
+        const ALIGN_STACK: [u8; 4] = [0x48, 0x83, 0xEC, 0x08]; // SUB RSP, 0x8
+        const UNALIGN_STACK: [u8; 4] = [0x48, 0x83, 0xC4, 0x08]; // ADD RSP, 0x8
+        const SAVE_XMM0: [u8; 5] = [0xF3, 0x0F, 0x7F, 0x04, 0x24]; // MOVDQU [RSP], XMM0
+        const LOAD_XMM0: [u8; 5] = [0xF3, 0x0F, 0x6F, 0x04, 0x24]; // MOVDQU XMM0, [RSP]
+                                                                   // CALL [RIP + 2]; JMP +8
+        const CALL_BYTES: [u8; 8] = [0xff, 0x15, 0x02, 0x00, 0x00, 0x00, 0xeb, 0x08];
+
+        let function = dummy as *const () as usize;
+        let call = [&CALL_BYTES[..], &function.to_le_bytes()].concat();
+        let cases = [
+            (
+                ReturnType::None,
+                [
+                    &[0x50][..],
+                    &SAVE_REGISTERS,
+                    &ALIGN_STACK,
+                    &SAVE_XMM0,
+                    &call,
+                    &LOAD_XMM0,
+                    &UNALIGN_STACK,
+                    &LOAD_REGISTERS,
+                    &[0x58],
+                ]
+                .concat(),
+            ),
+            (
+                ReturnType::Rax,
+                [
+                    &SAVE_REGISTERS[..],
+                    &SAVE_XMM0,
+                    &call,
+                    &LOAD_XMM0,
+                    &LOAD_REGISTERS,
+                ]
+                .concat(),
+            ),
+            (
+                ReturnType::Xmm0,
+                [
+                    &[0x50][..],
+                    &SAVE_REGISTERS,
+                    &ALIGN_STACK,
+                    &call,
+                    &UNALIGN_STACK,
+                    &LOAD_REGISTERS,
+                    &[0x58],
+                ]
+                .concat(),
+            ),
+        ];
+
+        for (allow_return, expected) in cases {
+            let mut asm = Asm::new();
+            call_body(&mut asm, function, allow_return);
+            assert_eq!(asm.finish(0).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn patch_sizes_must_end_on_an_instruction_boundary() {
+        // SUBSS XMM1, XMM7; COMISS XMM6, XMM1; padding for the decoder to read ahead.
+        let mut code: Vec<u8> = vec![0xf3, 0x0f, 0x5c, 0xcf, 0x0f, 0x2f, 0xf1];
+        code.resize(32, 0x90);
+        let address = code.as_ptr() as usize;
+
+        unsafe {
+            assert!(check_instruction_boundary(address, 7).is_ok());
+            assert!(matches!(
+                check_instruction_boundary(address, 6),
+                Err(PatchError::SplitInstruction { instruction_end, .. })
+                    if instruction_end == address + 7
+            ));
+            assert_eq!(Patch::hook_size(address).unwrap(), 7);
+            assert_eq!(Patch::hook_size(address + 4).unwrap(), 5);
+
+            code[7] = 0x06;
+            assert!(matches!(
+                check_instruction_boundary(address, 8),
+                Err(PatchError::UnknownInstruction { address: unknown }) if unknown == address + 7
+            ));
+        }
+    }
+
+    #[test]
+    fn patches_too_small_for_a_jump_are_rejected() {
+        let code = [0x90u8; 16];
+        let address = code.as_ptr() as usize;
+
+        unsafe {
+            assert!(matches!(
+                Patch::patch_call(
+                    address,
+                    Some("90 90 90 90"),
+                    dummy as *const (),
+                    4,
+                    true,
+                    ReturnType::None
+                ),
+                Err(PatchError::TooSmallForJump { size: 4, .. })
+            ));
+            assert!(matches!(
+                Patch::detour_with(address, Some("90 90 90 90 90"), 5, 0, |_| Ok(Vec::new())),
+                Err(PatchError::EmptyTrampoline { .. })
+            ));
+            assert!(matches!(
+                Patch::overwrite(address, Some("90"), &[]),
+                Err(PatchError::EmptyPatch)
+            ));
+        }
+    }
+
+    fn pending(id: usize, feature: &'static str, address: usize) -> PendingPatch {
+        PendingPatch {
+            id,
+            feature: Some(feature),
+            address,
+            overwritten: vec![0x90; 4],
+            replacement: vec![0xcc; 4],
+            trampoline: None,
+            enabled: false,
+        }
+    }
+
+    #[test]
+    fn patches_are_written_together() {
+        let mut memory = [0x90u8; 12];
+        let base = memory.as_mut_ptr() as usize;
+        let mut patches = vec![
+            pending(0, "Reduced shake", base),
+            pending(1, "Unblocked TTL", base + 8),
+        ];
+
+        unsafe { write_patches(&mut patches, |_, _| Ok(())) }.unwrap();
+
+        assert_eq!(memory[..4], [0xcc; 4]);
+        assert_eq!(memory[4..8], [0x90; 4]);
+        assert_eq!(memory[8..], [0xcc; 4]);
+        assert!(patches.iter().all(|patch| patch.enabled));
+    }
+
+    #[test]
+    fn failed_installations_restore_every_patch() {
+        let mut memory = [0x90u8; 12];
+        let base = memory.as_mut_ptr() as usize;
+        let mut patches = vec![
+            pending(0, "Reduced shake", base),
+            pending(1, "Unblocked TTL", base + 8),
+        ];
+        let flush = |address: usize, _| {
+            if address == base + 8 {
+                Err(PatchError::InstructionCache {
+                    address,
+                    error: "denied".to_string(),
+                })
+            } else {
+                Ok(())
+            }
+        };
+
+        let error = unsafe { write_patches(&mut patches, flush) }.unwrap_err();
+
+        assert_eq!(memory, [0x90; 12]);
+        assert!(patches.iter().all(|patch| !patch.enabled));
+        assert!(matches!(
+            error,
+            PatchError::InstallFailed { feature: Some("Unblocked TTL"), address, .. }
+                if address == base + 8
+        ));
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "unable to install the Unblocked TTL patch at {:#x}: unable to flush the instruction cache at {:#x}: denied",
+                base + 8,
+                base + 8
+            )
+        );
+    }
+
+    #[test]
+    fn failed_features_are_left_out_on_their_own() {
+        let mut memory = [0x90u8; 24];
+        let base = memory.as_mut_ptr() as usize;
+        let patches = vec![
+            pending(0, "Arcade zoom", base),
+            pending(1, "Reduced shake", base + 8),
+            pending(2, "Arcade zoom", base + 16),
+        ];
+        let flush = |address: usize, _| {
+            if address == base + 16 {
+                Err(PatchError::InstructionCache {
+                    address,
+                    error: "denied".to_string(),
+                })
+            } else {
+                Ok(())
+            }
+        };
+
+        let (installed, failures) = unsafe { install_by_feature(patches, flush) };
+
+        assert_eq!(memory[..8], [0x90; 8]);
+        assert_eq!(memory[8..12], [0xcc; 4]);
+        assert_eq!(memory[16..20], [0x90; 4]);
+        assert_eq!(
+            installed.iter().map(|patch| patch.id).collect::<Vec<_>>(),
+            [1]
+        );
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].feature, Some("Arcade zoom"));
+    }
+
+    #[test]
+    fn patch_sites_must_hold_the_expected_bytes() {
+        unsafe {
+            let address = MOV_EAX.as_ptr() as usize;
+
+            check_expected_bytes(address, Some("B8 ?? ?? BE EF"), 5).unwrap();
+            assert!(matches!(
+                check_expected_bytes(address, Some("B8 ?? ?? BE EE"), 5),
+                Err(PatchError::UnexpectedBytes { address: site, ref found, .. })
+                    if site == address && found == &MOV_EAX
+            ));
+            assert!(matches!(
+                check_expected_bytes(address, Some("B8 ??"), 5),
+                Err(PatchError::InvalidPattern { .. })
+            ));
+            assert!(matches!(
+                Patch::overwrite(address, Some("90 90"), &[0xcc, 0xcc]),
+                Err(PatchError::UnexpectedBytes { .. })
+            ));
+        }
+
+        let error = PatchError::UnexpectedBytes {
+            address: 0x1000,
+            expected: "B8 ?? 90".to_string(),
+            found: vec![0xb8, 0x01, 0xcc],
+        };
+        assert_eq!(
+            error.to_string(),
+            "expected `B8 ?? 90` at 0x1000, found `B8 01 CC`"
+        );
+    }
+
+    #[test]
+    fn patches_from_other_mods_are_reported() {
+        // JMP rel32, followed by the rest of the jump window.
+        let detoured = [0xe9u8, 0x00, 0x10, 0x00, 0x00, 0x90, 0, 0, 0, 0, 0, 0];
+        let nops = [0x90u8; 6];
+        unsafe {
+            let address = detoured.as_ptr() as usize;
+            assert!(matches!(
+                check_expected_bytes(address, Some("?? ?? ?? ?? ?? 90"), 6),
+                Err(PatchError::ForeignPatch {
+                    code: conflict::ForeignCode::Jump { destination, .. },
+                    ..
+                }) if destination == address + 5 + 0x1000
+            ));
+            assert!(matches!(
+                check_expected_bytes(address, Some("48 8B D8 48 8B CE"), 6),
+                Err(PatchError::ForeignPatch { .. })
+            ));
+            // A jump the game itself has is expected by its pattern.
+            check_expected_bytes(address, Some("E9 ?? ?? ?? ?? 90"), 6).unwrap();
+
+            assert!(matches!(
+                check_expected_bytes(nops.as_ptr() as usize, Some("?? ?? ?? ?? ?? 90"), 6),
+                Err(PatchError::ForeignPatch {
+                    code: conflict::ForeignCode::NopSled,
+                    ..
+                })
+            ));
+
+            // Unrecorded bytes are only checked for other mods.
+            assert!(matches!(
+                check_expected_bytes(address, None, 6),
+                Err(PatchError::ForeignPatch { .. })
+            ));
+            check_expected_bytes(detoured[6..].as_ptr() as usize, None, 6).unwrap();
+        }
+
+        let error = PatchError::ForeignPatch {
+            address: 0x1400_1000,
+            code: conflict::ForeignCode::Jump {
+                destination: 0x7ff8_0000_1000,
+                module: Some("other_mod.dll".to_string()),
+            },
+        };
+        assert_eq!(
+            error.to_string(),
+            "another mod already placed a jump to 0x7ff800001000 in other_mod.dll at 0x14001000"
+        );
+    }
+
+    #[test]
+    fn threads_inside_a_patch_are_detected() {
+        let stopped = |rips: &[usize]| stopped_inside(0x1000, 6, rips.iter().copied());
+
+        assert!(!stopped(&[]));
+        assert!(!stopped(&[0x1000, 0x1006, 0xfff]));
+        assert!(stopped(&[0x2000, 0x1001]));
+        assert!(stopped(&[0x1005]));
+
+        assert_eq!(
+            PatchError::PatchInUse { address: 0x1000 }.to_string(),
+            "a game thread stopped inside the patch at 0x1000, try again"
+        );
+    }
+
+    #[test]
+    fn discarded_features_release_their_trampolines() {
+        unsafe {
+            let size = 64;
+            let test_memory = Native::allocate_code(size).expect("Failed to allocate test memory");
+            let test_data = MOV_EAX.repeat(size / MOV_EAX.len());
+            std::ptr::copy_nonoverlapping(
+                test_data.as_ptr(),
+                test_memory as *mut u8,
+                test_data.len(),
+            );
+
+            let mut session = PatchSession::default();
+            let mut patch_call = |feature, address| {
+                session.feature = Some(feature);
+                let function = dummy as *const () as usize;
+                session
+                    .hook(
+                        address,
+                        Some("B8 ?? ?? ?? ?? B8 DE AD BE EF"),
+                        10,
+                        true,
+                        |asm| call_body(asm, function, ReturnType::None),
+                    )
+                    .unwrap()
+                    .trampoline_address()
+                    .unwrap()
+            };
+            let first = patch_call("Arcade zoom", test_memory);
+            let second = patch_call("Reduced shake", test_memory + 30);
+            let third = patch_call("Arcade zoom", test_memory + 40);
+            assert!(first < second && second < third);
+            let used = session.pages[0].used;
+
+            // A freed slot in front of a remaining trampoline stays allocated.
+            session.discard("Reduced shake");
+            assert_eq!(session.pending.len(), 2);
+            assert_eq!(session.pages.len(), 1);
+            assert_eq!(session.pages[0].used, used);
+
+            // A page without trampolines is released.
+            session.discard("Arcade zoom");
+            assert!(session.pending.is_empty());
+            assert!(session.pages.is_empty());
+
+            // Features left out at installation do not keep their pages either.
+            let mut session = PatchSession {
+                feature: Some("Arcade zoom"),
+                ..PatchSession::default()
+            };
+            session
+                .hook(
+                    test_memory,
+                    Some("B8 ?? ?? ?? ?? B8 DE AD BE EF"),
+                    10,
+                    true,
+                    |_| {},
+                )
+                .unwrap();
+            *(test_memory as *mut u8) = 0xcc;
+            let (runtime, failures) = session.install().unwrap();
+            assert_eq!(failures.len(), 1);
+            assert!(runtime.pages.is_empty());
+            assert!(runtime.patches.is_empty());
+
+            Native::free_code(test_memory, size);
+        }
+    }
+
+    #[test]
+    fn patch_calls_share_a_page_and_install_together() {
+        unsafe {
+            let size = 64;
+            let test_memory = Native::allocate_code(size).expect("Failed to allocate test memory");
+
+            let test_data = MOV_EAX.repeat(size / MOV_EAX.len());
+            std::ptr::copy_nonoverlapping(
+                test_data.as_ptr(),
+                test_memory as *mut u8,
+                test_data.len(),
+            );
+
+            // A session of its own keeps the test away from the global patch manager.
+            let mut session = PatchSession::default();
+            let mut patch_call = |address| {
+                let function = dummy as *const () as usize;
+                session
+                    .hook(
+                        address,
+                        Some("B8 ?? ?? ?? ?? B8 DE AD BE EF"),
+                        10,
+                        true,
+                        |asm| call_body(asm, function, ReturnType::None),
+                    )
+                    .unwrap()
+            };
+            let first_address = test_memory;
+            let second_address = first_address + 30;
+            let first = patch_call(first_address);
+            let second = patch_call(second_address);
+
+            let first_trampoline = first.trampoline.expect("first patch has no trampoline");
+            let second_trampoline = second.trampoline.expect("second patch has no trampoline");
+            assert_eq!(
+                align_down(first_trampoline.address, MmapOptions::page_size()),
+                align_down(second_trampoline.address, MmapOptions::page_size())
+            );
+            assert_ne!(first_trampoline.address, second_trampoline.address);
+
+            let replayed = slice::from_raw_parts(first_trampoline.address as *const u8, 10);
+            assert_eq!(replayed, &test_data[..10]);
+            assert_eq!(
+                slice::from_raw_parts(first_address as *const u8, MOV_EAX.len()),
+                &MOV_EAX
+            );
+
+            let (mut runtime, failures) = session.install().unwrap();
+
+            assert!(failures.is_empty());
+            assert_eq!(runtime.pages.len(), 1);
+            assert_eq!(*(first_address as *const u8), NEAR_JUMP);
+            assert_eq!(*(second_address as *const u8), NEAR_JUMP);
+            assert!(runtime.is_enabled(first.handle()));
+
+            // Installed patches can be toggled individually.
+            runtime.set_enabled(first.handle(), false).unwrap();
+            runtime.set_enabled(first.handle(), false).unwrap();
+            assert!(!runtime.is_enabled(first.handle()));
+            assert_eq!(
+                slice::from_raw_parts(first_address as *const u8, 10),
+                &test_data[..10]
+            );
+            assert_eq!(*(second_address as *const u8), NEAR_JUMP);
+
+            runtime.set_enabled(first.handle(), true).unwrap();
+            assert!(runtime.is_enabled(first.handle()));
+            assert_eq!(*(first_address as *const u8), NEAR_JUMP);
+
+            // Bytes changed by someone else are left alone.
+            *(second_address as *mut u8) = 0xcc;
+            assert!(matches!(
+                runtime.set_enabled(second.handle(), false),
+                Err(PatchError::ModifiedPatch { address }) if address == second_address
+            ));
+            assert!(runtime.is_enabled(second.handle()));
+            Native::free_code(test_memory, size);
+        }
+    }
+}
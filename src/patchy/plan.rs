@@ -0,0 +1,146 @@
+//! Reporting prepared patches without installing them.
+
+use std::{fmt::Write as _, fs, path::Path};
+
+use serde::Serialize;
+
+use super::{PatchError, PatchSession, PendingPatch};
+
+/// A prepared patch as reported by a dry run.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub(crate) struct PlannedPatch {
+    /// The feature that prepared the patch, if it was prepared for one.
+    pub feature: Option<&'static str>,
+    pub address: String,
+    pub original: String,
+    pub replacement: String,
+    pub trampoline: Option<PlannedTrampoline>,
+}
+
+/// The trampoline of a [`PlannedPatch`].
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub(crate) struct PlannedTrampoline {
+    pub address: String,
+    pub code: String,
+}
+
+impl PlannedPatch {
+    fn new(patch: &PendingPatch) -> Self {
+        Self {
+            feature: patch.feature,
+            address: format!("{:#x}", patch.address),
+            original: hex_bytes(&patch.overwritten),
+            replacement: hex_bytes(&patch.replacement),
+            trampoline: patch
+                .trampoline
+                .as_ref()
+                .map(|trampoline| PlannedTrampoline {
+                    address: format!("{:#x}", trampoline.address),
+                    code: hex_bytes(&trampoline.code),
+                }),
+        }
+    }
+}
+
+impl PatchSession {
+    /// Describes every patch prepared so far, in the order it was prepared.
+    pub(crate) fn plan(&self) -> Vec<PlannedPatch> {
+        self.pending.iter().map(PlannedPatch::new).collect()
+    }
+}
+
+/// Logs `plan` and writes it to `path` as JSON.
+pub(crate) fn dump(plan: &[PlannedPatch], path: &Path) -> Result<(), PatchError> {
+    for patch in plan {
+        let mut line = format!(
+            "Dry run: {} would replace `{}` at {} with `{}`",
+            patch.feature.unwrap_or("an unnamed feature"),
+            patch.original,
+            patch.address,
+            patch.replacement
+        );
+        if let Some(trampoline) = &patch.trampoline {
+            let _ = write!(line, " and a trampoline at {}", trampoline.address);
+        }
+        log::info!("{line}");
+    }
+
+    let write_error = |error: String| PatchError::DryRunOutput {
+        path: path.display().to_string(),
+        error,
+    };
+    let json =
+        serde_json::to_string_pretty(plan).map_err(|error| write_error(error.to_string()))?;
+    fs::write(path, json).map_err(|error| write_error(error.to_string()))
+}
+
+/// Formats `bytes` the way patterns are written, e.g. `48 8B D8`.
+pub(crate) fn hex_bytes(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{byte:02X}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::patchy::TrampolineCode;
+
+    fn pending(
+        id: usize,
+        feature: Option<&'static str>,
+        trampoline: Option<TrampolineCode>,
+    ) -> PendingPatch {
+        PendingPatch {
+            id,
+            feature,
+            address: 0x1400_0000 + id * 0x10,
+            overwritten: vec![0x48, 0x8b, 0xd8, 0x48, 0x8b, 0xce],
+            replacement: vec![0xe9, 0xfb, 0x0f, 0x00, 0x00, 0x90],
+            trampoline,
+            enabled: false,
+        }
+    }
+
+    #[test]
+    fn plans_describe_every_pending_patch() {
+        let session = PatchSession {
+            pending: vec![
+                pending(
+                    0,
+                    Some("Flare crash fix"),
+                    Some(TrampolineCode {
+                        address: 0x1400_1000,
+                        code: vec![0x48, 0x85, 0xc0, 0xc3],
+                    }),
+                ),
+                pending(1, None, None),
+            ],
+            ..PatchSession::default()
+        };
+
+        let plan = session.plan();
+        assert_eq!(
+            plan[0],
+            PlannedPatch {
+                feature: Some("Flare crash fix"),
+                address: "0x14000000".to_string(),
+                original: "48 8B D8 48 8B CE".to_string(),
+                replacement: "E9 FB 0F 00 00 90".to_string(),
+                trampoline: Some(PlannedTrampoline {
+                    address: "0x14001000".to_string(),
+                    code: "48 85 C0 C3".to_string(),
+                }),
+            }
+        );
+        assert_eq!(plan[1].address, "0x14000010");
+        assert_eq!(plan[1].trampoline, None);
+
+        let json = serde_json::to_value(&plan).unwrap();
+        assert_eq!(json[0]["feature"], "Flare crash fix");
+        assert_eq!(json[0]["trampoline"]["code"], "48 85 C0 C3");
+        assert!(json[1]["feature"].is_null());
+    }
+}
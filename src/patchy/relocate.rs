@@ -0,0 +1,217 @@
+//! Moves instructions to a trampoline while preserving where they point.
+
+use super::{
+    decode::{self, Branch},
+    relative_offset, PatchError,
+};
+
+/// Rewrites `code`, originally located at `source`, so it can run at `destination`.
+///
+/// RIP-relative memory operands and relative branches keep reaching the same
+/// absolute targets. Short jumps are widened to their 32-bit forms, so the size
+/// of the result depends only on `code` and never on `destination`.
+pub(crate) fn relocate(
+    code: &[u8],
+    source: usize,
+    destination: usize,
+) -> Result<Vec<u8>, PatchError> {
+    let end = source
+        .checked_add(code.len())
+        .ok_or(PatchError::AddressOverflow)?;
+    let mut relocated = Vec::with_capacity(code.len());
+    let mut offset = 0;
+
+    while offset < code.len() {
+        let address = source + offset;
+        let instruction =
+            decode::decode(&code[offset..]).ok_or(PatchError::UnknownInstruction { address })?;
+        let bytes = &code[offset..offset + instruction.length];
+        let next_instruction = address + instruction.length;
+        let start = relocated.len();
+        let relocated_address = destination
+            .checked_add(start)
+            .ok_or(PatchError::AddressOverflow)?;
+
+        if let Some(branch) = instruction.branch {
+            let displacement = match branch {
+                Branch::Short | Branch::ShortOnly => i64::from(bytes[bytes.len() - 1] as i8),
+                Branch::Near => i64::from(read_i32(bytes, bytes.len() - 4)),
+            };
+            let target = displaced(next_instruction, displacement)?;
+            // Jumping back to the start is fine, as it now leads to the trampoline.
+            if source < target && target < end {
+                return Err(PatchError::UnrelocatableInstruction {
+                    address,
+                    reason: "branches into the relocated instructions",
+                });
+            }
+
+            match branch {
+                Branch::ShortOnly => {
+                    return Err(PatchError::UnrelocatableInstruction {
+                        address,
+                        reason: "has no 32-bit form",
+                    });
+                }
+                Branch::Short => {
+                    let opcode = bytes[bytes.len() - 2];
+                    if opcode == 0xeb {
+                        // JMP rel8 -> JMP rel32
+                        relocated.push(0xe9);
+                    } else {
+                        // Jcc rel8 -> Jcc rel32
+                        relocated.extend_from_slice(&[0x0f, opcode + 0x10]);
+                    }
+                    relocated.extend_from_slice(&[0; 4]);
+                }
+                Branch::Near => relocated.extend_from_slice(bytes),
+            }
+
+            let length = relocated.len() - start;
+            let displacement = relative_offset(relocated_address + length, target)?;
+            relocated[start + length - 4..].copy_from_slice(&displacement.to_le_bytes());
+        } else {
+            relocated.extend_from_slice(bytes);
+
+            if let Some(position) = instruction.rip_displacement {
+                let target = displaced(next_instruction, i64::from(read_i32(bytes, position)))?;
+                let displacement = relative_offset(relocated_address + instruction.length, target)?;
+                relocated[start + position..start + position + 4]
+                    .copy_from_slice(&displacement.to_le_bytes());
+            }
+        }
+
+        offset += instruction.length;
+    }
+
+    Ok(relocated)
+}
+
+fn read_i32(bytes: &[u8], position: usize) -> i32 {
+    i32::from_le_bytes(
+        bytes[position..position + 4]
+            .try_into()
+            .expect("displacement is four bytes"),
+    )
+}
+
+fn displaced(next_instruction: usize, displacement: i64) -> Result<usize, PatchError> {
+    next_instruction
+        .checked_add_signed(displacement as isize)
+        .ok_or(PatchError::AddressOverflow)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: usize = 0x140001000;
+
+    #[test]
+    fn instructions_keep_their_targets() {
+        let destination = SOURCE + 0x10000;
+        let cases: &[(&str, &[u8], &[u8])] = &[
+            (
+                "mov rbx, rax; mov rcx, rsi",
+                &[0x48, 0x8b, 0xd8, 0x48, 0x8b, 0xce],
+                &[0x48, 0x8b, 0xd8, 0x48, 0x8b, 0xce],
+            ),
+            (
+                "mov rax, [rip+0x100]",
+                &[0x48, 0x8b, 0x05, 0x00, 0x01, 0x00, 0x00],
+                &[0x48, 0x8b, 0x05, 0x00, 0x01, 0xff, 0xff],
+            ),
+            (
+                "mov dword [rip+0x10], 1",
+                &[0xc7, 0x05, 0x10, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00],
+                &[0xc7, 0x05, 0x10, 0x00, 0xff, 0xff, 0x01, 0x00, 0x00, 0x00],
+            ),
+            (
+                "call rel32",
+                &[0xe8, 0x00, 0x02, 0x00, 0x00],
+                &[0xe8, 0x00, 0x02, 0xff, 0xff],
+            ),
+            (
+                "jz rel32",
+                &[0x0f, 0x84, 0x00, 0x00, 0x00, 0x00],
+                &[0x0f, 0x84, 0x00, 0x00, 0xff, 0xff],
+            ),
+            ("jmp rel8", &[0xeb, 0x10], &[0xe9, 0x0d, 0x00, 0xff, 0xff]),
+            (
+                "jnz rel8",
+                &[0x75, 0xf0],
+                &[0x0f, 0x85, 0xec, 0xff, 0xfe, 0xff],
+            ),
+            (
+                "xor eax, eax; call rel32 at offset 2",
+                &[0x31, 0xc0, 0xe8, 0x00, 0x00, 0x00, 0x00],
+                &[0x31, 0xc0, 0xe8, 0x00, 0x00, 0xff, 0xff],
+            ),
+            (
+                "jnz rel8; call rel32 behind the widened jump",
+                &[0x75, 0x10, 0xe8, 0x00, 0x00, 0x00, 0x00],
+                &[
+                    0x0f, 0x85, 0x0c, 0x00, 0xff, 0xff, 0xe8, 0xfc, 0xff, 0xfe, 0xff,
+                ],
+            ),
+            (
+                "jmp back to the hook",
+                &[0x90, 0xeb, 0xfd],
+                &[0x90, 0xe9, 0xfa, 0xff, 0xfe, 0xff],
+            ),
+        ];
+
+        for (name, code, expected) in cases {
+            let relocated = relocate(code, SOURCE, destination)
+                .unwrap_or_else(|error| panic!("{name}: {error}"));
+            assert_eq!(relocated, *expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn relocated_size_does_not_depend_on_the_destination() {
+        let code = [0x75, 0x10, 0x48, 0x8b, 0x05, 0x00, 0x01, 0x00, 0x00];
+
+        let near = relocate(&code, SOURCE, SOURCE).unwrap();
+        let far = relocate(&code, SOURCE, SOURCE - 0x7000_0000).unwrap();
+
+        assert_eq!(near.len(), far.len());
+        assert_eq!(near.len(), 13);
+    }
+
+    #[test]
+    fn unrelocatable_instructions_are_rejected() {
+        let cases: &[(&str, &[u8], usize)] = &[
+            ("loop", &[0xe2, 0xfe], SOURCE),
+            ("jrcxz", &[0x90, 0xe3, 0x10], SOURCE + 1),
+            (
+                "jump into the block",
+                &[0xeb, 0x01, 0x90, 0x90, 0x90],
+                SOURCE,
+            ),
+        ];
+
+        for (name, code, address) in cases {
+            match relocate(code, SOURCE, SOURCE + 0x10000) {
+                Err(PatchError::UnrelocatableInstruction { address: found, .. }) => {
+                    assert_eq!(found, *address, "{name}")
+                }
+                other => panic!("{name}: expected an unrelocatable instruction, got {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn unreachable_targets_are_reported() {
+        let code = [0xe8, 0x00, 0x00, 0x00, 0x00];
+
+        assert!(matches!(
+            relocate(&code, SOURCE, SOURCE + 0x1_0000_0000),
+            Err(PatchError::RelativeJumpOutOfRange { .. })
+        ));
+        assert!(matches!(
+            relocate(&[0x06], SOURCE, SOURCE),
+            Err(PatchError::UnknownInstruction { address: SOURCE })
+        ));
+    }
+}
@@ -0,0 +1,370 @@
+//! Byte-pattern (AOB) scanning used to locate patch sites inside the game executable.
+//!
+//! Patterns are written as space separated hex bytes, with `?` or `??` marking
+//! a wildcard byte, e.g. `"48 8B 05 ?? ?? ?? ?? 48 85 C0"`.
+
+use std::{fmt, ops::Range, slice};
+
+use super::{
+    memory::{Memory, Native},
+    PatchError,
+};
+
+/// Maximum number of match addresses kept in an [`PatchError::AmbiguousPattern`].
+const REPORTED_MATCHES: usize = 8;
+
+const DOS_MAGIC: &[u8; 2] = b"MZ";
+const PE_MAGIC: &[u8; 4] = b"PE\0\0";
+const PE_POINTER_OFFSET: usize = 0x3c;
+const COFF_HEADER_SIZE: usize = 20;
+const SECTION_HEADER_SIZE: usize = 40;
+/// The PE headers of the game executable fit comfortably within the first page.
+const HEADERS_SIZE: usize = 0x1000;
+
+/// A byte pattern where `None` matches any byte.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    bytes: Vec<Option<u8>>,
+}
+
+impl Pattern {
+    /// Parses a pattern such as `"E8 ?? ?? ?? ?? 84 C0"`.
+    ///
+    /// A pattern needs at least one concrete byte. One made only of wildcards
+    /// would match anywhere, or any code at a patch site of the same size.
+    pub fn parse(pattern: &str) -> Result<Self, PatchError> {
+        let invalid = |reason: String| PatchError::InvalidPattern {
+            pattern: pattern.to_string(),
+            reason,
+        };
+
+        let bytes = pattern
+            .split_whitespace()
+            .map(|token| match token {
+                "?" | "??" => Ok(None),
+                _ if token.len() == 2 => u8::from_str_radix(token, 16)
+                    .map(Some)
+                    .map_err(|_| invalid(format!("`{token}` is not a hex byte"))),
+                _ => Err(invalid(format!("`{token}` is not a hex byte"))),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if bytes.is_empty() {
+            return Err(invalid("the pattern is empty".to_string()));
+        }
+        if bytes.iter().all(Option::is_none) {
+            return Err(invalid("the pattern only contains wildcards".to_string()));
+        }
+
+        Ok(Self { bytes })
+    }
+
+    /// Returns the number of bytes covered by this pattern.
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Returns true if the first byte of this pattern is a wildcard.
+    pub fn starts_with_wildcard(&self) -> bool {
+        self.bytes[0].is_none()
+    }
+
+    /// Returns true if `bytes` starts with this pattern.
+    pub fn matches(&self, bytes: &[u8]) -> bool {
+        bytes.len() >= self.bytes.len()
+            && self
+                .bytes
+                .iter()
+                .zip(bytes)
+                .all(|(expected, actual)| expected.is_none_or(|expected| expected == *actual))
+    }
+
+    /// Returns the offset of every match of this pattern inside `haystack`.
+    pub fn find_all(&self, haystack: &[u8]) -> Vec<usize> {
+        if haystack.len() < self.bytes.len() {
+            return Vec::new();
+        }
+
+        // Anchor the search on the first concrete byte so most positions are rejected early.
+        let (anchor, anchor_byte) = self
+            .bytes
+            .iter()
+            .enumerate()
+            .find_map(|(index, byte)| byte.map(|byte| (index, byte)))
+            .expect("patterns always contain a concrete byte");
+
+        (0..=haystack.len() - self.bytes.len())
+            .filter(|&offset| haystack[offset + anchor] == anchor_byte)
+            .filter(|&offset| self.matches(&haystack[offset..]))
+            .collect()
+    }
+
+    /// Returns the offset of the only match of this pattern inside `haystack`.
+    ///
+    /// Fails if the pattern matches nowhere or more than once.
+    pub fn find_unique(&self, haystack: &[u8]) -> Result<usize, PatchError> {
+        let matches = self.find_all(haystack);
+        match matches.as_slice() {
+            [] => Err(PatchError::PatternNotFound {
+                pattern: self.to_string(),
+            }),
+            [offset] => Ok(*offset),
+            _ => Err(PatchError::AmbiguousPattern {
+                pattern: self.to_string(),
+                count: matches.len(),
+                matches: matches.into_iter().take(REPORTED_MATCHES).collect(),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, byte) in self.bytes.iter().enumerate() {
+            if index > 0 {
+                write!(f, " ")?;
+            }
+            match byte {
+                Some(byte) => write!(f, "{byte:02X}")?,
+                None => write!(f, "??")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Finds the unique match of `pattern` inside the game executable's `.text` section
+/// and returns its absolute address.
+///
+/// # Safety
+/// The headers and `.text` section of the main module must be readable.
+pub(crate) unsafe fn scan_text(pattern: &str) -> Result<usize, PatchError> {
+    let pattern = Pattern::parse(pattern)?;
+    let module_base = Native::main_module().map_err(PatchError::MalformedImage)?;
+
+    let headers = slice::from_raw_parts(module_base as *const u8, HEADERS_SIZE);
+    let text = section_range(headers, ".text")?;
+    let text_start = module_base
+        .checked_add(text.start)
+        .ok_or(PatchError::AddressOverflow)?;
+    let code = slice::from_raw_parts(text_start as *const u8, text.len());
+
+    let offset = pattern.find_unique(code).map_err(|error| {
+        let error = offset_matches(error, text_start);
+        log::error!("Signature scan failed: {error}");
+        error
+    })?;
+    Ok(text_start + offset)
+}
+
+/// Rewrites the offsets inside an ambiguous-match error into absolute addresses.
+fn offset_matches(error: PatchError, base: usize) -> PatchError {
+    match error {
+        PatchError::AmbiguousPattern {
+            pattern,
+            count,
+            matches,
+        } => PatchError::AmbiguousPattern {
+            pattern,
+            count,
+            matches: matches.into_iter().map(|offset| base + offset).collect(),
+        },
+        error => error,
+    }
+}
+
+/// Returns the relative virtual address range of the section called `name`.
+///
+/// `headers` must start at the image's DOS header.
+fn section_range(headers: &[u8], name: &str) -> Result<Range<usize>, PatchError> {
+    if headers.get(..DOS_MAGIC.len()) != Some(DOS_MAGIC.as_slice()) {
+        return Err(PatchError::MalformedImage("missing DOS header".to_string()));
+    }
+
+    let pe_offset = read_u32(headers, PE_POINTER_OFFSET)? as usize;
+    if headers.get(pe_offset..pe_offset + PE_MAGIC.len()) != Some(PE_MAGIC.as_slice()) {
+        return Err(PatchError::MalformedImage(
+            "missing PE signature".to_string(),
+        ));
+    }
+
+    let coff_header = pe_offset + PE_MAGIC.len();
+    let section_count = read_u16(headers, coff_header + 2)? as usize;
+    let optional_header_size = read_u16(headers, coff_header + 16)? as usize;
+    let section_table = coff_header + COFF_HEADER_SIZE + optional_header_size;
+
+    for index in 0..section_count {
+        let header = section_table + index * SECTION_HEADER_SIZE;
+        let raw_name = headers
+            .get(header..header + 8)
+            .ok_or_else(|| PatchError::MalformedImage("truncated section table".to_string()))?;
+        let name_length = raw_name.iter().position(|&byte| byte == 0).unwrap_or(8);
+        if &raw_name[..name_length] != name.as_bytes() {
+            continue;
+        }
+
+        let virtual_size = read_u32(headers, header + 8)? as usize;
+        let virtual_address = read_u32(headers, header + 12)? as usize;
+        return Ok(virtual_address..virtual_address + virtual_size);
+    }
+
+    Err(PatchError::SectionNotFound {
+        name: name.to_string(),
+    })
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Result<u16, PatchError> {
+    bytes
+        .get(offset..offset + 2)
+        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+        .ok_or_else(|| PatchError::MalformedImage(format!("header truncated at {offset:#x}")))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, PatchError> {
+    bytes
+        .get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .ok_or_else(|| PatchError::MalformedImage(format!("header truncated at {offset:#x}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds minimal PE headers containing the given `(name, rva, size)` sections.
+    fn image_headers(sections: &[(&str, u32, u32)]) -> Vec<u8> {
+        let pe_offset = 0x80;
+        let optional_header_size = 0xf0u16;
+        let mut headers = vec![0; 0x400];
+
+        headers[..2].copy_from_slice(DOS_MAGIC);
+        headers[PE_POINTER_OFFSET..PE_POINTER_OFFSET + 4]
+            .copy_from_slice(&(pe_offset as u32).to_le_bytes());
+        headers[pe_offset..pe_offset + 4].copy_from_slice(PE_MAGIC);
+
+        let coff_header = pe_offset + 4;
+        headers[coff_header + 2..coff_header + 4]
+            .copy_from_slice(&(sections.len() as u16).to_le_bytes());
+        headers[coff_header + 16..coff_header + 18]
+            .copy_from_slice(&optional_header_size.to_le_bytes());
+
+        let section_table = coff_header + COFF_HEADER_SIZE + optional_header_size as usize;
+        for (index, (name, rva, size)) in sections.iter().enumerate() {
+            let header = section_table + index * SECTION_HEADER_SIZE;
+            headers[header..header + name.len()].copy_from_slice(name.as_bytes());
+            headers[header + 8..header + 12].copy_from_slice(&size.to_le_bytes());
+            headers[header + 12..header + 16].copy_from_slice(&rva.to_le_bytes());
+        }
+
+        headers
+    }
+
+    #[test]
+    fn patterns_parse_wildcards_and_round_trip() {
+        let pattern = Pattern::parse("48 8b ?? ? 05").unwrap();
+
+        assert_eq!(pattern.to_string(), "48 8B ?? ?? 05");
+    }
+
+    #[test]
+    fn invalid_patterns_are_rejected() {
+        for pattern in ["", "   ", "?? ??", "48 8", "48 GG", "488B"] {
+            assert!(
+                matches!(
+                    Pattern::parse(pattern),
+                    Err(PatchError::InvalidPattern { .. })
+                ),
+                "`{pattern}` should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn wildcards_match_any_byte() {
+        let pattern = Pattern::parse("E8 ?? ?? ?? ?? 84 C0").unwrap();
+
+        assert!(pattern.matches(&[0xe8, 0x01, 0x02, 0x03, 0x04, 0x84, 0xc0]));
+        assert!(pattern.matches(&[0xe8, 0xff, 0xff, 0xff, 0xff, 0x84, 0xc0, 0x90]));
+        assert!(!pattern.matches(&[0xe8, 0x01, 0x02, 0x03, 0x04, 0x84, 0xc1]));
+        assert!(!pattern.matches(&[0xe8, 0x01, 0x02, 0x03, 0x04, 0x84]));
+    }
+
+    #[test]
+    fn unique_matches_report_their_offset() {
+        let code = [0x90, 0x90, 0x48, 0x85, 0xc0, 0x75, 0x0e, 0x90];
+        let pattern = Pattern::parse("48 85 C0 75 ??").unwrap();
+
+        assert_eq!(pattern.find_unique(&code).unwrap(), 2);
+    }
+
+    #[test]
+    fn leading_wildcards_are_supported() {
+        let code = [0x00, 0x11, 0x22, 0x33];
+        let pattern = Pattern::parse("?? 22 33").unwrap();
+
+        assert_eq!(pattern.find_all(&code), [1]);
+        assert!(pattern.find_all(&code[..2]).is_empty());
+    }
+
+    #[test]
+    fn missing_patterns_are_reported() {
+        let code = [0x90; 16];
+        let pattern = Pattern::parse("CC CC").unwrap();
+
+        assert!(matches!(
+            pattern.find_unique(&code),
+            Err(PatchError::PatternNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn ambiguous_patterns_report_every_match() {
+        let code = [0x48, 0x85, 0xc0, 0x90, 0x48, 0x85, 0xc0];
+        let pattern = Pattern::parse("48 85 C0").unwrap();
+
+        match pattern.find_unique(&code) {
+            Err(PatchError::AmbiguousPattern { count, matches, .. }) => {
+                assert_eq!(count, 2);
+                assert_eq!(matches, [0, 4]);
+            }
+            other => panic!("expected an ambiguous match, got {other:?}"),
+        }
+
+        let error = offset_matches(pattern.find_unique(&code).unwrap_err(), 0x1000);
+        assert!(matches!(
+            error,
+            PatchError::AmbiguousPattern { matches, .. } if matches == [0x1000, 0x1004]
+        ));
+    }
+
+    #[test]
+    fn text_section_is_located_from_headers() {
+        let headers = image_headers(&[
+            (".text", 0x1000, 0x2345),
+            (".rdata", 0x4000, 0x100),
+            (".data", 0x5000, 0x80),
+        ]);
+
+        assert_eq!(section_range(&headers, ".text").unwrap(), 0x1000..0x3345);
+        assert_eq!(section_range(&headers, ".data").unwrap(), 0x5000..0x5080);
+        assert!(matches!(
+            section_range(&headers, ".reloc"),
+            Err(PatchError::SectionNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn malformed_headers_are_rejected() {
+        let mut headers = image_headers(&[(".text", 0x1000, 0x10)]);
+        headers[0x80] = b'X';
+
+        assert!(matches!(
+            section_range(&headers, ".text"),
+            Err(PatchError::MalformedImage(_))
+        ));
+        assert!(matches!(
+            section_range(&[0; 4], ".text"),
+            Err(PatchError::MalformedImage(_))
+        ));
+    }
+}
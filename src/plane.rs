@@ -3,41 +3,28 @@ use std::{collections::HashMap, hash::Hash};
 use highfleet::general::EscadraString;
 use serde::Serialize;
 
-use crate::structs::{cvec::CVec, loadout, plane::Plane, tll::TllContainer};
-
-fn get_plane_tll_addr() -> u64 {
-    if cfg!(feature = "1_151") {
-        0x143942740
-    } else if cfg!(feature = "1_163") {
-        0x143a13c50
-    } else {
-        // Default to 1.163
-        0x143a13c50
-    }
+use crate::{
+    structs::{cvec::CVec, loadout, plane::Plane, tll::TllContainer},
+    version::addresses,
+};
+
+fn get_plane_tll_addr() -> usize {
+    addresses().plane_tll
 }
 
-fn get_loadout_tll_addr() -> u64 {
-    if cfg!(feature = "1_151") {
-        0x143944528
-    } else if cfg!(feature = "1_163") {
-        0x143a15a60
-    } else {
-        // Default to 1.163
-        0x143a15a60
-    }
+fn get_loadout_tll_addr() -> usize {
+    addresses().loadout_tll
 }
 
 pub fn get_planes() -> HashMap<EscadraString, Vec<loadout::Loadout>> {
     let loadout_tll_addr = get_plane_tll_addr();
-    let tll_container_ptr = loadout_tll_addr as *const TllContainer<EscadraString, Plane>
-        as *mut TllContainer<EscadraString, Plane>;
+    let tll_container_ptr = loadout_tll_addr as *const TllContainer<EscadraString, Plane>;
 
     unsafe {
-        let tll_container = &mut *tll_container_ptr;
+        let tll_container = &*tll_container_ptr;
 
         tll_container
-            .get_map()
-            .into_iter()
+            .iter()
             .map(|(k, v)| {
                 (
                     k.clone(),
@@ -69,12 +56,10 @@ pub unsafe fn patch_planes(planes: &HashMap<EscadraString, Vec<loadout::Loadout>
             loadouts: CVec::empty(),
         };
 
-        let new_loadout_map = new_loadouts.get_map();
-
         for loadout in plane_loadouts {
             plane
                 .loadouts
-                .insert(*new_loadout_map.get(&loadout.oid).unwrap() as *const loadout::Loadout);
+                .insert(new_loadouts.get(&loadout.oid).unwrap() as *const loadout::Loadout);
         }
 
         new_planes.insert(plane_name.clone(), plane);
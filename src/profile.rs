@@ -0,0 +1,815 @@
+//! Address profiles describing where the mod patches each game version.
+//!
+//! A profile is a JSON file that maps symbol names such as `zoom.min_level` to
+//! addresses. Patch sites also record the bytes the game has there, which are
+//! checked before the site is patched, and may be located by a byte pattern
+//! instead of a fixed address. Profiles for the supported Steam releases are built into the mod.
+//! Profiles placed in [`PROFILE_DIRECTORY`] take precedence over the built-in
+//! ones and can add support for other builds without recompiling.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    error::Error,
+    fmt,
+    path::PathBuf,
+};
+
+use serde::Deserialize;
+
+use crate::{
+    patchy::{
+        memory::{Memory, Native},
+        scan::{self, Pattern},
+        PatchError,
+    },
+    version::{
+        Addresses, BodyOffsets, CityOffsets, FireGunSites, FlareCrashSites, GameVersion,
+        ProfileOffsets, ShipPartOffsets, StructOffsets,
+    },
+};
+
+/// Directory searched for user-provided profiles.
+pub const PROFILE_DIRECTORY: &str = "Modloader/config/qol_profiles";
+
+/// The newest profile format understood by this build.
+const PROFILE_VERSION: u32 = 1;
+
+const BUILTIN_PROFILES: [(&str, &str); 2] = [
+    (
+        "steam_1_151.json",
+        include_str!("../profiles/steam_1_151.json"),
+    ),
+    (
+        "steam_1_163.json",
+        include_str!("../profiles/steam_1_163.json"),
+    ),
+];
+
+/// The addresses of one game build.
+#[derive(Debug)]
+pub struct Profile {
+    /// The version string reported by the modloader, e.g. `"Steam 1.163"`.
+    pub game_version: String,
+    /// Which hooks the build's code is compatible with.
+    pub layout: GameVersion,
+    /// The resolved addresses.
+    pub addresses: Addresses,
+}
+
+/// Why a profile could not be used.
+///
+/// `source` names the profile, either its path or the name of a built-in one.
+#[derive(Debug)]
+pub enum ProfileError {
+    /// The profile file could not be read.
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    /// The profile is not valid JSON or does not have the profile layout.
+    Parse {
+        source: String,
+        error: serde_json::Error,
+    },
+    /// The profile was written for a newer release of the mod.
+    UnsupportedVersion { source: String, version: u32 },
+    /// The value of the symbol `name` is not a number.
+    InvalidSymbol {
+        source: String,
+        name: String,
+        value: String,
+    },
+    /// The original bytes recorded for the site `name` are not a valid pattern.
+    InvalidOriginalBytes {
+        source: String,
+        name: String,
+        reason: String,
+    },
+    /// The site `name` has both an address and a pattern, or neither.
+    InvalidSite { source: String, name: String },
+    /// The pattern of the symbol `name`, which is not a patch site, does not
+    /// match exactly once.
+    SignatureNotFound {
+        source: String,
+        name: String,
+        reason: String,
+    },
+    /// Required symbols are absent from the profile.
+    MissingSymbols { source: String, names: Vec<String> },
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io { path, error } => write!(f, "unable to read {}: {error}", path.display()),
+            Self::Parse { source, error } => write!(f, "{source} is not a valid profile: {error}"),
+            Self::UnsupportedVersion { source, version } => write!(
+                f,
+                "{source} uses profile version {version}, but only versions up to {PROFILE_VERSION} are supported"
+            ),
+            Self::InvalidSymbol {
+                source,
+                name,
+                value,
+            } => write!(f, "{source}: symbol `{name}` has invalid value `{value}`"),
+            Self::InvalidOriginalBytes {
+                source,
+                name,
+                reason,
+            } => write!(f, "{source}: the original bytes of `{name}` are invalid: {reason}"),
+            Self::InvalidSite { source, name } => write!(
+                f,
+                "{source}: site `{name}` needs either an `address` or a `pattern`"
+            ),
+            Self::SignatureNotFound {
+                source,
+                name,
+                reason,
+            } => write!(f, "{source}: unable to locate `{name}`: {reason}"),
+            Self::MissingSymbols { source, names } => write!(
+                f,
+                "{source} is missing required symbol(s): {}",
+                names.join(", ")
+            ),
+        }
+    }
+}
+
+impl Error for ProfileError {}
+
+#[derive(Deserialize)]
+struct ProfileFile {
+    profile_version: u32,
+    game_version: String,
+    layout: GameVersion,
+    symbols: BTreeMap<String, SymbolEntry>,
+}
+
+/// A symbol, or a patch site written as an object.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SymbolEntry {
+    Site(Site),
+    Value(SymbolValue),
+}
+
+/// A patch site, located by its address or by a byte pattern that must match
+/// exactly once in the game's code, and the bytes the game has there.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Site {
+    address: Option<SymbolValue>,
+    pattern: Option<String>,
+    original: Option<String>,
+}
+
+/// The symbols of the sites features patch, the only ones whose pattern may be
+/// left unresolved.
+const PATCH_SITES: [&str; 10] = [
+    "zoom.calc_zoom_call",
+    "zoom.set_levels_hook",
+    "shake.multiplier",
+    "ttl.check",
+    "dumpable.hook",
+    "sell.hook",
+    "parts.hook",
+    "guns.sector_check",
+    "fire_gun.charge_hook",
+    "flare_crash.hook_rva",
+];
+
+/// Locates the byte pattern of the symbol `name`, returning its value.
+type Scanner<'a> = &'a dyn Fn(&str, &str) -> Result<usize, PatchError>;
+
+/// A symbol written either as a number or as a (usually hexadecimal) string.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SymbolValue {
+    Number(usize),
+    Text(String),
+}
+
+impl SymbolValue {
+    fn resolve(&self) -> Option<usize> {
+        match self {
+            Self::Number(value) => Some(*value),
+            Self::Text(text) => {
+                let text = text.trim();
+                match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+                    Some(hex) => usize::from_str_radix(hex, 16).ok(),
+                    None => text.parse().ok(),
+                }
+            }
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Self::Number(value) => value.to_string(),
+            Self::Text(text) => text.clone(),
+        }
+    }
+}
+
+impl Profile {
+    /// Resolves a parsed profile, checking that every required symbol is present.
+    ///
+    /// `source` names the profile in error messages. Sites given by a pattern are
+    /// located with `scan`.
+    fn from_file(file: ProfileFile, source: &str, scan: Scanner) -> Result<Self, ProfileError> {
+        if file.profile_version > PROFILE_VERSION {
+            return Err(ProfileError::UnsupportedVersion {
+                source: source.to_string(),
+                version: file.profile_version,
+            });
+        }
+
+        let mut values = BTreeMap::new();
+        let mut originals = BTreeMap::new();
+        let mut unresolved = BTreeMap::new();
+        for (name, entry) in &file.symbols {
+            let resolve = |value: &SymbolValue| {
+                value.resolve().ok_or_else(|| ProfileError::InvalidSymbol {
+                    source: source.to_string(),
+                    name: name.clone(),
+                    value: value.describe(),
+                })
+            };
+            let (resolved, original) = match entry {
+                SymbolEntry::Value(value) => (resolve(value)?, None),
+                SymbolEntry::Site(Site {
+                    address: Some(address),
+                    pattern: None,
+                    original,
+                }) => (resolve(address)?, original.as_deref()),
+                SymbolEntry::Site(Site {
+                    address: None,
+                    pattern: Some(pattern),
+                    original,
+                }) => match scan(name, pattern) {
+                    Ok(address) => {
+                        log::info!("{source}: found `{name}` at {address:#x}");
+                        (address, original.as_deref())
+                    }
+                    // Features check their sites, so only those patching this one fail.
+                    Err(error) if PATCH_SITES.contains(&name.as_str()) => {
+                        log::error!("{source}: unable to locate `{name}`: {error}");
+                        unresolved.insert(name.clone(), error.to_string());
+                        (0, original.as_deref())
+                    }
+                    Err(error) => {
+                        return Err(ProfileError::SignatureNotFound {
+                            source: source.to_string(),
+                            name: name.clone(),
+                            reason: error.to_string(),
+                        })
+                    }
+                },
+                SymbolEntry::Site(_) => {
+                    return Err(ProfileError::InvalidSite {
+                        source: source.to_string(),
+                        name: name.clone(),
+                    })
+                }
+            };
+            values.insert(name.clone(), resolved);
+
+            if let Some(original) = original {
+                Pattern::parse(original).map_err(|error| ProfileError::InvalidOriginalBytes {
+                    source: source.to_string(),
+                    name: name.clone(),
+                    reason: error.to_string(),
+                })?;
+                originals.insert(name.clone(), original.to_string());
+            }
+        }
+
+        let mut symbols = Symbols::new(values, originals);
+        let addresses = Addresses {
+            unresolved,
+            ..symbols.addresses()
+        };
+
+        for name in symbols.unused() {
+            log::warn!("{source}: ignoring unknown symbol `{name}`");
+        }
+        if !symbols.missing.is_empty() {
+            return Err(ProfileError::MissingSymbols {
+                source: source.to_string(),
+                names: symbols.missing,
+            });
+        }
+
+        Ok(Self {
+            game_version: file.game_version,
+            layout: file.layout,
+            addresses,
+        })
+    }
+}
+
+fn parse_file(json: &str, source: &str) -> Result<ProfileFile, ProfileError> {
+    serde_json::from_str(json).map_err(|error| ProfileError::Parse {
+        source: source.to_string(),
+        error,
+    })
+}
+
+/// Finds the profile for `game_version`, preferring user profiles over built-in ones.
+///
+/// Returns `Ok(None)` if no profile targets that version.
+pub fn load_profile(game_version: &str) -> Result<Option<Profile>, ProfileError> {
+    for path in user_profiles() {
+        let source = path.display().to_string();
+        let json = match std::fs::read_to_string(&path) {
+            Ok(json) => json,
+            Err(error) => {
+                log::error!("Skipping profile: {}", ProfileError::Io { path, error });
+                continue;
+            }
+        };
+        let file = match parse_file(&json, &source) {
+            Ok(file) => file,
+            Err(error) => {
+                log::error!("Skipping profile: {error}");
+                continue;
+            }
+        };
+
+        if file.game_version == game_version {
+            log::info!("Using address profile {source}");
+            return Profile::from_file(file, &source, &scan_game).map(Some);
+        }
+    }
+
+    builtin_profile(game_version)
+}
+
+fn builtin_profile(game_version: &str) -> Result<Option<Profile>, ProfileError> {
+    for (name, json) in BUILTIN_PROFILES {
+        let file = parse_file(json, name)?;
+        if file.game_version == game_version {
+            return Profile::from_file(file, name, &scan_game).map(Some);
+        }
+    }
+
+    Ok(None)
+}
+
+/// Locates `pattern` in the running game. Symbols ending in `_rva` are relative
+/// to the game executable, the others are absolute addresses.
+fn scan_game(name: &str, pattern: &str) -> Result<usize, PatchError> {
+    // SAFETY: the game executable is mapped for as long as the mod is loaded.
+    unsafe {
+        let address = scan::scan_text(pattern)?;
+        if name.ends_with("_rva") {
+            let base = Native::main_module().map_err(PatchError::MalformedImage)?;
+            Ok(address - base)
+        } else {
+            Ok(address)
+        }
+    }
+}
+
+fn user_profiles() -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(PROFILE_DIRECTORY) else {
+        return Vec::new();
+    };
+
+    let mut paths = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().and_then(|extension| extension.to_str()) == Some("json"))
+        .collect::<Vec<_>>();
+    paths.sort();
+    paths
+}
+
+/// Tracks which symbols were read while building an [`Addresses`] table.
+struct Symbols {
+    values: BTreeMap<String, usize>,
+    originals: BTreeMap<String, String>,
+    used: BTreeSet<String>,
+    missing: Vec<String>,
+}
+
+impl Symbols {
+    fn new(values: BTreeMap<String, usize>, originals: BTreeMap<String, String>) -> Self {
+        Self {
+            values,
+            originals,
+            used: BTreeSet::new(),
+            missing: Vec::new(),
+        }
+    }
+
+    fn required(&mut self, name: &str) -> usize {
+        self.used.insert(name.to_string());
+        match self.values.get(name) {
+            Some(value) => *value,
+            None => {
+                self.missing.push(name.to_string());
+                0
+            }
+        }
+    }
+
+    fn optional(&mut self, name: &str) -> Option<usize> {
+        self.used.insert(name.to_string());
+        self.values.get(name).copied()
+    }
+
+    /// Reads a group of symbols sharing `prefix`, which is absent if none of them exist.
+    fn group<T>(&mut self, prefix: &str, read: impl FnOnce(&mut Self) -> T) -> Option<T> {
+        let prefix = format!("{prefix}.");
+        let present = self.values.keys().any(|name| name.starts_with(&prefix));
+        present.then(|| read(self))
+    }
+
+    fn unused(&self) -> impl Iterator<Item = &String> {
+        self.values
+            .keys()
+            .filter(|name| !self.used.contains(name.as_str()))
+    }
+
+    fn addresses(&mut self) -> Addresses {
+        Addresses {
+            zoom_min_level: self.required("zoom.min_level"),
+            zoom_max_level: self.required("zoom.max_level"),
+            zoom_level: self.required("zoom.level"),
+            zoom_is_in_arcade: self.required("zoom.is_in_arcade"),
+            zoom_calc_zoom_call: self.required("zoom.calc_zoom_call"),
+            zoom_set_levels_hook: self.required("zoom.set_levels_hook"),
+            zoom_set_levels_size: self.required("zoom.set_levels_size"),
+            shake_multiplier: self.required("shake.multiplier"),
+            ttl_check: self.required("ttl.check"),
+            dumpable_hook: self.required("dumpable.hook"),
+            dumpable_flag_offset: self.required("dumpable.flag_offset"),
+            sell_hook: self.required("sell.hook"),
+            parts_define_part_fn: self.required("parts.define_part_fn"),
+            parts_all_part_library: self.required("parts.all_part_library"),
+            parts_city: self.required("parts.city"),
+            parts_category_fallback: self.required("parts.category_fallback"),
+            parts_hook: self.required("parts.hook"),
+            plane_tll: self.required("tll.planes"),
+            loadout_tll: self.required("tll.loadouts"),
+            structs: StructOffsets {
+                profile: ProfileOffsets {
+                    cash: self.required("profile.cash_offset"),
+                },
+                city: CityOffsets {
+                    city_type: self.required("city.type_offset"),
+                    category_node: self.required("city.category_node_offset"),
+                },
+                body: BodyOffsets {
+                    m_code: self.required("body.m_code"),
+                    master_node: self.required("body.master_node"),
+                    owner_node: self.required("body.owner_node"),
+                    angle: self.required("body.angle"),
+                    part_index: self.required("body.part_index"),
+                    sectors_begin: self.required("body.sectors_begin"),
+                    sectors_end: self.required("body.sectors_end"),
+                },
+                // The ShipPart layout has only been mapped where FireGun needs it.
+                ship_part: self.group("fire_gun", |symbols| ShipPartOffsets {
+                    sectors_type: symbols.required("fire_gun.part_sectors_type"),
+                }),
+            },
+            gun_sector_check: self.optional("guns.sector_check"),
+            fire_gun: self.group("fire_gun", |symbols| FireGunSites {
+                charge_hook: symbols.required("fire_gun.charge_hook"),
+                blocked_exit: symbols.required("fire_gun.blocked_exit"),
+                resume: symbols.required("fire_gun.resume"),
+                get_stats_fn: symbols.required("fire_gun.get_stats_fn"),
+            }),
+            flare_crash: self.group("flare_crash", |symbols| FlareCrashSites {
+                hook_rva: symbols.required("flare_crash.hook_rva"),
+                resume_rva: symbols.required("flare_crash.resume_rva"),
+                null_exit_rva: symbols.required("flare_crash.null_exit_rva"),
+            }),
+            originals: self.originals.clone(),
+            unresolved: BTreeMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::patchy::PatchError;
+
+    const IMAGE: std::ops::Range<usize> = 0x140000000..0x148000000;
+
+    /// Sites of the built-in profiles whose original bytes are not recorded yet,
+    /// so they are patched unchecked.
+    const UNRECORDED: [(&str, &str); 9] = [
+        ("Steam 1.151", "zoom.set_levels_hook"),
+        ("Steam 1.151", "shake.multiplier"),
+        ("Steam 1.151", "ttl.check"),
+        ("Steam 1.151", "dumpable.hook"),
+        ("Steam 1.151", "guns.sector_check"),
+        ("Steam 1.163", "zoom.set_levels_hook"),
+        ("Steam 1.163", "shake.multiplier"),
+        ("Steam 1.163", "ttl.check"),
+        ("Steam 1.163", "dumpable.hook"),
+    ];
+
+    fn builtin(game_version: &str) -> Profile {
+        builtin_profile(game_version)
+            .unwrap()
+            .unwrap_or_else(|| panic!("no built-in profile for {game_version}"))
+    }
+
+    fn parse(json: &str, source: &str) -> Result<Profile, ProfileError> {
+        Profile::from_file(parse_file(json, source)?, source, &no_scan)
+    }
+
+    fn no_scan(_name: &str, pattern: &str) -> Result<usize, PatchError> {
+        Err(PatchError::PatternNotFound {
+            pattern: pattern.to_string(),
+        })
+    }
+
+    /// The built-in 1.163 profile with the symbol `name` replaced by `entry`.
+    fn with_symbol(name: &str, entry: serde_json::Value) -> String {
+        let mut profile: serde_json::Value = serde_json::from_str(BUILTIN_PROFILES[1].1).unwrap();
+        profile["symbols"][name] = entry;
+        profile.to_string()
+    }
+
+    fn absolute_addresses(addresses: &Addresses) -> Vec<usize> {
+        let mut result = vec![
+            addresses.zoom_min_level,
+            addresses.zoom_max_level,
+            addresses.zoom_level,
+            addresses.zoom_is_in_arcade,
+            addresses.zoom_calc_zoom_call,
+            addresses.zoom_set_levels_hook,
+            addresses.shake_multiplier,
+            addresses.ttl_check,
+            addresses.dumpable_hook,
+            addresses.sell_hook,
+            addresses.parts_define_part_fn,
+            addresses.parts_all_part_library,
+            addresses.parts_city,
+            addresses.parts_category_fallback,
+            addresses.parts_hook,
+            addresses.plane_tll,
+            addresses.loadout_tll,
+        ];
+        result.extend(addresses.gun_sector_check);
+        if let Some(fire_gun) = &addresses.fire_gun {
+            result.extend([
+                fire_gun.charge_hook,
+                fire_gun.blocked_exit,
+                fire_gun.resume,
+                fire_gun.get_stats_fn,
+            ]);
+        }
+        result
+    }
+
+    #[test]
+    fn builtin_profiles_cover_the_steam_releases() {
+        let v1_151 = builtin("Steam 1.151");
+        let v1_163 = builtin("Steam 1.163");
+
+        assert_eq!(v1_151.layout, GameVersion::V1_151);
+        assert_eq!(v1_163.layout, GameVersion::V1_163);
+        assert_eq!(v1_151.addresses.structs.profile.cash, 0x260);
+        assert_eq!(v1_163.addresses.structs.profile.cash, 0x2a8);
+        assert!(builtin_profile("Gog 1.163").unwrap().is_none());
+    }
+
+    #[test]
+    fn sites_record_their_original_bytes() {
+        let v1_151 = builtin("Steam 1.151").addresses;
+        let v1_163 = builtin("Steam 1.163").addresses;
+
+        assert_eq!(v1_151.parts_hook, 0x14029ae0f);
+        assert_eq!(
+            v1_151.original_bytes("parts.hook").unwrap(),
+            Some("8B 88 60 02 00 00")
+        );
+        assert_eq!(
+            v1_163.original_bytes("parts.hook").unwrap(),
+            Some("8B 88 A8 02 00 00")
+        );
+        assert_eq!(v1_151.original_bytes("flare_crash.hook_rva").unwrap(), None);
+
+        for (source, json) in BUILTIN_PROFILES {
+            let file = parse_file(json, source).unwrap();
+            let game_version = file.game_version.clone();
+            let addresses = parse(json, source).unwrap().addresses;
+            for site in addresses.originals.keys() {
+                assert!(
+                    PATCH_SITES.contains(&site.as_str()),
+                    "{site} is not a known patch site"
+                );
+            }
+            for site in PATCH_SITES {
+                if !file.symbols.contains_key(site) {
+                    continue;
+                }
+                let size = match site {
+                    "zoom.calc_zoom_call" => 5,
+                    "zoom.set_levels_hook" => addresses.zoom_set_levels_size,
+                    "shake.multiplier" | "ttl.check" => 4,
+                    "fire_gun.charge_hook" => 7,
+                    _ => 6,
+                };
+                match addresses.originals.get(site) {
+                    Some(original) => assert_eq!(
+                        Pattern::parse(original).unwrap().len(),
+                        size,
+                        "{game_version} {site}"
+                    ),
+                    None => assert!(
+                        UNRECORDED.contains(&(game_version.as_str(), site)),
+                        "{game_version} does not record the bytes at {site}"
+                    ),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn builtin_profiles_use_every_symbol() {
+        for (name, json) in BUILTIN_PROFILES {
+            let file = parse_file(json, name).unwrap();
+            let values = file
+                .symbols
+                .iter()
+                .map(|(name, entry)| {
+                    let value = match entry {
+                        SymbolEntry::Value(value) => value,
+                        SymbolEntry::Site(site) => site.address.as_ref().unwrap(),
+                    };
+                    (name.clone(), value.resolve().unwrap())
+                })
+                .collect();
+            let mut symbols = Symbols::new(values, BTreeMap::new());
+            symbols.addresses();
+
+            assert!(symbols.missing.is_empty(), "{name}: {:?}", symbols.missing);
+            assert_eq!(symbols.unused().count(), 0, "{name} has unknown symbols");
+        }
+    }
+
+    #[test]
+    fn addresses_lie_inside_the_game_image() {
+        for game_version in ["Steam 1.151", "Steam 1.163"] {
+            for address in absolute_addresses(&builtin(game_version).addresses) {
+                assert!(
+                    IMAGE.contains(&address),
+                    "{game_version} address {address:#x} is outside the game image"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn every_version_handles_gun_blocking_one_way() {
+        for game_version in ["Steam 1.151", "Steam 1.163"] {
+            let addresses = builtin(game_version).addresses;
+            assert!(
+                addresses.gun_sector_check.is_some() != addresses.fire_gun.is_some(),
+                "{game_version} must either remove or restore the sector check"
+            );
+            assert_eq!(
+                addresses.fire_gun.is_some(),
+                addresses.structs.ship_part.is_some(),
+                "{game_version} restores the sector check without the ShipPart layout"
+            );
+        }
+    }
+
+    #[test]
+    fn fire_gun_exits_surround_the_hook() {
+        let addresses = builtin("Steam 1.163").addresses;
+        let fire_gun = addresses.fire_gun.unwrap();
+
+        assert!(fire_gun.blocked_exit < fire_gun.charge_hook);
+        assert!(fire_gun.resume > fire_gun.charge_hook);
+    }
+
+    #[test]
+    fn sites_can_be_located_by_pattern() {
+        let json = with_symbol(
+            "ttl.check",
+            serde_json::json!({ "pattern": "0F 84 ?? ?? ?? ?? 48", "original": "0F 84 ?? ??" }),
+        );
+        let scan = |name: &str, pattern: &str| {
+            assert_eq!((name, pattern), ("ttl.check", "0F 84 ?? ?? ?? ?? 48"));
+            Ok(0x140052af2)
+        };
+        let profile =
+            Profile::from_file(parse_file(&json, "scan.json").unwrap(), "scan.json", &scan)
+                .unwrap();
+
+        assert_eq!(profile.addresses.ttl_check, 0x140052af2);
+        assert_eq!(
+            profile.addresses.original_bytes("ttl.check").unwrap(),
+            Some("0F 84 ?? ??")
+        );
+
+        // An unresolved patch site only fails the features patching it.
+        let addresses = parse(&json, "scan.json").unwrap().addresses;
+        assert!(matches!(
+            addresses.original_bytes("ttl.check"),
+            Err(PatchError::UnresolvedSite { site, .. }) if site == "ttl.check"
+        ));
+        assert_eq!(
+            addresses.original_bytes("parts.hook").unwrap(),
+            Some("8B 88 A8 02 00 00")
+        );
+
+        let json = with_symbol(
+            "fire_gun.resume",
+            serde_json::json!({ "pattern": "F3 0F 5C" }),
+        );
+        assert!(matches!(
+            parse(&json, "scan.json"),
+            Err(ProfileError::SignatureNotFound { name, .. }) if name == "fire_gun.resume"
+        ));
+        for entry in [
+            serde_json::json!({ "address": "0x140052af2", "pattern": "0F 84" }),
+            serde_json::json!({ "original": "0F 84 ?? ??" }),
+        ] {
+            assert!(matches!(
+                parse(&with_symbol("ttl.check", entry), "site.json"),
+                Err(ProfileError::InvalidSite { name, .. }) if name == "ttl.check"
+            ));
+        }
+    }
+
+    #[test]
+    fn symbols_accept_hex_and_decimal_values() {
+        let values = [
+            SymbolValue::Number(20),
+            SymbolValue::Text("0x1402C31C9".to_string()),
+            SymbolValue::Text(" 0X25c ".to_string()),
+            SymbolValue::Text("348".to_string()),
+        ];
+        let resolved = values.iter().map(SymbolValue::resolve).collect::<Vec<_>>();
+
+        assert_eq!(
+            resolved,
+            [Some(20), Some(0x1402c31c9), Some(0x25c), Some(348)]
+        );
+        assert_eq!(SymbolValue::Text("0xZZ".to_string()).resolve(), None);
+    }
+
+    #[test]
+    fn every_missing_symbol_is_reported() {
+        let json = r#"{
+            "profile_version": 1,
+            "game_version": "Gog 1.163",
+            "layout": "1.163",
+            "symbols": {
+                "zoom.min_level": "0x143a119a4",
+                "fire_gun.charge_hook": "0x140032f22"
+            }
+        }"#;
+
+        match parse(json, "gog.json") {
+            Err(ProfileError::MissingSymbols { source, names }) => {
+                assert_eq!(source, "gog.json");
+                assert!(!names.contains(&"zoom.min_level".to_string()));
+                assert!(names.contains(&"zoom.max_level".to_string()));
+                assert!(names.contains(&"tll.loadouts".to_string()));
+                assert!(names.contains(&"fire_gun.resume".to_string()));
+                assert!(!names.iter().any(|name| name.starts_with("flare_crash.")));
+            }
+            other => panic!("expected missing symbols, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn invalid_profiles_are_rejected() {
+        let newer =
+            r#"{"profile_version": 2, "game_version": "x", "layout": "1.163", "symbols": {}}"#;
+        assert!(matches!(
+            parse(newer, "newer.json"),
+            Err(ProfileError::UnsupportedVersion { version: 2, .. })
+        ));
+
+        let layout =
+            r#"{"profile_version": 1, "game_version": "x", "layout": "2.0", "symbols": {}}"#;
+        assert!(matches!(
+            parse(layout, "layout.json"),
+            Err(ProfileError::Parse { .. })
+        ));
+
+        let symbol = r#"{"profile_version": 1, "game_version": "x", "layout": "1.163", "symbols": {"ttl.check": "0xnope"}}"#;
+        assert!(matches!(
+            parse(symbol, "symbol.json"),
+            Err(ProfileError::InvalidSymbol { name, .. }) if name == "ttl.check"
+        ));
+
+        let original = r#"{"profile_version": 1, "game_version": "x", "layout": "1.163", "symbols": {"ttl.check": {"address": "0x140052af2", "original": "?? ?? ?? ??"}}}"#;
+        assert!(matches!(
+            parse(original, "original.json"),
+            Err(ProfileError::InvalidOriginalBytes { name, .. }) if name == "ttl.check"
+        ));
+    }
+}
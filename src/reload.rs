@@ -0,0 +1,147 @@
+//! Reloads the config file while the game is running.
+//!
+//! Every registered feature is brought to its new settings as soon as the file
+//! changes, which also turns features installed at startup off and on again.
+//! Features that were not installed at startup and changes to settings outside
+//! the features are logged, since they only take effect after restarting the game.
+
+use std::{
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{config::Config, feature::FEATURES};
+
+/// How often the config file is checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Settings outside the registered features, applied only at startup.
+const RESTART_SETTINGS: [&str; 1] = ["planes"];
+
+/// Starts a thread that applies changes made to the config file at `path`.
+///
+/// `config` is the configuration the game was patched with.
+pub fn watch(path: &'static str, config: &Config) {
+    let current = match serde_json::to_value(config) {
+        Ok(current) => current,
+        Err(error) => {
+            log::error!("Unable to watch {path} for changes: {error}");
+            return;
+        }
+    };
+
+    let watcher = thread::Builder::new()
+        .name("qol-config-watcher".to_string())
+        .spawn(move || watch_file(path, current));
+    match watcher {
+        Ok(_) => log::info!("Watching {path} for changes"),
+        Err(error) => log::error!("Unable to watch {path} for changes: {error}"),
+    }
+}
+
+fn watch_file(path: &str, mut current: Value) {
+    let mut last_modified = modified_time(path);
+
+    loop {
+        thread::sleep(POLL_INTERVAL);
+
+        let modified = modified_time(path);
+        if modified == last_modified {
+            continue;
+        }
+        last_modified = modified;
+
+        // Broken settings fall back to their defaults, like at startup.
+        let mut config = match Config::load_upgraded(path) {
+            Ok(config) => config,
+            Err(error) => {
+                log::warn!("Unable to reload {path}, keeping the previous settings: {error}");
+                continue;
+            }
+        };
+        config.repair();
+        let updated = match serde_json::to_value(&config) {
+            Ok(updated) => updated,
+            Err(error) => {
+                log::warn!("Unable to reload {path}, keeping the previous settings: {error}");
+                continue;
+            }
+        };
+
+        let changed = changed_settings(&current, &updated);
+        if !changed.is_empty() {
+            log::info!("Reloading {path}");
+            match Config::deserialize(&current) {
+                Ok(previous) => apply(&changed, &previous, &config),
+                Err(error) => log::warn!("Unable to compare with the previous settings: {error}"),
+            }
+        }
+        current = updated;
+    }
+}
+
+fn modified_time(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Returns the top-level settings whose values differ between two configs.
+fn changed_settings(current: &Value, updated: &Value) -> Vec<String> {
+    let (Some(current), Some(updated)) = (current.as_object(), updated.as_object()) else {
+        return Vec::new();
+    };
+
+    let mut changed = current
+        .keys()
+        .chain(updated.keys().filter(|key| !current.contains_key(*key)))
+        .filter(|key| current.get(*key) != updated.get(*key))
+        .cloned()
+        .collect::<Vec<_>>();
+    changed.sort();
+    changed
+}
+
+fn apply(changed: &[String], current: &Config, updated: &Config) {
+    for feature in FEATURES {
+        feature.reload(current, updated);
+    }
+
+    for setting in changed {
+        if RESTART_SETTINGS.contains(&setting.as_str()) {
+            log::warn!("`{setting}` changed; restart HighFleet to apply it");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn only_changed_settings_are_reported() {
+        let current = json!({
+            "enable_arcade_zoom": true,
+            "sell_multiplier": 1.0,
+            "zoom_levels": [14.0, 7.0, 1.0],
+            "removed": 1,
+        });
+        let updated = json!({
+            "enable_arcade_zoom": true,
+            "sell_multiplier": 1.5,
+            "zoom_levels": [14.0, 7.0, 1.0, 0.5],
+            "added": 2,
+        });
+
+        assert_eq!(
+            changed_settings(&current, &updated),
+            ["added", "removed", "sell_multiplier", "zoom_levels"]
+        );
+        assert!(changed_settings(&current, &current).is_empty());
+    }
+}
@@ -10,102 +10,102 @@
 //!   v1.151: ADD dword ptr [RAX + 0x260], EBX
 //!   v1.163: ADD dword ptr [RAX + 0x2a8], ECX
 //!
-//! We replace it with our own logic that multiplies the price by the configured
+//! We replace it with a context hook that multiplies the price by the configured
 //! multiplier before adding it to the player's cash.
 
-use std::arch::naked_asm;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 
-use crate::patchy::{Patch, ReturnType};
+use crate::{
+    config::Config,
+    feature::Feature,
+    patchy::{Context, Patch, PatchError},
+    structs::game::Profile,
+    version::{addresses, game_version, GameVersion},
+};
 
-/// The sell price multiplier, written once at init before any patch callback fires.
-static mut SELL_MULTIPLIER: f32 = 1.0;
+/// Bit pattern of the `f32` sell price multiplier.
+static SELL_MULTIPLIER: AtomicU32 = AtomicU32::new(1.0f32.to_bits());
 
-/// The byte offset of `cash` within the Profile struct (version-dependent).
-#[cfg(feature = "1_151")]
-const CASH_OFFSET: usize = 0x260;
-#[cfg(any(feature = "1_163", not(any(feature = "1_151", feature = "1_163"))))]
-const CASH_OFFSET: usize = 0x2a8;
-
-/// Address of the ADD instruction inside TransferShopItemToPlayer.
-///   v1.151: ADD dword ptr [RAX + 0x260], EBX   (6 bytes)
-///   v1.163: ADD dword ptr [RAX + 0x2a8], ECX   (6 bytes)
-#[cfg(feature = "1_151")]
-const PATCH_ADDRESS: usize = 0x140204467;
-#[cfg(any(feature = "1_163", not(any(feature = "1_151", feature = "1_163"))))]
-const PATCH_ADDRESS: usize = 0x140221a3f;
+/// Set once the sell-multiplier patch has been prepared.
+static PATCHED: AtomicBool = AtomicBool::new(false);
 
 /// Size of the overwritten instruction (6 bytes in both versions).
 const PATCH_SIZE: usize = 6;
 
+/// Multiplies the money received when selling a part.
+pub struct SellMultiplier;
+
+impl Feature for SellMultiplier {
+    type Settings = f32;
+
+    const NAME: &'static str = "Sell multiplier";
+
+    fn settings(config: &Config) -> Option<f32> {
+        Some(config.sell_multiplier)
+    }
+
+    unsafe fn install(multiplier: f32) -> Result<Vec<Patch>, PatchError> {
+        patch_sell_multiplier(multiplier)
+    }
+
+    fn reload(multiplier: f32) -> bool {
+        set_multiplier(multiplier)
+    }
+}
+
 /// Installs the sell-multiplier patch if the multiplier differs from 1.0.
-pub unsafe fn patch_sell_multiplier(multiplier: f32) {
+unsafe fn patch_sell_multiplier(multiplier: f32) -> Result<Vec<Patch>, PatchError> {
     if (multiplier - 1.0).abs() < f32::EPSILON {
         log::info!("Sell multiplier is 1.0, skipping patch.");
-        return;
-    }
-
-    if PATCH_ADDRESS == 0x0 {
-        log::warn!("Sell multiplier patch is not supported on this game version.");
-        return;
+        return Ok(Vec::new());
     }
 
-    SELL_MULTIPLIER = multiplier;
+    let addresses = addresses();
+    let patch_address = addresses.sell_hook;
+    set_multiplier(multiplier);
 
-    let p = Patch::patch_call(
-        PATCH_ADDRESS,
-        trampoline as *const (),
+    let p = Patch::hook_with_context(
+        patch_address,
+        addresses.original_bytes("sell.hook")?,
         PATCH_SIZE,
         false,
-        ReturnType::None,
-    );
-    std::mem::forget(p);
+        apply_sell_multiplier,
+    )?;
+    PATCHED.store(true, Ordering::Relaxed);
 
     log::info!(
-        "Sell multiplier patch installed at {PATCH_ADDRESS:#x} (multiplier: {multiplier:.2}x)"
+        "Sell multiplier patch installed at {patch_address:#x} (multiplier: {multiplier:.2}x)"
     );
+    Ok(vec![p])
 }
 
-/// Naked trampoline that shuttles live register values into the Windows x64
-/// calling convention (RCX, RDX) and tail-calls the pure-Rust helper.
-///
-/// On entry (after patchy's register save sequence):
-///   RAX = profile pointer  (not clobbered — patchy pushes it but doesn't modify it)
-///   v1.151: EBX = sale price (non-volatile, untouched by patchy)
-///   v1.163: ECX = sale price (saved/restored by patchy as a volatile register,
-///           but the value is still live in RCX at our entry point because patchy
-///           pushes RCX early in SAVE_REGISTERS and the push doesn't modify it)
+/// Changes the multiplier applied by the installed patch.
 ///
-/// We move the profile pointer into RCX and the price into EDX, then JMP to
-/// the helper.
-#[unsafe(naked)]
-#[cfg(feature = "1_151")]
-unsafe extern "C" fn trampoline() {
-    naked_asm!(
-        "mov rcx, rax",
-        "mov edx, ebx",
-        "jmp {helper}",
-        helper = sym apply_sell_multiplier,
-    );
+/// Returns false if the patch was skipped at startup, so the multiplier has no effect.
+fn set_multiplier(multiplier: f32) -> bool {
+    SELL_MULTIPLIER.store(multiplier.to_bits(), Ordering::Relaxed);
+    PATCHED.load(Ordering::Relaxed)
 }
 
-#[unsafe(naked)]
-#[cfg(any(feature = "1_163", not(any(feature = "1_151", feature = "1_163"))))]
-unsafe extern "C" fn trampoline() {
-    naked_asm!(
-        "mov edx, ecx",
-        "mov rcx, rax",
-        "jmp {helper}",
-        helper = sym apply_sell_multiplier,
-    );
-}
+/// Adds the multiplied sale price to the player's cash in place of the
+/// overwritten instruction.
+extern "C" fn apply_sell_multiplier(context: &mut Context) {
+    let price = match game_version() {
+        GameVersion::V1_151 => context.rbx as i32,
+        GameVersion::V1_163 => context.rcx as i32,
+    };
+    let multiplier = f32::from_bits(SELL_MULTIPLIER.load(Ordering::Relaxed));
+    let adjusted_price = (price as f32 * multiplier) as i32;
 
-/// Pure-Rust helper that applies the sell multiplier.
-///
-/// Receives the profile pointer and price directly as function arguments
-/// (forwarded from the trampoline via RCX and EDX).
-unsafe extern "C" fn apply_sell_multiplier(profile_ptr: *mut u8, price: i32) {
-    let cash_ptr = profile_ptr.add(CASH_OFFSET) as *mut i32;
-    let adjusted_price = (price as f32 * SELL_MULTIPLIER) as i32;
-    let new_cash = cash_ptr.read() + adjusted_price;
-    cash_ptr.write(new_cash);
+    let profile = Profile::at(context.rax as usize);
+    // SAFETY: RAX holds the profile, and the game thread running the hook is the
+    // one updating the cash.
+    let added = unsafe {
+        profile
+            .cash()
+            .and_then(|cash| profile.set_cash(cash.saturating_add(adjusted_price)))
+    };
+    if let Err(error) = added {
+        log::error!("Unable to add {adjusted_price} to the player's cash: {error}");
+    }
 }
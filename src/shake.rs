@@ -1,19 +1,32 @@
-use crate::patchy::Patch;
+use crate::{
+    config::Config,
+    feature::Feature,
+    patchy::{Patch, PatchError},
+    version::addresses,
+};
 
-pub unsafe fn patch_shake() {
-    let address;
-    if cfg!(feature = "1_151") {
-        address = 0x1403285e0;
-    } else if cfg!(feature = "1_163") {
-        address = 0x140354758;
-    } else {
-        // Default to 1.163
-        address = 0x140354758;
+/// Reduces the screen shake in the battle screen.
+pub struct ReducedShake;
+
+impl Feature for ReducedShake {
+    type Settings = ();
+
+    const NAME: &'static str = "Reduced shake";
+
+    fn settings(config: &Config) -> Option<()> {
+        config.enable_reduced_shake.then_some(())
     }
 
-    // Hex representation of float 1.0
-    let data = [0x00, 0x00, 0x80, 0x3F];
+    unsafe fn install((): ()) -> Result<Vec<Patch>, PatchError> {
+        // Hex representation of float 1.0
+        let data = [0x00, 0x00, 0x80, 0x3F];
 
-    let p = Patch::overwrite(address, &data);
-    std::mem::forget(p);
+        let addresses = addresses();
+        let p = Patch::overwrite(
+            addresses.shake_multiplier,
+            addresses.original_bytes("shake.multiplier")?,
+            &data,
+        )?;
+        Ok(vec![p])
+    }
 }
@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+use crate::mem;
+
 #[derive(Debug)]
 /// Basically a c std::Vector
 pub struct CVec<T> {
@@ -120,6 +122,12 @@ impl<T> From<&CVec<T>> for Vec<&T> {
             log::warn!("CVec has an unusually high number of items: {}", count);
         }
 
+        let size = count as usize * std::mem::size_of::<T>();
+        if let Err(error) = mem::check_readable(cvec.items as usize, size) {
+            log::error!("CVec items cannot be read, returning empty Vec: {error}");
+            return Vec::new();
+        }
+
         let mut result = Vec::with_capacity(count as usize);
 
         unsafe {
@@ -0,0 +1,297 @@
+//! Typed access to the game's structs.
+//!
+//! The game structs are too large and too version-dependent to mirror with
+//! `#[repr(C)]` types, so each type here wraps the address of a struct and reads
+//! its fields through [`mem`], at the offsets the selected profile gives in
+//! [`StructOffsets`](crate::version::StructOffsets). Supporting a new game version
+//! only needs new offsets in its profile.
+
+use std::mem::size_of;
+
+use crate::{
+    mem::{self, MemoryError},
+    version::{addresses, BodyOffsets, CityOffsets, ProfileOffsets, ShipPartOffsets},
+};
+
+/// Type code of Body nodes, in the low bits of `m_code`.
+const CODE_BODY: u8 = 0x0F;
+
+/// The player's profile.
+#[derive(Debug, Clone, Copy)]
+pub struct Profile {
+    address: usize,
+    offsets: &'static ProfileOffsets,
+}
+
+impl Profile {
+    /// The profile at `address`.
+    pub fn at(address: usize) -> Self {
+        Self {
+            address,
+            offsets: &addresses().structs.profile,
+        }
+    }
+
+    /// The player's cash.
+    ///
+    /// # Safety
+    /// `self` must point to a profile.
+    pub unsafe fn cash(self) -> Result<i32, MemoryError> {
+        mem::read(self.address + self.offsets.cash)
+    }
+
+    /// Sets the player's cash.
+    ///
+    /// # Safety
+    /// `self` must point to a profile, and only the game thread may call this.
+    pub unsafe fn set_cash(self, cash: i32) -> Result<(), MemoryError> {
+        mem::write(self.address + self.offsets.cash, cash)
+    }
+}
+
+/// The city the player is in.
+#[derive(Debug, Clone, Copy)]
+pub struct City {
+    address: usize,
+    offsets: &'static CityOffsets,
+}
+
+impl City {
+    /// The current city object.
+    ///
+    /// Fails with [`MemoryError::NullPointer`] outside of cities.
+    ///
+    /// # Safety
+    /// No other thread may be replacing the city object.
+    pub unsafe fn current() -> Result<Self, MemoryError> {
+        let addresses = addresses();
+        Ok(Self {
+            address: mem::resolve(addresses.parts_city, &[0])?,
+            offsets: &addresses.structs.city,
+        })
+    }
+
+    /// The city type, 1–7 for valid cities.
+    ///
+    /// # Safety
+    /// `self` must point to a city object.
+    pub unsafe fn city_type(self) -> Result<i32, MemoryError> {
+        mem::read(self.address + self.offsets.city_type)
+    }
+
+    /// The category library node used when the city defines shop parts.
+    ///
+    /// # Safety
+    /// `self` must point to a city object.
+    pub unsafe fn category_node(self) -> Result<*const u8, MemoryError> {
+        mem::read(self.address + self.offsets.category_node)
+    }
+}
+
+/// A node of a ship, e.g. a part or a gun.
+#[derive(Debug, Clone, Copy)]
+pub struct Body {
+    address: usize,
+    offsets: &'static BodyOffsets,
+}
+
+impl Body {
+    /// The body at `address`.
+    pub fn at(address: usize) -> Self {
+        Self::with_offsets(address, &addresses().structs.body)
+    }
+
+    fn with_offsets(address: usize, offsets: &'static BodyOffsets) -> Self {
+        Self { address, offsets }
+    }
+
+    /// Index of the body's part, as passed to `GetStats`.
+    ///
+    /// # Safety
+    /// `self` must point to a body.
+    pub unsafe fn part_index(self) -> Result<i32, MemoryError> {
+        mem::read(self.address + self.offsets.part_index)
+    }
+
+    /// The node owning this body, if any.
+    ///
+    /// # Safety
+    /// `self` must point to a body.
+    pub unsafe fn owner(self) -> Result<Option<Self>, MemoryError> {
+        self.node(self.offsets.owner_node)
+    }
+
+    /// The body this one is attached to, if it is attached to a body rather
+    /// than another kind of node.
+    ///
+    /// # Safety
+    /// `self` must point to a body.
+    pub unsafe fn master_body(self) -> Result<Option<Self>, MemoryError> {
+        let Some(master) = self.node(self.offsets.master_node)? else {
+            return Ok(None);
+        };
+        let code = mem::read::<u8>(master.address + self.offsets.m_code)?;
+        Ok((code & 0x0F == CODE_BODY).then_some(master))
+    }
+
+    /// Rotation of the body in radians.
+    ///
+    /// # Safety
+    /// `self` must point to a body.
+    pub unsafe fn angle(self) -> Result<f32, MemoryError> {
+        mem::read(self.address + self.offsets.angle)
+    }
+
+    /// The firing sectors of a gun body.
+    ///
+    /// # Safety
+    /// `self` must point to a body.
+    pub unsafe fn sectors(self) -> Result<Sectors, MemoryError> {
+        let begin = mem::read::<usize>(self.address + self.offsets.sectors_begin)?;
+        let end = mem::read::<usize>(self.address + self.offsets.sectors_end)?;
+        let len = if begin == 0 {
+            0
+        } else {
+            end.saturating_sub(begin) / size_of::<f32>()
+        };
+        Ok(Sectors { begin, len })
+    }
+
+    unsafe fn node(self, offset: usize) -> Result<Option<Self>, MemoryError> {
+        let node = mem::read::<usize>(self.address + offset)?;
+        Ok((node != 0).then(|| Self::with_offsets(node, self.offsets)))
+    }
+}
+
+/// The firing sectors of a gun, one float per sector that is zero where the
+/// gun's own ship blocks it.
+#[derive(Debug, Clone, Copy)]
+pub struct Sectors {
+    begin: usize,
+    len: usize,
+}
+
+impl Sectors {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// The sector at `index`, or `None` if it is out of range.
+    ///
+    /// # Safety
+    /// The body the sectors were read from must still exist.
+    pub unsafe fn get(&self, index: usize) -> Result<Option<f32>, MemoryError> {
+        if index >= self.len {
+            return Ok(None);
+        }
+        mem::read(self.begin + index * size_of::<f32>()).map(Some)
+    }
+}
+
+/// The stats of a part, as returned by `GetStats`.
+#[derive(Debug, Clone, Copy)]
+pub struct ShipPart {
+    address: usize,
+    offsets: &'static ShipPartOffsets,
+}
+
+impl ShipPart {
+    /// The ship part at `address`, or `None` if the ShipPart layout of the
+    /// running game is unknown.
+    pub fn at(address: usize) -> Option<Self> {
+        let offsets = addresses().structs.ship_part.as_ref()?;
+        Some(Self { address, offsets })
+    }
+
+    /// Whether the part has firing sectors.
+    ///
+    /// # Safety
+    /// `self` must point to a ship part.
+    pub unsafe fn has_sectors(self) -> Result<bool, MemoryError> {
+        Ok(mem::read::<i32>(self.address + self.offsets.sectors_type)? != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static OFFSETS: BodyOffsets = BodyOffsets {
+        m_code: 0x08,
+        master_node: 0x10,
+        owner_node: 0x18,
+        angle: 0x20,
+        part_index: 0x24,
+        sectors_begin: 0x28,
+        sectors_end: 0x30,
+    };
+
+    /// Memory laid out as a body with [`OFFSETS`].
+    #[repr(C, align(8))]
+    struct FakeBody([u8; 0x38]);
+
+    impl FakeBody {
+        fn new() -> Self {
+            Self([0; 0x38])
+        }
+
+        fn set<T>(&mut self, offset: usize, value: T) {
+            unsafe { (self.0.as_mut_ptr().add(offset) as *mut T).write_unaligned(value) }
+        }
+
+        fn body(&self) -> Body {
+            Body::with_offsets(self.0.as_ptr() as usize, &OFFSETS)
+        }
+    }
+
+    #[test]
+    fn body_fields_are_read_at_their_offsets() {
+        let mut hull = FakeBody::new();
+        hull.set(OFFSETS.m_code, 0x3fu8);
+        let mut turret = FakeBody::new();
+        turret.set(OFFSETS.m_code, 0x21u8);
+        let mut gun = FakeBody::new();
+        gun.set(OFFSETS.angle, 1.5f32);
+        gun.set(OFFSETS.part_index, 42i32);
+        gun.set(OFFSETS.owner_node, hull.body().address);
+        gun.set(OFFSETS.master_node, hull.body().address);
+        let mut mounted = FakeBody::new();
+        mounted.set(OFFSETS.master_node, turret.body().address);
+
+        unsafe {
+            let gun = gun.body();
+            assert_eq!(gun.angle(), Ok(1.5));
+            assert_eq!(gun.part_index(), Ok(42));
+            assert_eq!(
+                gun.owner().unwrap().map(|body| body.address),
+                Some(hull.body().address)
+            );
+            assert_eq!(
+                gun.master_body().unwrap().map(|body| body.address),
+                Some(hull.body().address)
+            );
+            // Attached to a node that is not a body.
+            assert!(mounted.body().master_body().unwrap().is_none());
+            assert!(hull.body().owner().unwrap().is_none());
+            assert!(Body::with_offsets(0x10, &OFFSETS).angle().is_err());
+        }
+    }
+
+    #[test]
+    fn sectors_are_bounded_by_their_array() {
+        let sectors = [1.0f32, 0.0, 1.0];
+        let mut gun = FakeBody::new();
+        gun.set(OFFSETS.sectors_begin, sectors.as_ptr() as usize);
+        gun.set(OFFSETS.sectors_end, sectors.as_ptr() as usize + 12);
+
+        unsafe {
+            let read = gun.body().sectors().unwrap();
+            assert_eq!(read.len(), 3);
+            assert_eq!(read.get(1), Ok(Some(0.0)));
+            assert_eq!(read.get(3), Ok(None));
+
+            gun.set(OFFSETS.sectors_begin, 0usize);
+            assert_eq!(gun.body().sectors().unwrap().len(), 0);
+        }
+    }
+}
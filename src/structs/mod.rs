@@ -1,4 +1,5 @@
 pub mod cvec;
+pub mod game;
 pub mod loadout;
 pub mod plane;
 pub mod tll;
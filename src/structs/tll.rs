@@ -1,4 +1,9 @@
-use std::{fmt, hash::Hash};
+use std::{
+    fmt,
+    hash::Hash,
+    marker::PhantomData,
+    ops::{Bound, RangeBounds},
+};
 
 #[repr(C)]
 #[derive(Debug, Clone)]
@@ -96,6 +101,7 @@ impl<T, U> TllContainer<T, U> {
         self.get_sentinel().into()
     }
 
+    #[allow(dead_code)]
     pub fn get_map(&mut self) -> std::collections::HashMap<&T, &mut U>
     where
         T: Eq + Hash,
@@ -103,6 +109,145 @@ impl<T, U> TllContainer<T, U> {
         self.get_sentinel().into()
     }
 
+    /// Returns the value of `key`.
+    pub fn get(&self, key: &T) -> Option<&U>
+    where
+        T: Ord,
+    {
+        unsafe {
+            let node = self.find(key);
+            (!(*node).is_sentinel).then(|| &(*node).data)
+        }
+    }
+
+    /// Returns the value of `key` for modification.
+    #[allow(dead_code)]
+    pub fn get_mut(&mut self, key: &T) -> Option<&mut U>
+    where
+        T: Ord,
+    {
+        unsafe {
+            let node = self.find(key);
+            (!(*node).is_sentinel).then(|| &mut (*node).data)
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn contains_key(&self, key: &T) -> bool
+    where
+        T: Ord,
+    {
+        unsafe { !(*self.find(key)).is_sentinel }
+    }
+
+    /// Returns the first entry whose key is not less than `key`, like
+    /// `std::map::lower_bound`.
+    #[allow(dead_code)]
+    pub fn lower_bound(&self, key: &T) -> Option<(&T, &U)>
+    where
+        T: Ord,
+    {
+        unsafe {
+            let node = self.lower_bound_node(key);
+            (!(*node).is_sentinel).then(|| (&(*node).key, &(*node).data))
+        }
+    }
+
+    /// Iterates over the entries with keys in `range`, in key order.
+    #[allow(dead_code)]
+    pub fn range(&self, range: impl RangeBounds<T>) -> Iter<'_, T, U>
+    where
+        T: Ord,
+    {
+        unsafe {
+            let (front, back) = self.range_nodes(range);
+            Iter {
+                front,
+                back,
+                _map: PhantomData,
+            }
+        }
+    }
+
+    /// Iterates over the entries in key order.
+    pub fn iter(&self) -> Iter<'_, T, U> {
+        Iter {
+            front: unsafe { (*self.sentinel).left },
+            back: self.sentinel,
+            _map: PhantomData,
+        }
+    }
+
+    /// Iterates over the entries in key order, allowing their values to be modified.
+    #[allow(dead_code)]
+    pub fn iter_mut(&mut self) -> IterMut<'_, T, U> {
+        IterMut {
+            front: unsafe { (*self.sentinel).left },
+            back: self.sentinel,
+            _map: PhantomData,
+        }
+    }
+
+    /// Returns the first node whose key is not less than `key`, or the sentinel.
+    unsafe fn lower_bound_node(&self, key: &T) -> *mut Tll<T, U>
+    where
+        T: Ord,
+    {
+        let mut result = self.sentinel;
+        let mut current = (*self.sentinel).parent;
+        while !(*current).is_sentinel {
+            if (*current).key < *key {
+                current = (*current).right;
+            } else {
+                result = current;
+                current = (*current).left;
+            }
+        }
+        result
+    }
+
+    /// Returns the first node whose key is greater than `key`, or the sentinel.
+    unsafe fn upper_bound_node(&self, key: &T) -> *mut Tll<T, U>
+    where
+        T: Ord,
+    {
+        let mut result = self.sentinel;
+        let mut current = (*self.sentinel).parent;
+        while !(*current).is_sentinel {
+            if (*current).key <= *key {
+                current = (*current).right;
+            } else {
+                result = current;
+                current = (*current).left;
+            }
+        }
+        result
+    }
+
+    /// Returns the first node in `range` and the node after the last one,
+    /// which are the same if the range is empty.
+    unsafe fn range_nodes(&self, range: impl RangeBounds<T>) -> (*mut Tll<T, U>, *mut Tll<T, U>)
+    where
+        T: Ord,
+    {
+        let front = match range.start_bound() {
+            Bound::Included(key) => self.lower_bound_node(key),
+            Bound::Excluded(key) => self.upper_bound_node(key),
+            Bound::Unbounded => (*self.sentinel).left,
+        };
+        let back = match range.end_bound() {
+            Bound::Included(key) => self.upper_bound_node(key),
+            Bound::Excluded(key) => self.lower_bound_node(key),
+            Bound::Unbounded => self.sentinel,
+        };
+
+        // A range starting past the last key or ending before it starts is empty
+        if (*front).is_sentinel || (!(*back).is_sentinel && (*front).key > (*back).key) {
+            return (back, back);
+        }
+        (front, back)
+    }
+
     pub fn insert(&mut self, key: T, value: U)
     where
         T: Ord + fmt::Debug,
@@ -226,6 +371,196 @@ impl<T, U> TllContainer<T, U> {
         (*(*sentinel).parent).is_black = true;
     }
 
+    /// Removes `key` from the map and returns its value.
+    ///
+    /// # Safety
+    /// The removed node is released with this module's `libc::free`, so every
+    /// node of the map must have been allocated by the same C runtime, as the
+    /// nodes created by `new` and `insert` are. Maps owned by the game are
+    /// allocated by the game's C runtime, which need not be ours, and must not
+    /// be shrunk with this.
+    #[allow(dead_code)]
+    pub unsafe fn remove(&mut self, key: &T) -> Option<U>
+    where
+        T: Ord,
+    {
+        let node = self.find(key);
+        if (*node).is_sentinel {
+            return None;
+        }
+
+        self.unlink(node);
+        self.size -= 1;
+        self.update_sentinel_min_max();
+
+        let Tll { key, data, .. } = std::ptr::read(node);
+        libc::free(node as *mut libc::c_void);
+        drop(key);
+        Some(data)
+    }
+
+    /// Returns the node holding `key`, or the sentinel if there is none.
+    unsafe fn find(&self, key: &T) -> *mut Tll<T, U>
+    where
+        T: Ord,
+    {
+        let mut current = (*self.sentinel).parent;
+        while !(*current).is_sentinel {
+            if *key < (*current).key {
+                current = (*current).left;
+            } else if *key > (*current).key {
+                current = (*current).right;
+            } else {
+                break;
+            }
+        }
+        current
+    }
+
+    /// Detaches `node` from the tree and restores the red-black properties.
+    ///
+    /// A node with two children is replaced by its in-order successor, which
+    /// takes over its color, so the color removed from the tree is the
+    /// successor's. The sentinel can't record the parent of a removed leaf the
+    /// way a nil node does, so that parent is tracked separately.
+    unsafe fn unlink(&mut self, node: *mut Tll<T, U>) {
+        let sentinel = self.sentinel;
+
+        let replacement;
+        let replacement_parent;
+        let removed_black;
+
+        if (*(*node).left).is_sentinel || (*(*node).right).is_sentinel {
+            // At most one child, which takes the node's place
+            replacement = if (*(*node).left).is_sentinel {
+                (*node).right
+            } else {
+                (*node).left
+            };
+            replacement_parent = (*node).parent;
+            if !(*replacement).is_sentinel {
+                (*replacement).parent = replacement_parent;
+            }
+            self.replace_child(node, replacement);
+            removed_black = (*node).is_black;
+        } else {
+            // Two children: move the successor into the node's place
+            let mut successor = (*node).right;
+            while !(*(*successor).left).is_sentinel {
+                successor = (*successor).left;
+            }
+            replacement = (*successor).right;
+
+            if std::ptr::eq(successor, (*node).right) {
+                replacement_parent = successor;
+            } else {
+                replacement_parent = (*successor).parent;
+                if !(*replacement).is_sentinel {
+                    (*replacement).parent = replacement_parent;
+                }
+                (*replacement_parent).left = replacement;
+                (*successor).right = (*node).right;
+                (*(*successor).right).parent = successor;
+            }
+
+            (*successor).left = (*node).left;
+            (*(*successor).left).parent = successor;
+            self.replace_child(node, successor);
+            (*successor).parent = (*node).parent;
+
+            removed_black = (*successor).is_black;
+            (*successor).is_black = (*node).is_black;
+        }
+
+        if removed_black {
+            self.remove_fixup(replacement, replacement_parent);
+        }
+
+        // The sentinel stays black, even if it ended up as the replacement
+        (*sentinel).is_black = true;
+    }
+
+    /// Points the parent of `node` (or the sentinel, for the root) at `child` instead.
+    unsafe fn replace_child(&mut self, node: *mut Tll<T, U>, child: *mut Tll<T, U>) {
+        let parent = (*node).parent;
+        if (*parent).is_sentinel {
+            (*self.sentinel).parent = child;
+        } else if std::ptr::eq((*parent).left, node) {
+            (*parent).left = child;
+        } else {
+            (*parent).right = child;
+        }
+    }
+
+    /// Rebalances the tree after a black node was removed above `node`, which
+    /// counts as an extra black until the deficit is resolved.
+    unsafe fn remove_fixup(&mut self, mut node: *mut Tll<T, U>, mut parent: *mut Tll<T, U>) {
+        let sentinel = self.sentinel;
+
+        while !std::ptr::eq(node, (*sentinel).parent) && (*node).is_black {
+            if std::ptr::eq(node, (*parent).left) {
+                let mut sibling = (*parent).right;
+
+                if !(*sibling).is_black {
+                    (*sibling).is_black = true;
+                    (*parent).is_black = false;
+                    (*parent).rotate_left(sentinel);
+                    sibling = (*parent).right;
+                }
+
+                if (*(*sibling).left).is_black && (*(*sibling).right).is_black {
+                    (*sibling).is_black = false;
+                    node = parent;
+                    parent = (*node).parent;
+                } else {
+                    if (*(*sibling).right).is_black {
+                        (*(*sibling).left).is_black = true;
+                        (*sibling).is_black = false;
+                        (*sibling).rotate_right(sentinel);
+                        sibling = (*parent).right;
+                    }
+
+                    (*sibling).is_black = (*parent).is_black;
+                    (*parent).is_black = true;
+                    (*(*sibling).right).is_black = true;
+                    (*parent).rotate_left(sentinel);
+                    node = (*sentinel).parent;
+                }
+            } else {
+                // Mirror cases for right side
+                let mut sibling = (*parent).left;
+
+                if !(*sibling).is_black {
+                    (*sibling).is_black = true;
+                    (*parent).is_black = false;
+                    (*parent).rotate_right(sentinel);
+                    sibling = (*parent).left;
+                }
+
+                if (*(*sibling).right).is_black && (*(*sibling).left).is_black {
+                    (*sibling).is_black = false;
+                    node = parent;
+                    parent = (*node).parent;
+                } else {
+                    if (*(*sibling).left).is_black {
+                        (*(*sibling).right).is_black = true;
+                        (*sibling).is_black = false;
+                        (*sibling).rotate_left(sentinel);
+                        sibling = (*parent).left;
+                    }
+
+                    (*sibling).is_black = (*parent).is_black;
+                    (*parent).is_black = true;
+                    (*(*sibling).left).is_black = true;
+                    (*parent).rotate_right(sentinel);
+                    node = (*sentinel).parent;
+                }
+            }
+        }
+
+        (*node).is_black = true;
+    }
+
     unsafe fn update_sentinel_min_max(&mut self) {
         let sentinel = self.sentinel;
         let root = (*sentinel).parent;
@@ -334,6 +669,49 @@ impl<T, U> Tll<T, U> {
         x
     }
 
+    /// Returns the node following `node` in key order, or the sentinel after
+    /// the last one.
+    ///
+    /// Like the MSVC iterator, this climbs the parent links instead of keeping a stack.
+    unsafe fn successor(mut node: *mut Self) -> *mut Self {
+        if !(*(*node).right).is_sentinel {
+            node = (*node).right;
+            while !(*(*node).left).is_sentinel {
+                node = (*node).left;
+            }
+            return node;
+        }
+
+        let mut parent = (*node).parent;
+        while !(*parent).is_sentinel && std::ptr::eq(node, (*parent).right) {
+            node = parent;
+            parent = (*parent).parent;
+        }
+        parent
+    }
+
+    /// Returns the node preceding `node` in key order. The node preceding the
+    /// sentinel is the last one.
+    unsafe fn predecessor(mut node: *mut Self) -> *mut Self {
+        if (*node).is_sentinel {
+            return (*node).right;
+        }
+        if !(*(*node).left).is_sentinel {
+            node = (*node).left;
+            while !(*(*node).right).is_sentinel {
+                node = (*node).right;
+            }
+            return node;
+        }
+
+        let mut parent = (*node).parent;
+        while !(*parent).is_sentinel && std::ptr::eq(node, (*parent).left) {
+            node = parent;
+            parent = (*parent).parent;
+        }
+        parent
+    }
+
     unsafe fn grandparent(&self) -> Option<*mut Tll<T, U>> {
         if (*self.parent).is_sentinel || (*(*self.parent).parent).is_sentinel {
             None
@@ -343,6 +721,79 @@ impl<T, U> Tll<T, U> {
     }
 }
 
+/// An in-order iterator over the entries of a [`TllContainer`].
+///
+/// `back` is the node after the last entry still to be returned, so the
+/// iterator is exhausted once `front` reaches it.
+pub struct Iter<'a, T, U> {
+    front: *mut Tll<T, U>,
+    back: *mut Tll<T, U>,
+    _map: PhantomData<&'a Tll<T, U>>,
+}
+
+impl<'a, T, U> Iterator for Iter<'a, T, U> {
+    type Item = (&'a T, &'a U);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if std::ptr::eq(self.front, self.back) {
+            return None;
+        }
+        unsafe {
+            let node = self.front;
+            self.front = Tll::successor(node);
+            Some((&(*node).key, &(*node).data))
+        }
+    }
+}
+
+impl<T, U> DoubleEndedIterator for Iter<'_, T, U> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if std::ptr::eq(self.front, self.back) {
+            return None;
+        }
+        unsafe {
+            self.back = Tll::predecessor(self.back);
+            Some((&(*self.back).key, &(*self.back).data))
+        }
+    }
+}
+
+/// An in-order iterator over the entries of a [`TllContainer`] that allows
+/// their values to be modified.
+#[allow(dead_code)]
+pub struct IterMut<'a, T, U> {
+    front: *mut Tll<T, U>,
+    back: *mut Tll<T, U>,
+    _map: PhantomData<&'a mut Tll<T, U>>,
+}
+
+impl<'a, T, U> Iterator for IterMut<'a, T, U> {
+    type Item = (&'a T, &'a mut U);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if std::ptr::eq(self.front, self.back) {
+            return None;
+        }
+        unsafe {
+            let node = self.front;
+            self.front = Tll::successor(node);
+            Some((&(*node).key, &mut (*node).data))
+        }
+    }
+}
+
+impl<T, U> DoubleEndedIterator for IterMut<'_, T, U> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if std::ptr::eq(self.front, self.back) {
+            return None;
+        }
+        unsafe {
+            self.back = Tll::predecessor(self.back);
+            Some((&(*self.back).key, &mut (*self.back).data))
+        }
+    }
+}
+
 impl<T, U> Drop for TllContainer<T, U> {
     fn drop(&mut self) {
         unsafe {
@@ -1593,4 +2044,299 @@ mod tests {
             assert_eq!(keys, expected, "Loadouts should be in alphabetical order");
         }
     }
+
+    // Helper function to check the keys, sentinel links and red-black properties after a removal
+    unsafe fn verify_tree(container: &TllContainer<i32, String>, expected: &[i32]) {
+        let sentinel = container.sentinel;
+        let root = (*sentinel).parent;
+
+        let mut result = Vec::new();
+        in_order_traverse(root, &mut result);
+        let keys: Vec<i32> = result.iter().map(|(key, _)| **key).collect();
+        assert_eq!(keys, expected, "Keys should be sorted and complete");
+        assert_eq!(container.len(), expected.len());
+
+        if expected.is_empty() {
+            assert_eq!(root, sentinel, "Empty tree should have no root");
+            assert_eq!((*sentinel).left, sentinel, "Empty tree should have no min");
+            assert_eq!((*sentinel).right, sentinel, "Empty tree should have no max");
+            return;
+        }
+
+        assert!((*root).is_black, "Root must be black");
+        assert_eq!(
+            (*root).parent,
+            sentinel,
+            "Root's parent should be the sentinel"
+        );
+        assert!((*sentinel).is_black, "Sentinel must stay black");
+        let (valid, _) = verify_rb_properties(root);
+        assert!(valid, "Red-black tree properties violated");
+
+        assert_eq!((*(*sentinel).left).key, expected[0], "Wrong min");
+        assert_eq!(
+            (*(*sentinel).right).key,
+            expected[expected.len() - 1],
+            "Wrong max"
+        );
+    }
+
+    fn container_with(keys: &[i32]) -> TllContainer<i32, String> {
+        let mut container = TllContainer::<i32, String>::new();
+        for &key in keys {
+            container.insert(key, format!("value_{}", key));
+        }
+        container
+    }
+
+    #[test]
+    fn test_remove_returns_value() {
+        let mut container = container_with(&[50, 30, 70]);
+
+        assert_eq!(
+            unsafe { container.remove(&30) },
+            Some("value_30".to_string())
+        );
+        assert_eq!(unsafe { container.remove(&30) }, None, "Key should be gone");
+        assert_eq!(
+            unsafe { container.remove(&99) },
+            None,
+            "Missing key removes nothing"
+        );
+
+        unsafe { verify_tree(&container, &[50, 70]) };
+    }
+
+    #[test]
+    fn test_remove_leaf_one_child_and_two_children() {
+        //         50
+        //       /    \
+        //     25      75
+        //    /  \    /
+        //   10  30  60
+        let mut container = container_with(&[50, 25, 75, 10, 30, 60]);
+
+        // Leaf
+        unsafe { container.remove(&10) };
+        unsafe { verify_tree(&container, &[25, 30, 50, 60, 75]) };
+
+        // One child
+        unsafe { container.remove(&75) };
+        unsafe { verify_tree(&container, &[25, 30, 50, 60]) };
+
+        // Two children, the root
+        unsafe { container.remove(&50) };
+        unsafe { verify_tree(&container, &[25, 30, 60]) };
+    }
+
+    #[test]
+    fn test_remove_successor_deeper_than_right_child() {
+        // The successor of 20 is 25, the leftmost node of its right subtree
+        let mut container = container_with(&[20, 10, 40, 5, 15, 30, 50, 25, 35]);
+
+        unsafe { container.remove(&20) };
+        unsafe { verify_tree(&container, &[5, 10, 15, 25, 30, 35, 40, 50]) };
+    }
+
+    #[test]
+    fn test_remove_until_empty() {
+        let mut container = container_with(&[3, 1, 2]);
+
+        for (key, remaining) in [(2, &[1, 3][..]), (1, &[3]), (3, &[])] {
+            assert!(unsafe { container.remove(&key) }.is_some());
+            unsafe { verify_tree(&container, remaining) };
+        }
+
+        // The emptied map can be filled again
+        container.insert(7, "seven".to_string());
+        unsafe { verify_tree(&container, &[7]) };
+    }
+
+    #[test]
+    fn test_remove_maintains_rb_properties() {
+        // Insert and remove in scrambled orders to hit every fixup case on both sides
+        let keys: Vec<i32> = (0..128).map(|i| (i * 37) % 128).collect();
+        let mut container = container_with(&keys);
+        let mut expected: Vec<i32> = (0..128).collect();
+
+        for i in 0..128 {
+            let key = (i * 53 + 11) % 128;
+            assert_eq!(
+                unsafe { container.remove(&key) },
+                Some(format!("value_{}", key))
+            );
+            expected.retain(|&k| k != key);
+
+            unsafe { verify_tree(&container, &expected) };
+        }
+    }
+
+    #[test]
+    fn test_remove_ascending_and_descending() {
+        let mut ascending = container_with(&(1..=32).collect::<Vec<_>>());
+        for key in 1..=32 {
+            unsafe { ascending.remove(&key) };
+            unsafe { verify_tree(&ascending, &(key + 1..=32).collect::<Vec<_>>()) };
+        }
+
+        let mut descending = container_with(&(1..=32).collect::<Vec<_>>());
+        for key in (1..=32).rev() {
+            unsafe { descending.remove(&key) };
+            unsafe { verify_tree(&descending, &(1..key).collect::<Vec<_>>()) };
+        }
+    }
+
+    #[test]
+    fn test_remove_drops_key_and_value() {
+        use std::rc::Rc;
+
+        let value = Rc::new(());
+        let mut container = TllContainer::<i32, Rc<()>>::new();
+        container.insert(1, Rc::clone(&value));
+        container.insert(2, Rc::clone(&value));
+
+        let removed = unsafe { container.remove(&1) };
+        assert_eq!(Rc::strong_count(&value), 3, "Removed value is returned");
+        drop(removed);
+        assert_eq!(
+            Rc::strong_count(&value),
+            2,
+            "Node no longer holds the value"
+        );
+    }
+
+    #[test]
+    fn test_get_and_contains_key() {
+        let mut container = container_with(&[50, 25, 75, 10, 30]);
+
+        assert_eq!(container.get(&30).map(String::as_str), Some("value_30"));
+        assert_eq!(container.get(&31), None);
+        assert!(container.contains_key(&10));
+        assert!(!container.contains_key(&100));
+
+        container.get_mut(&75).unwrap().push_str("_updated");
+        assert_eq!(
+            container.get(&75).map(String::as_str),
+            Some("value_75_updated")
+        );
+        assert!(container.get_mut(&76).is_none());
+
+        let empty = TllContainer::<i32, String>::new();
+        assert_eq!(empty.get(&1), None);
+    }
+
+    #[test]
+    fn test_lower_bound() {
+        let container = container_with(&[10, 20, 30, 40]);
+
+        let key = |key| container.lower_bound(&key).map(|(key, _)| *key);
+        assert_eq!(key(5), Some(10));
+        assert_eq!(key(20), Some(20), "Equal key should be its own lower bound");
+        assert_eq!(key(21), Some(30));
+        assert_eq!(key(40), Some(40));
+        assert_eq!(key(41), None, "No key past the maximum");
+    }
+
+    #[test]
+    fn test_iter_in_both_directions() {
+        let keys: Vec<i32> = (0..100).map(|i| (i * 37) % 100).collect();
+        let container = container_with(&keys);
+        let sorted: Vec<i32> = (0..100).collect();
+
+        let forward: Vec<i32> = container.iter().map(|(key, _)| *key).collect();
+        assert_eq!(forward, sorted);
+
+        let backward: Vec<i32> = container.iter().rev().map(|(key, _)| *key).collect();
+        assert_eq!(backward, sorted.iter().rev().copied().collect::<Vec<_>>());
+
+        // Both ends meet without returning an entry twice
+        let mut iter = container.iter();
+        let mut seen = Vec::new();
+        while let Some((front, _)) = iter.next() {
+            seen.push(*front);
+            if let Some((back, _)) = iter.next_back() {
+                seen.push(*back);
+            }
+        }
+        seen.sort();
+        assert_eq!(seen, sorted);
+
+        let empty = TllContainer::<i32, String>::new();
+        assert!(empty.iter().next().is_none());
+        assert!(empty.iter().next_back().is_none());
+    }
+
+    #[test]
+    fn test_iter_mut_modifies_values() {
+        let mut container = container_with(&[3, 1, 2]);
+
+        for (key, value) in container.iter_mut() {
+            *value = format!("{}_{}", value, key * 10);
+        }
+        if let Some((_, last)) = container.iter_mut().next_back() {
+            last.push('!');
+        }
+
+        let values: Vec<&str> = container.iter().map(|(_, value)| value.as_str()).collect();
+        assert_eq!(values, vec!["value_1_10", "value_2_20", "value_3_30!"]);
+    }
+
+    #[test]
+    fn test_range_bounds() {
+        let container = container_with(&[10, 20, 30, 40, 50]);
+        let keys = |range: (Bound<i32>, Bound<i32>)| {
+            container
+                .range(range)
+                .map(|(key, _)| *key)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            container
+                .range(20..40)
+                .map(|(key, _)| *key)
+                .collect::<Vec<_>>(),
+            vec![20, 30]
+        );
+        assert_eq!(
+            container
+                .range(15..=40)
+                .map(|(key, _)| *key)
+                .collect::<Vec<_>>(),
+            vec![20, 30, 40]
+        );
+        assert_eq!(
+            container
+                .range(..)
+                .rev()
+                .map(|(key, _)| *key)
+                .collect::<Vec<_>>(),
+            vec![50, 40, 30, 20, 10]
+        );
+        assert_eq!(
+            keys((Bound::Excluded(10), Bound::Excluded(50))),
+            vec![20, 30, 40]
+        );
+        assert_eq!(keys((Bound::Included(45), Bound::Unbounded)), vec![50]);
+        assert_eq!(
+            keys((Bound::Unbounded, Bound::Included(5))),
+            Vec::<i32>::new()
+        );
+
+        // Empty and inverted ranges yield nothing
+        assert_eq!(container.range(31..40).count(), 0);
+        assert_eq!(container.range(55..).count(), 0);
+        assert_eq!(
+            keys((Bound::Included(60), Bound::Included(5))),
+            Vec::<i32>::new()
+        );
+        assert_eq!(
+            keys((Bound::Included(40), Bound::Excluded(20))),
+            Vec::<i32>::new()
+        );
+        assert_eq!(
+            keys((Bound::Excluded(30), Bound::Excluded(30))),
+            Vec::<i32>::new()
+        );
+    }
 }
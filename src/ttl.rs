@@ -1,17 +1,30 @@
-use crate::patchy::Patch;
+use crate::{
+    config::Config,
+    feature::Feature,
+    patchy::{Patch, PatchError},
+    version::addresses,
+};
 
-pub unsafe fn patch_ttl() {
-    let address;
-    if cfg!(feature = "1_151") {
-        address = 0x140050372;
-    } else if cfg!(feature = "1_163") {
-        address = 0x140052af2;
-    } else {
-        // Default to 1.163
-        address = 0x140052af2;
+/// NOPs out the TTL check.
+pub struct UnblockedTtl;
+
+impl Feature for UnblockedTtl {
+    type Settings = ();
+
+    const NAME: &'static str = "Unblocked TTL";
+
+    fn settings(config: &Config) -> Option<()> {
+        config.enable_unblocked_ttl.then_some(())
     }
 
-    let data = [0x90u8; 4]; // NOP instructions
-    let p = Patch::overwrite(address, &data);
-    std::mem::forget(p);
+    unsafe fn install((): ()) -> Result<Vec<Patch>, PatchError> {
+        let data = [0x90u8; 4]; // NOP instructions
+        let addresses = addresses();
+        let p = Patch::overwrite(
+            addresses.ttl_check,
+            addresses.original_bytes("ttl.check")?,
+            &data,
+        )?;
+        Ok(vec![p])
+    }
 }
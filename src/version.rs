@@ -0,0 +1,223 @@
+//! Game version detection and the address table of the running game.
+//!
+//! The modloader reports the running game through `version()`, which looks up a
+//! matching [`Profile`](crate::profile::Profile) and selects it. Every patch reads
+//! its addresses from the selected profile, so a single build of the mod supports
+//! every version that has a profile.
+
+use std::{collections::BTreeMap, sync::OnceLock};
+
+use serde::Deserialize;
+
+use crate::{patchy::PatchError, profile::Profile};
+
+/// The code layout of a HighFleet release.
+///
+/// Releases sharing a layout differ only in their addresses, so the same hooks
+/// work for all of them once a profile provides the addresses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum GameVersion {
+    /// Layout of release 1.151.
+    #[serde(rename = "1.151")]
+    V1_151,
+    /// Layout of release 1.163.
+    #[serde(rename = "1.163")]
+    V1_163,
+}
+
+/// Addresses, sizes and struct offsets that differ between game versions.
+///
+/// Sites that only exist in some versions are optional; features relying on them
+/// are skipped when they are absent.
+#[derive(Debug)]
+pub struct Addresses {
+    /// Minimum zoom level variable.
+    pub zoom_min_level: usize,
+    /// Maximum zoom level variable.
+    pub zoom_max_level: usize,
+    /// Current zoom level variable.
+    pub zoom_level: usize,
+    /// Flag set while the battle screen is in arcade mode.
+    pub zoom_is_in_arcade: usize,
+    /// Call of the game's zoom value calculation when the battle screen zooms.
+    pub zoom_calc_zoom_call: usize,
+    /// Instructions that initialise the zoom level limits.
+    pub zoom_set_levels_hook: usize,
+    /// Number of bytes replaced at `zoom_set_levels_hook`.
+    pub zoom_set_levels_size: usize,
+    /// Screen shake multiplier constant.
+    pub shake_multiplier: usize,
+    /// Time-to-live check removed by the unblocked TTL patch.
+    pub ttl_check: usize,
+    /// Hook used to clear the dumpable flag of GUI elements.
+    pub dumpable_hook: usize,
+    /// Offset of the dumpable flag inside the GUI element in RSI.
+    pub dumpable_flag_offset: usize,
+    /// Cash update inside `TransferShopItemToPlayer`.
+    pub sell_hook: usize,
+    /// `DefinePart`.
+    pub parts_define_part_fn: usize,
+    /// Pointer to the part library passed to `DefinePart`.
+    pub parts_all_part_library: usize,
+    /// Pointer to the current city object.
+    pub parts_city: usize,
+    /// Pointer to the fallback category library.
+    pub parts_category_fallback: usize,
+    /// Hook after a `DefinePart` call during shop generation.
+    pub parts_hook: usize,
+    /// The game's plane `std::map`.
+    pub plane_tll: usize,
+    /// The game's loadout `std::map`.
+    pub loadout_tll: usize,
+    /// Field offsets of the game structs read through
+    /// [`structs::game`](crate::structs::game).
+    pub structs: StructOffsets,
+    /// The native gun-blocking sector check, where the game still has one.
+    pub gun_sector_check: Option<usize>,
+    /// `FireGun` sites used to restore gun blocking where the game removed it.
+    pub fire_gun: Option<FireGunSites>,
+    /// Missile-fuze sites patched by the flare crash fix.
+    pub flare_crash: Option<FlareCrashSites>,
+    /// The bytes the game has at each patch site, by symbol name.
+    pub originals: BTreeMap<String, String>,
+    /// Patch sites whose pattern was not found, with the reason.
+    pub unresolved: BTreeMap<String, String>,
+}
+
+impl Addresses {
+    /// The bytes the profile records at the patch site `site`, e.g. `"ttl.check"`.
+    ///
+    /// Sites without recorded bytes are patched without checking what they hold,
+    /// which is logged as a warning. Fails if the pattern of the site was not
+    /// found, so only the features patching it are left out.
+    pub fn original_bytes(&self, site: &str) -> Result<Option<&str>, PatchError> {
+        if let Some(reason) = self.unresolved.get(site) {
+            return Err(PatchError::UnresolvedSite {
+                site: site.to_string(),
+                reason: reason.clone(),
+            });
+        }
+
+        let original = self.originals.get(site).map(String::as_str);
+        if original.is_none() {
+            log::warn!(
+                "The profile does not record the original bytes at `{site}`, patching it unchecked"
+            );
+        }
+        Ok(original)
+    }
+}
+
+/// Field offsets of the game structs, grouped by struct.
+#[derive(Debug)]
+pub struct StructOffsets {
+    /// The player's Profile.
+    pub profile: ProfileOffsets,
+    /// The current city.
+    pub city: CityOffsets,
+    /// Bodies, the parts of a ship.
+    pub body: BodyOffsets,
+    /// Absent where the ShipPart layout has not been mapped.
+    pub ship_part: Option<ShipPartOffsets>,
+}
+
+/// Field offsets of the Profile struct.
+#[derive(Debug)]
+pub struct ProfileOffsets {
+    /// The player's cash, an `i32`.
+    pub cash: usize,
+}
+
+/// Field offsets of the city object.
+#[derive(Debug)]
+pub struct CityOffsets {
+    /// The city type, 1–7.
+    pub city_type: usize,
+    /// Pointer to the category library node.
+    pub category_node: usize,
+}
+
+/// Field offsets of the Body struct.
+#[derive(Debug)]
+pub struct BodyOffsets {
+    /// `m_code`, whose low nibble tells bodies from other objects.
+    pub m_code: usize,
+    /// Node this body is attached to.
+    pub master_node: usize,
+    /// Node linking to the body that owns this one.
+    pub owner_node: usize,
+    /// Rotation of the body in radians.
+    pub angle: usize,
+    /// Index of the body's part, passed to `GetStats`.
+    pub part_index: usize,
+    /// Start of the firing sectors of a gun body, an array of `f32`.
+    pub sectors_begin: usize,
+    /// End of the firing sectors.
+    pub sectors_end: usize,
+}
+
+/// Field offsets of the ShipPart struct.
+#[derive(Debug)]
+pub struct ShipPartOffsets {
+    /// `mdl_sectors_type`, zero for parts without firing sectors.
+    pub sectors_type: usize,
+}
+
+/// Sites inside `FireGun` used to restore the gun-blocking sector check.
+#[derive(Debug)]
+pub struct FireGunSites {
+    /// Charge decrement where the sector check is injected.
+    pub charge_hook: usize,
+    /// Exit taken when the gun is blocked.
+    pub blocked_exit: usize,
+    /// Instruction following the replaced charge decrement.
+    pub resume: usize,
+    /// `GetStats`, which returns the ShipPart of a part index.
+    pub get_stats_fn: usize,
+}
+
+/// Missile-fuze sites, relative to the game module, used by the flare crash fix.
+#[derive(Debug)]
+pub struct FlareCrashSites {
+    /// Instructions replaced by the null check.
+    pub hook_rva: usize,
+    /// Where execution resumes when the linked object exists.
+    pub resume_rva: usize,
+    /// Where execution continues when the linked object is missing.
+    pub null_exit_rva: usize,
+}
+
+static PROFILE: OnceLock<Profile> = OnceLock::new();
+
+/// Selects the profile of the running game.
+///
+/// Returns false if a profile for a different game version was already selected.
+pub fn select(profile: Profile) -> bool {
+    let game_version = profile.game_version.clone();
+    PROFILE.get_or_init(|| profile).game_version == game_version
+}
+
+/// The profile selected by `version()`, if any.
+pub fn selected() -> Option<&'static Profile> {
+    PROFILE.get()
+}
+
+/// The code layout of the selected game version.
+///
+/// # Panics
+/// Panics if no version has been selected yet.
+pub fn game_version() -> GameVersion {
+    profile().layout
+}
+
+/// The address table of the selected game version.
+///
+/// # Panics
+/// Panics if no version has been selected yet.
+pub fn addresses() -> &'static Addresses {
+    &profile().addresses
+}
+
+fn profile() -> &'static Profile {
+    selected().expect("the game version must be selected before patching")
+}
@@ -1,111 +1,149 @@
-use crate::patchy::{Patch, ReturnType};
-
-static mut MIN_ZOOM: u32 = 3;
-static mut MAX_ZOOM: u32 = 3;
-static mut ZOOM_LEVELS: Vec<f32> = Vec::new();
-
-#[cfg(feature = "1_151")]
-static MIN_ZOOM_ADDR: usize = 0x143942538;
-#[cfg(feature = "1_151")]
-static MAX_ZOOM_ADDR: usize = 0x140391160;
-#[cfg(feature = "1_151")]
-static ZOOM_LEVEL_ADDR: usize = 0x14039115c;
-
-#[cfg(any(feature = "1_163", not(any(feature = "1_151", feature = "1_163"))))]
-static MIN_ZOOM_ADDR: usize = 0x143a119a4;
-#[cfg(any(feature = "1_163", not(any(feature = "1_151", feature = "1_163"))))]
-static MAX_ZOOM_ADDR: usize = 0x1403c11d0;
-#[cfg(any(feature = "1_163", not(any(feature = "1_151", feature = "1_163"))))]
-static ZOOM_LEVEL_ADDR: usize = 0x1403c11cc;
-
-pub unsafe fn patch_zoom(min_zoom: u32, max_zoom: u32) {
-    MAX_ZOOM = max_zoom;
-    MIN_ZOOM = min_zoom;
-
-    let address;
-    if cfg!(feature = "1_151") {
-        address = 0x1401adf72;
-    } else if cfg!(feature = "1_163") {
-        address = 0x1402C31C9;
-    } else {
-        // Default to 1.163
-        address = 0x1402C31C9;
+use std::sync::{
+    atomic::{AtomicBool, AtomicU32, Ordering},
+    OnceLock, PoisonError, RwLock,
+};
+
+use crate::{
+    config::Config,
+    feature::Feature,
+    patchy::{Patch, PatchError, ReturnType},
+    version::addresses,
+};
+
+static MIN_ZOOM: AtomicU32 = AtomicU32::new(3);
+static MAX_ZOOM: AtomicU32 = AtomicU32::new(3);
+static ZOOM_LEVELS: RwLock<Vec<f32>> = RwLock::new(Vec::new());
+
+type CalcZoomFn = unsafe extern "C" fn() -> f32;
+
+/// The game's zoom value calculation, used outside of arcade mode.
+static ORIGINAL_CALC_ZOOM: OnceLock<CalcZoomFn> = OnceLock::new();
+
+/// Set once the zoom patches have been prepared.
+static PATCHED: AtomicBool = AtomicBool::new(false);
+
+/// Unlocks zooming in and out in the battle screen.
+pub struct ArcadeZoom;
+
+/// The zoom settings of [`ArcadeZoom`].
+#[derive(PartialEq)]
+pub struct ZoomSettings {
+    min_level: u32,
+    max_level: u32,
+    levels: Vec<f32>,
+}
+
+impl Feature for ArcadeZoom {
+    type Settings = ZoomSettings;
+
+    const NAME: &'static str = "Arcade zoom";
+
+    fn settings(config: &Config) -> Option<ZoomSettings> {
+        config.enable_arcade_zoom.then(|| ZoomSettings {
+            min_level: config.min_zoom_level as u32,
+            max_level: config.max_zoom_level as u32,
+            levels: config.zoom_levels.clone(),
+        })
+    }
+
+    unsafe fn install(settings: ZoomSettings) -> Result<Vec<Patch>, PatchError> {
+        let patches = patch_zoom(settings.min_level, settings.max_level, settings.levels)?;
+        log::info!(
+            "Arcade zoom enabled (min zoom level {}, max zoom level {})",
+            settings.min_level,
+            settings.max_level
+        );
+        Ok(patches)
     }
 
-    // Default to 1.163
-    let mut override_count = 14;
-    if cfg!(feature = "1_151") {
-        override_count = 20;
+    fn reload(settings: ZoomSettings) -> bool {
+        set_zoom_range(settings.min_level, settings.max_level) & set_zoom_levels(settings.levels)
     }
+}
 
-    let p = Patch::patch_call(
-        address,
+/// Patches the zoom level limits and the zoom value of each level.
+unsafe fn patch_zoom(
+    min_zoom: u32,
+    max_zoom: u32,
+    zoom_levels: Vec<f32>,
+) -> Result<Vec<Patch>, PatchError> {
+    set_zoom_range(min_zoom, max_zoom);
+    set_zoom_levels(zoom_levels);
+
+    let addresses = addresses();
+    let set_levels = Patch::patch_call(
+        addresses.zoom_set_levels_hook,
+        addresses.original_bytes("zoom.set_levels_hook")?,
         set_zoom_level as *const (),
-        override_count,
+        addresses.zoom_set_levels_size,
         false,
         ReturnType::None,
-    );
-    std::mem::forget(p);
-}
-
-pub unsafe fn patch_levels(zoom_levels: Vec<f32>) {
-    ZOOM_LEVELS = zoom_levels;
-
-    let address;
-    if cfg!(feature = "1_151") {
-        address = 0x140249371;
-    } else if cfg!(feature = "1_163") {
-        address = 0x14026b03f;
-    } else {
-        // Default to 1.163
-        address = 0x14026b03f;
-    }
-
-    let p = Patch::patch_call(
-        address,
+    )?;
+
+    // Only this call site is hooked; other callers keep the game's zoom value.
+    let calc_zoom_call = addresses.zoom_calc_zoom_call;
+    let expected = addresses.original_bytes("zoom.calc_zoom_call")?;
+    let original = Patch::call_target::<CalcZoomFn>(calc_zoom_call, expected)?;
+    let calc_zoom = Patch::patch_call(
+        calc_zoom_call,
+        expected,
         calc_zoom_value as *const (),
         5,
         false,
         ReturnType::Xmm0,
-    );
-    std::mem::forget(p);
+    )?;
+    // Zoom is only patched once, so the original is never replaced.
+    let _ = ORIGINAL_CALC_ZOOM.set(original);
+
+    PATCHED.store(true, Ordering::Relaxed);
+    Ok(vec![set_levels, calc_zoom])
+}
+
+/// Updates the zoom level limits applied the next time the game sets them.
+///
+/// Returns false if arcade zoom is not patched, so the limits have no effect.
+fn set_zoom_range(min_zoom: u32, max_zoom: u32) -> bool {
+    MIN_ZOOM.store(min_zoom, Ordering::Relaxed);
+    MAX_ZOOM.store(max_zoom, Ordering::Relaxed);
+    PATCHED.load(Ordering::Relaxed)
+}
+
+/// Replaces the zoom value of every zoom level.
+///
+/// Returns false if arcade zoom is not patched, so the levels have no effect.
+fn set_zoom_levels(zoom_levels: Vec<f32>) -> bool {
+    *ZOOM_LEVELS.write().unwrap_or_else(PoisonError::into_inner) = zoom_levels;
+    PATCHED.load(Ordering::Relaxed)
 }
 
 unsafe extern "C" fn set_zoom_level() {
-    let max = MAX_ZOOM;
-    let min = MIN_ZOOM;
+    let max = MAX_ZOOM.load(Ordering::Relaxed);
+    let min = MIN_ZOOM.load(Ordering::Relaxed);
 
-    let max_level = MAX_ZOOM_ADDR as *mut u32;
+    let addresses = addresses();
+    let max_level = addresses.zoom_max_level as *mut u32;
     *max_level = max;
 
-    let min_level = MIN_ZOOM_ADDR as *mut u32;
+    let min_level = addresses.zoom_min_level as *mut u32;
     *min_level = min;
 }
 
-#[cfg(feature = "1_151")]
-static IS_IN_ARCADE: usize = 0x147eed995;
-#[cfg(feature = "1_151")]
-static REAL_CALC_ZOOM: usize = 0x14022da90;
-
-#[cfg(any(feature = "1_163", not(any(feature = "1_151", feature = "1_163"))))]
-static IS_IN_ARCADE: usize = 0x147fc6fb7;
-#[cfg(any(feature = "1_163", not(any(feature = "1_151", feature = "1_163"))))]
-static REAL_CALC_ZOOM: usize = 0x14024f170;
-
-#[allow(static_mut_refs)]
 unsafe extern "C" fn calc_zoom_value() -> f32 {
-    let is_in_arcade = IS_IN_ARCADE as *const bool;
+    let addresses = addresses();
+    let is_in_arcade = addresses.zoom_is_in_arcade as *const bool;
 
     if !*is_in_arcade {
         // Call original function if both booleans are false
-        let func: extern "C" fn() -> f32 = std::mem::transmute(REAL_CALC_ZOOM as *const ());
-        return func();
+        if let Some(original) = ORIGINAL_CALC_ZOOM.get() {
+            return original();
+        }
     }
 
-    let zoom_value = ZOOM_LEVEL_ADDR as *const u32;
+    let zoom_value = addresses.zoom_level as *const u32;
 
-    if *zoom_value > ZOOM_LEVELS.len() as u32 {
-        return 1.0;
-    }
-    ZOOM_LEVELS[*zoom_value as usize]
+    let zoom_levels = ZOOM_LEVELS.read().unwrap_or_else(PoisonError::into_inner);
+    zoom_levels
+        .get(*zoom_value as usize)
+        .copied()
+        .unwrap_or(1.0)
 }